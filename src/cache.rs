@@ -0,0 +1,569 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    AIError, Flow, GenerativeAIInterface, Handler, HandlerError, MutHandler, Prompt, RequestMeta,
+    Role, finish_request, finish_request_mut, unix_now,
+};
+
+/// Wraps any `GenerativeAIInterface` and replays completed responses from a `CacheStore`.
+/// If no store is set, every request is passed through to the inner AI.
+pub struct CachedAI<AI: GenerativeAIInterface> {
+    inner: AI,
+    store: Option<CacheStore>,
+    engine: String,
+    model: String,
+    // if you set this value, cached chunks are replayed with this delay between them.
+    replay_delay: Option<Duration>,
+}
+
+impl<AI: GenerativeAIInterface> CachedAI<AI> {
+    pub fn new(inner: AI, engine: &str) -> Self {
+        Self {
+            inner,
+            store: None,
+            engine: engine.to_string(),
+            model: String::new(),
+            replay_delay: None,
+        }
+    }
+    pub fn store(mut self, store: Option<CacheStore>) -> Self {
+        self.store = store;
+        self
+    }
+    pub fn model(mut self, model: &str) -> Self {
+        self.model = model.to_string();
+        self
+    }
+    pub fn replay_delay(mut self, delay: Option<Duration>) -> Self {
+        self.replay_delay = delay;
+        self
+    }
+    pub fn inner(&self) -> &AI {
+        &self.inner
+    }
+    fn key(&self, prompt: &Prompt) -> CacheKey {
+        CacheKey::new(&self.engine, &self.model, prompt)
+    }
+    async fn replay<H: Handler>(&self, entry: CacheEntry, handler: &H) -> Result<(), AIError> {
+        for chunk in entry.chunks {
+            if let Some(delay) = self.replay_delay {
+                tokio::time::sleep(delay).await;
+            }
+            let flow = handler
+                .handle_flow(chunk.as_str())
+                .await
+                .context("Failed to handle cached response")?;
+            if flow == Flow::Stop {
+                break;
+            }
+        }
+        Ok(())
+    }
+    async fn replay_mut<H: MutHandler>(
+        &self,
+        entry: CacheEntry,
+        handler: &mut H,
+    ) -> Result<(), AIError> {
+        for chunk in entry.chunks {
+            if let Some(delay) = self.replay_delay {
+                tokio::time::sleep(delay).await;
+            }
+            let flow = handler
+                .handle_mut_flow(chunk.as_str())
+                .await
+                .context("Failed to handle cached response")?;
+            if flow == Flow::Stop {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<AI: GenerativeAIInterface> GenerativeAIInterface for CachedAI<AI> {
+    async fn request<H: Handler>(&self, prompt: Prompt, handler: &H) -> Result<(), AIError> {
+        let Some(store) = &self.store else {
+            return self.inner.request(prompt, handler).await;
+        };
+        let key = self.key(&prompt);
+        if let Some(entry) = store.get(&key) {
+            let meta = RequestMeta::new(&self.model, &prompt).cached(true);
+            handler
+                .on_start(&meta)
+                .await
+                .context("Failed to start handler")?;
+            let result = self.replay(entry, handler).await;
+            return finish_request(handler, &meta, result).await;
+        }
+        let recorder = RecordingHandler {
+            inner: handler,
+            chunks: Mutex::new(Vec::new()),
+            stopped: Mutex::new(false),
+        };
+        self.inner.request(prompt, &recorder).await?;
+        // a stopped response is not complete.
+        if recorder.stopped.into_inner().unwrap_or_default() {
+            return Ok(());
+        }
+        let chunks = recorder.chunks.into_inner().unwrap_or_default();
+        if let Err(e) = store.put(&key, chunks) {
+            tracing::warn!("failed to write cache: {:?}", e);
+        }
+        Ok(())
+    }
+    async fn request_mut<H: MutHandler>(
+        &self,
+        prompt: Prompt,
+        handler: &mut H,
+    ) -> Result<(), AIError> {
+        let Some(store) = &self.store else {
+            return self.inner.request_mut(prompt, handler).await;
+        };
+        let key = self.key(&prompt);
+        if let Some(entry) = store.get(&key) {
+            let meta = RequestMeta::new(&self.model, &prompt).cached(true);
+            handler
+                .on_start(&meta)
+                .await
+                .context("Failed to start handler")?;
+            let result = self.replay_mut(entry, handler).await;
+            return finish_request_mut(handler, &meta, result).await;
+        }
+        let mut recorder = RecordingMutHandler {
+            inner: handler,
+            chunks: Vec::new(),
+            stopped: false,
+        };
+        self.inner.request_mut(prompt, &mut recorder).await?;
+        if recorder.stopped {
+            return Ok(());
+        }
+        if let Err(e) = store.put(&key, recorder.chunks) {
+            tracing::warn!("failed to write cache: {:?}", e);
+        }
+        Ok(())
+    }
+}
+
+struct RecordingHandler<'a, H: Handler> {
+    inner: &'a H,
+    chunks: Mutex<Vec<String>>,
+    stopped: Mutex<bool>,
+}
+impl<H: Handler> Handler for RecordingHandler<'_, H> {
+    async fn handle(&self, resp: &str) -> Result<(), HandlerError> {
+        self.handle_flow(resp).await.map(|_| ())
+    }
+    async fn handle_flow(&self, resp: &str) -> Result<Flow, HandlerError> {
+        if !resp.is_empty()
+            && let Ok(mut chunks) = self.chunks.lock()
+        {
+            chunks.push(resp.to_string());
+        }
+        let flow = self.inner.handle_flow(resp).await?;
+        if flow == Flow::Stop
+            && let Ok(mut stopped) = self.stopped.lock()
+        {
+            *stopped = true;
+        }
+        Ok(flow)
+    }
+    async fn on_start(&self, meta: &RequestMeta) -> Result<(), HandlerError> {
+        self.inner.on_start(meta).await
+    }
+    async fn on_finish(&self, meta: &RequestMeta) -> Result<(), HandlerError> {
+        self.inner.on_finish(meta).await
+    }
+    async fn on_error(&self, meta: &RequestMeta, error: &AIError) {
+        self.inner.on_error(meta, error).await
+    }
+}
+
+struct RecordingMutHandler<'a, H: MutHandler> {
+    inner: &'a mut H,
+    chunks: Vec<String>,
+    stopped: bool,
+}
+impl<H: MutHandler> MutHandler for RecordingMutHandler<'_, H> {
+    async fn handle_mut(&mut self, resp: &str) -> Result<(), HandlerError> {
+        self.handle_mut_flow(resp).await.map(|_| ())
+    }
+    async fn handle_mut_flow(&mut self, resp: &str) -> Result<Flow, HandlerError> {
+        if !resp.is_empty() {
+            self.chunks.push(resp.to_string());
+        }
+        let flow = self.inner.handle_mut_flow(resp).await?;
+        self.stopped |= flow == Flow::Stop;
+        Ok(flow)
+    }
+    async fn on_start(&mut self, meta: &RequestMeta) -> Result<(), HandlerError> {
+        self.inner.on_start(meta).await
+    }
+    async fn on_finish(&mut self, meta: &RequestMeta) -> Result<(), HandlerError> {
+        self.inner.on_finish(meta).await
+    }
+    async fn on_error(&mut self, meta: &RequestMeta, error: &AIError) {
+        self.inner.on_error(meta, error).await
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub struct CacheError(anyhow::Error);
+crate::impl_from_error!(CacheError);
+
+/// Everything that can change the answer of a request.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct CacheKey {
+    engine: String,
+    model: String,
+    messages: Vec<(String, String)>,
+}
+impl CacheKey {
+    pub fn new(engine: &str, model: &str, prompt: &Prompt) -> Self {
+        let messages = prompt
+            .clone()
+            .messages()
+            .into_iter()
+            .map(|m| {
+                let role = match m.role {
+                    Role::User => "user",
+                    Role::AI => "ai",
+                    Role::RolePlay => "role_play",
+                };
+                (role.to_string(), m.content)
+            })
+            .collect();
+        Self {
+            engine: engine.to_string(),
+            model: model.to_string(),
+            messages,
+        }
+    }
+    pub fn digest(&self) -> String {
+        // serializing a struct with fixed field order is stable, so the json is used as the content.
+        let content = serde_json::to_string(self).unwrap_or_default();
+        Sha256::digest(content.as_bytes())
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct CacheEntry {
+    pub key: CacheKey,
+    // unix time in seconds
+    pub created_at: u64,
+    pub chunks: Vec<String>,
+}
+
+/// A content-addressed directory of responses.
+/// Each entry is stored in `<dir>/<first 2 chars of digest>/<digest>.json`.
+pub struct CacheStore {
+    dir: PathBuf,
+    ttl: Option<Duration>,
+}
+
+impl CacheStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            ttl: None,
+        }
+    }
+    /// `$CAI_CACHE_DIR`, `$XDG_CACHE_HOME/cai/responses` or `$HOME/.cache/cai/responses`.
+    pub fn default_dir() -> PathBuf {
+        if let Ok(dir) = std::env::var("CAI_CACHE_DIR") {
+            return PathBuf::from(dir);
+        }
+        let base = std::env::var("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| {
+                PathBuf::from(std::env::var("HOME").unwrap_or_else(|_| ".".to_string()))
+                    .join(".cache")
+            });
+        base.join("cai").join("responses")
+    }
+    pub fn ttl(mut self, ttl: Option<Duration>) -> Self {
+        self.ttl = ttl;
+        self
+    }
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+    pub fn get(&self, key: &CacheKey) -> Option<CacheEntry> {
+        let path = self.path(key);
+        let content = std::fs::read_to_string(&path).ok()?;
+        let entry = serde_json::from_str::<CacheEntry>(&content).ok()?;
+        // a digest collision is unlikely, but never replay an answer to another prompt.
+        if &entry.key != key {
+            return None;
+        }
+        if self.is_expired(&entry) {
+            let _ = std::fs::remove_file(&path);
+            return None;
+        }
+        Some(entry)
+    }
+    pub fn put(&self, key: &CacheKey, chunks: Vec<String>) -> Result<(), CacheError> {
+        let path = self.path(key);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let entry = CacheEntry {
+            key: key.clone(),
+            created_at: unix_now(),
+            chunks,
+        };
+        let content = serde_json::to_string(&entry).context("Failed to serialize cache entry")?;
+        std::fs::write(&path, content)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+    /// Removes all entries and returns how many were removed.
+    pub fn clear(&self) -> Result<usize, CacheError> {
+        let count = self.entry_paths()?.len();
+        if self.dir.exists() {
+            std::fs::remove_dir_all(&self.dir)
+                .with_context(|| format!("Failed to remove {}", self.dir.display()))?;
+        }
+        Ok(count)
+    }
+    pub fn stats(&self) -> Result<CacheStats, CacheError> {
+        let mut stats = CacheStats::default();
+        for path in self.entry_paths()? {
+            let metadata =
+                std::fs::metadata(&path).with_context(|| format!("Failed to read {:?}", path))?;
+            stats.entries += 1;
+            stats.bytes += metadata.len();
+            let expired = std::fs::read_to_string(&path)
+                .ok()
+                .and_then(|content| serde_json::from_str::<CacheEntry>(&content).ok())
+                .is_none_or(|entry| self.is_expired(&entry));
+            if expired {
+                stats.expired += 1;
+            }
+        }
+        Ok(stats)
+    }
+    fn path(&self, key: &CacheKey) -> PathBuf {
+        let digest = key.digest();
+        self.dir.join(&digest[..2]).join(format!("{}.json", digest))
+    }
+    fn is_expired(&self, entry: &CacheEntry) -> bool {
+        self.ttl
+            .is_some_and(|ttl| unix_now().saturating_sub(entry.created_at) > ttl.as_secs())
+    }
+    fn entry_paths(&self) -> Result<Vec<PathBuf>, CacheError> {
+        if !self.dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut paths = vec![];
+        for shard in std::fs::read_dir(&self.dir)
+            .with_context(|| format!("Failed to read {}", self.dir.display()))?
+        {
+            let shard = shard.context("Failed to read cache directory")?.path();
+            if !shard.is_dir() {
+                continue;
+            }
+            for entry in std::fs::read_dir(&shard)
+                .with_context(|| format!("Failed to read {}", shard.display()))?
+            {
+                let path = entry.context("Failed to read cache directory")?.path();
+                if path.extension().is_some_and(|ext| ext == "json") {
+                    paths.push(path);
+                }
+            }
+        }
+        Ok(paths)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub bytes: u64,
+    pub expired: usize,
+}
+impl std::fmt::Display for CacheStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "entries: {}\nsize: {} bytes\nexpired: {}",
+            self.entries, self.bytes, self.expired
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::{clients::mocks::MockHandler, handlers::recorder::Recorder};
+
+    use super::*;
+
+    struct FakeAI {
+        calls: AtomicUsize,
+    }
+    impl GenerativeAIInterface for FakeAI {
+        async fn request<H: Handler>(&self, _: Prompt, handler: &H) -> Result<(), AIError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            handler.handle("hello, ").await.context("handle")?;
+            Ok(handler.handle("world").await.context("handle")?)
+        }
+        async fn request_mut<H: MutHandler>(
+            &self,
+            _: Prompt,
+            handler: &mut H,
+        ) -> Result<(), AIError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if handler.handle_mut_flow("hello, ").await.context("handle")? == Flow::Stop {
+                return Ok(());
+            }
+            Ok(handler.handle_mut("world").await.context("handle")?)
+        }
+    }
+    #[derive(Default)]
+    struct Hooks {
+        stop: bool,
+        chunks: Vec<String>,
+        events: Vec<String>,
+    }
+    impl MutHandler for Hooks {
+        async fn handle_mut(&mut self, resp: &str) -> Result<(), HandlerError> {
+            self.chunks.push(resp.to_string());
+            Ok(())
+        }
+        async fn handle_mut_flow(&mut self, resp: &str) -> Result<Flow, HandlerError> {
+            self.handle_mut(resp).await?;
+            Ok(if self.stop {
+                Flow::Stop
+            } else {
+                Flow::Continue
+            })
+        }
+        async fn on_start(&mut self, meta: &RequestMeta) -> Result<(), HandlerError> {
+            self.events
+                .push(format!("start {} cached={}", meta.model, meta.cached));
+            Ok(())
+        }
+        async fn on_finish(&mut self, _: &RequestMeta) -> Result<(), HandlerError> {
+            self.events.push("finish".to_string());
+            Ok(())
+        }
+    }
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cai-cache-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[tokio::test]
+    async fn cached_response_is_replayed_without_calling_ai() {
+        let dir = temp_dir("replay");
+        let sut = CachedAI::new(
+            FakeAI {
+                calls: AtomicUsize::new(0),
+            },
+            "fake",
+        )
+        .store(Some(CacheStore::new(&dir)));
+
+        let mut first = Recorder::new();
+        sut.request_mut(Prompt::ask("hi"), &mut first)
+            .await
+            .unwrap();
+        let mut second = MockHandler::new();
+        sut.request_mut(Prompt::ask("hi"), &mut second)
+            .await
+            .unwrap();
+        let mut other = Recorder::new();
+        sut.request_mut(Prompt::ask("bye"), &mut other)
+            .await
+            .unwrap();
+
+        assert_eq!(first.message(), "hello, world");
+        assert_eq!(second.received, "hello, world");
+        assert_eq!(sut.inner().calls.load(Ordering::SeqCst), 2);
+        assert_eq!(CacheStore::new(&dir).stats().unwrap().entries, 2);
+        assert_eq!(CacheStore::new(&dir).clear().unwrap(), 2);
+        assert!(!dir.exists());
+    }
+    #[tokio::test]
+    async fn stopped_response_is_not_cached_and_replay_calls_hooks() {
+        let dir = temp_dir("stop");
+        let sut = CachedAI::new(
+            FakeAI {
+                calls: AtomicUsize::new(0),
+            },
+            "fake",
+        )
+        .model("fake-1")
+        .store(Some(CacheStore::new(&dir)));
+
+        let mut stopped = Hooks {
+            stop: true,
+            ..Default::default()
+        };
+        sut.request_mut(Prompt::ask("hi"), &mut stopped)
+            .await
+            .unwrap();
+        assert_eq!(stopped.chunks, vec!["hello, "]);
+        assert_eq!(CacheStore::new(&dir).stats().unwrap().entries, 0);
+
+        sut.request_mut(Prompt::ask("hi"), &mut Recorder::new())
+            .await
+            .unwrap();
+        let mut replayed = Hooks::default();
+        sut.request_mut(Prompt::ask("hi"), &mut replayed)
+            .await
+            .unwrap();
+
+        assert_eq!(replayed.chunks, vec!["hello, ", "world"]);
+        assert_eq!(replayed.events, vec!["start fake-1 cached=true", "finish"]);
+        assert_eq!(sut.inner().calls.load(Ordering::SeqCst), 2);
+        CacheStore::new(&dir).clear().unwrap();
+    }
+    #[test]
+    fn expired_entry_is_not_returned() {
+        let dir = temp_dir("ttl");
+        let key = CacheKey::new("fake", "model", &Prompt::ask("hi"));
+        let store = CacheStore::new(&dir);
+        store.put(&key, vec!["hello".to_string()]).unwrap();
+
+        assert!(store.get(&key).is_some());
+        let store = store.ttl(Some(Duration::from_secs(0)));
+        let path = store.path(&key);
+        let mut entry = store.get(&key).unwrap();
+        entry.created_at -= 10;
+        std::fs::write(&path, serde_json::to_string(&entry).unwrap()).unwrap();
+
+        assert_eq!(store.stats().unwrap().expired, 1);
+        assert!(store.get(&key).is_none());
+        assert!(!path.exists());
+        store.clear().unwrap();
+    }
+    #[test]
+    fn key_digest_depends_on_every_component() {
+        let prompt = Prompt::ask("hi");
+        let base = CacheKey::new("gpt4", "gpt-4", &prompt).digest();
+
+        assert_eq!(base, CacheKey::new("gpt4", "gpt-4", &prompt).digest());
+        assert_ne!(base, CacheKey::new("gpt4-o", "gpt-4", &prompt).digest());
+        assert_ne!(base, CacheKey::new("gpt4", "gpt-4o", &prompt).digest());
+        assert_ne!(
+            base,
+            CacheKey::new("gpt4", "gpt-4", &Prompt::ask("ho")).digest()
+        );
+    }
+}
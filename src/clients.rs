@@ -5,7 +5,9 @@ pub mod openai;
 
 #[cfg(test)]
 pub mod mocks {
-    use crate::{Handler, HandlerError, MutHandler};
+    use anyhow::Context;
+
+    use crate::{AIError, GenerativeAIInterface, Handler, HandlerError, MutHandler, Prompt};
 
     #[derive(Debug, Clone, PartialEq, Eq)]
     pub struct MockHandler {
@@ -20,6 +22,11 @@ pub mod mocks {
             }
         }
     }
+    impl Default for MockHandler {
+        fn default() -> Self {
+            Self::new()
+        }
+    }
     impl Handler for MockHandler {
         async fn handle(&self, _: &str) -> Result<(), HandlerError> {
             Ok(())
@@ -32,4 +39,28 @@ pub mod mocks {
             Ok(())
         }
     }
+
+    /// A fake AI which answers every request with the closure, or fails with its error.
+    pub struct FakeAI<F: Fn(Prompt) -> anyhow::Result<String>> {
+        answer: F,
+    }
+    impl<F: Fn(Prompt) -> anyhow::Result<String>> FakeAI<F> {
+        pub fn new(answer: F) -> Self {
+            Self { answer }
+        }
+    }
+    impl<F: Fn(Prompt) -> anyhow::Result<String>> GenerativeAIInterface for FakeAI<F> {
+        async fn request<H: Handler>(&self, prompt: Prompt, handler: &H) -> Result<(), AIError> {
+            let answer = (self.answer)(prompt)?;
+            Ok(handler.handle(&answer).await.context("handle")?)
+        }
+        async fn request_mut<H: MutHandler>(
+            &self,
+            prompt: Prompt,
+            handler: &mut H,
+        ) -> Result<(), AIError> {
+            let answer = (self.answer)(prompt)?;
+            Ok(handler.handle_mut(&answer).await.context("handle")?)
+        }
+    }
 }
@@ -38,6 +38,9 @@ impl ClaudeMessageClient {
             model: ClaudeModel::Claude3Haiku,
         }
     }
+    pub fn model(&self) -> &'static str {
+        self.model.to_str()
+    }
 }
 impl GenerativeAIInterface for ClaudeMessageClient {
     async fn request<H: crate::Handler>(
@@ -48,16 +51,16 @@ impl GenerativeAIInterface for ClaudeMessageClient {
         let f = |stream: crate::sse::SseResponse| async {
             let data = match stream {
                 crate::sse::SseResponse::Data(data) => data,
-                _ => return Ok(()),
+                _ => return Ok(crate::Flow::Continue),
             };
 
             let Ok(resp) = serde_json::from_str::<ClaudeMessageStreamResponse>(data.as_str())
             else {
-                return Ok(());
+                return Ok(crate::Flow::Continue);
             };
 
             Ok(handler
-                .handle(resp.into_string().as_str())
+                .handle_flow(resp.into_string().as_str())
                 .await
                 .context("Failed to handle response")?)
         };
@@ -67,7 +70,7 @@ impl GenerativeAIInterface for ClaudeMessageClient {
             .post()
             .header("anthropic-version", "2023-06-01")
             .header("x-api-key", self.api_key.as_str())
-            .json(&ClaudeMessageRequest::new(self.model, prompt))
+            .json(ClaudeMessageRequest::new(self.model, prompt))
             .request()
             .await
             .context("Failed to request")?
@@ -98,7 +101,7 @@ impl GenerativeAIInterface for ClaudeMessageClient {
             .post()
             .header("anthropic-version", "2023-06-01")
             .header("x-api-key", self.api_key.as_str())
-            .json(&ClaudeMessageRequest::new(self.model, prompt))
+            .json(ClaudeMessageRequest::new(self.model, prompt))
             .request()
             .await
             .context("Failed to request")?
@@ -181,7 +184,7 @@ enum ClaudeModel {
     Claude3Haiku,
 }
 impl ClaudeModel {
-    fn to_str(&self) -> &'static str {
+    fn to_str(self) -> &'static str {
         match self {
             ClaudeModel::Claude35Sonnet => "claude-3-5-sonnet-20240620",
             ClaudeModel::Claude3Ops => "claude-3-opus-20240229",
@@ -1,7 +1,12 @@
 use super::{
     claude::ClaudeMessageClient, gemini::GeminiGenerateContent, openai::ChatCompletionsClient,
 };
-use crate::{AIError, GenerativeAIInterface, Handler, MutHandler, Prompt};
+use anyhow::Context;
+
+use crate::{
+    AIError, GenerativeAIInterface, Handler, MutHandler, Prompt, RequestMeta, finish_request,
+    finish_request_mut,
+};
 
 macro_rules! gai_engine {
     ($($name:ident:$t:ty),*) => {
@@ -11,29 +16,37 @@ macro_rules! gai_engine {
             )*
         }
         impl GAIEngines {
-            pub async fn run_mut<H:MutHandler>(&self,handler:&mut H,prompt:Prompt)->Result<(),AIError> {
+            pub fn model(&self) -> &'static str {
                 match &self {
                     $(
-                        &GAIEngines::$name(t) => t.request_mut(prompt,handler).await,
+                        &GAIEngines::$name(t) => t.model(),
                     )*
                 }
-
+            }
+            pub async fn run_mut<H:MutHandler>(&self,handler:&mut H,prompt:Prompt)->Result<(),AIError> {
+                self.request_mut(prompt,handler).await
             }
         }
         impl GenerativeAIInterface for GAIEngines {
             async fn request<H:Handler>(&self,prompt:Prompt,handler:&H)->Result<(),AIError> {
-                match &self {
+                let meta = RequestMeta::new(self.model(), &prompt);
+                handler.on_start(&meta).await.context("Failed to start handler")?;
+                let result = match &self {
                     $(
                         &GAIEngines::$name(t) => t.request(prompt,handler).await,
                     )*
-                }
+                };
+                finish_request(handler, &meta, result).await
             }
             async fn request_mut<H:MutHandler>(&self,prompt:Prompt,handler:&mut H)->Result<(),AIError> {
-                match &self {
+                let meta = RequestMeta::new(self.model(), &prompt);
+                handler.on_start(&meta).await.context("Failed to start handler")?;
+                let result = match &self {
                     $(
                         &GAIEngines::$name(t) => t.request_mut(prompt,handler).await,
                     )*
-                }
+                };
+                finish_request_mut(handler, &meta, result).await
             }
         }
     }
@@ -61,6 +74,20 @@ impl GAIEngines {
     }
 }
 
+/// Engine names accepted by `GAIEngines::from_str`.
+pub const ENGINES: [&str; 10] = [
+    "gpt4",
+    "gpt4-o",
+    "gpt4-o-mini",
+    "gpt3-5-turbo",
+    "gemini15flash",
+    "gemini2flashexp",
+    "claude3-haiku",
+    "claude3-ops",
+    "claude35-sonnet",
+    "claude3-sonnet",
+];
+
 pub fn engine_to_default_key_from_env(engine: &str) -> String {
     if engine.contains("gpt") {
         return std::env::var("OPENAI_API_KEY").unwrap_or_else(|_| "".to_string());
@@ -74,6 +101,17 @@ pub fn engine_to_default_key_from_env(engine: &str) -> String {
     panic!("Unknown engine: {}", engine);
 }
 
+/// Name of the provider that serves the engine. Unknown engines fall back to openai like `GAIEngines::from_str`.
+pub fn engine_to_provider(engine: &str) -> &'static str {
+    if engine.contains("claude") {
+        return "claude";
+    }
+    if engine.contains("gemini") {
+        return "gemini";
+    }
+    "openai"
+}
+
 gai_engine!(
     Gpt4:ChatCompletionsClient,
     Gpt4o:ChatCompletionsClient,
@@ -57,6 +57,7 @@ impl GeminiAPIClient {
 pub struct GeminiGenerateContent {
     inner: SseClient,
     api_key: String,
+    model: GeminiModel,
 }
 impl GeminiGenerateContent {
     fn new(api_key: String, model: GeminiModel) -> Self {
@@ -64,8 +65,12 @@ impl GeminiGenerateContent {
         GeminiGenerateContent {
             inner: SseClient::new(url.as_str()),
             api_key,
+            model,
         }
     }
+    pub fn model(&self) -> &'static str {
+        self.model.to_str()
+    }
     pub fn gemini_15_flash(api_key: String) -> Self {
         Self::new(api_key, GeminiModel::Gemini15Flash)
     }
@@ -79,7 +84,7 @@ impl GenerativeAIInterface for GeminiGenerateContent {
         let f = |stream: crate::sse::SseResponse| async {
             let data = match stream {
                 crate::sse::SseResponse::Data(data) => data,
-                _ => return Ok(()),
+                _ => return Ok(crate::Flow::Continue),
             };
 
             let resp = serde_json::from_str::<GeminiResponse>(data.as_str())
@@ -88,7 +93,7 @@ impl GenerativeAIInterface for GeminiGenerateContent {
             let content: String = resp.into();
 
             Ok(handler
-                .handle(content.as_str())
+                .handle_flow(content.as_str())
                 .await
                 .context("Failed to handle response")?)
         };
@@ -97,7 +102,7 @@ impl GenerativeAIInterface for GeminiGenerateContent {
             .inner
             .post()
             .query(&[("key", self.api_key.as_str()), ("alt", "sse")])
-            .json(&GeminiRequest::from(prompt))
+            .json(GeminiRequest::from(prompt))
             .request()
             .await
             .context("Failed to request")?
@@ -128,7 +133,7 @@ impl GenerativeAIInterface for GeminiGenerateContent {
             .inner
             .post()
             .query(&[("key", self.api_key.as_str()), ("alt", "sse")])
-            .json(&GeminiRequest::from(prompt))
+            .json(GeminiRequest::from(prompt))
             .request()
             .await
             .context("Failed to request")?
@@ -47,7 +47,7 @@ pub struct ChatCompletionsClient {
     model: ChatCompletionsModel,
 }
 
-const URL: &'static str = "https://api.openai.com/v1/chat/completions";
+const URL: &str = "https://api.openai.com/v1/chat/completions";
 impl ChatCompletionsClient {
     pub fn gpt4(api_key: String) -> Self {
         ChatCompletionsClient {
@@ -80,6 +80,9 @@ impl ChatCompletionsClient {
     pub fn change_model(&mut self, model: ChatCompletionsModel) {
         self.model = model;
     }
+    pub fn model(&self) -> &'static str {
+        self.model.to_str()
+    }
 }
 
 impl GenerativeAIInterface for ChatCompletionsClient {
@@ -97,19 +100,19 @@ impl GenerativeAIInterface for ChatCompletionsClient {
         let f = |stream: SseResponse| async {
             let data = match stream {
                 SseResponse::Data(data) => data,
-                _ => return Ok(()),
+                _ => return Ok(crate::Flow::Continue),
             };
 
             let resp = ChatResponse::try_from(data.as_str())
                 .with_context(|| format!("Failed to parse response: {}", data.as_str()))?;
 
             let resp = match resp {
-                ChatResponse::Done => return Ok(()),
+                ChatResponse::Done => return Ok(crate::Flow::Continue),
                 ChatResponse::DeltaContent(content) => content,
             };
 
             Ok(handler
-                .handle(resp.as_str())
+                .handle_flow(resp.as_str())
                 .await
                 .with_context(|| format!("Failed to handle response: {}", resp.as_str()))?)
         };
@@ -183,6 +186,16 @@ pub enum ChatCompletionsModel {
     #[serde(rename = "gpt-4o")]
     Gpt4o,
 }
+impl ChatCompletionsModel {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            ChatCompletionsModel::Gpt3Dot5Turbo => "gpt-3.5-turbo",
+            ChatCompletionsModel::Gpt4 => "gpt-4",
+            ChatCompletionsModel::Gpt4oMini => "gpt-4o-mini",
+            ChatCompletionsModel::Gpt4o => "gpt-4o",
+        }
+    }
+}
 
 #[derive(Debug, Clone, serde::Serialize, PartialEq, Eq)]
 struct Message {
@@ -217,8 +230,8 @@ impl From<crate::Role> for Role {
     fn from(value: crate::Role) -> Self {
         match value {
             crate::Role::User => Self::User,
-            crate::Role::AI => Self::System,
-            crate::Role::RolePlay => Self::Assistant,
+            crate::Role::AI => Self::Assistant,
+            crate::Role::RolePlay => Self::System,
         }
     }
 }
@@ -240,7 +253,7 @@ impl GPTResponse {
         self.choices
             .pop()
             .map(|c| c.message.content)
-            .unwrap_or_else(|| "".to_string())
+            .unwrap_or_default()
     }
 }
 impl TryFrom<&str> for GPTResponse {
@@ -314,14 +327,11 @@ impl TryFrom<&str> for StreamChat {
 impl From<StreamChat> for ChatResponse {
     fn from(s: StreamChat) -> Self {
         let mut s = s;
-        s.choices.pop().map_or_else(
-            || Self::default(),
-            |c| {
-                c.delta
-                    .content
-                    .map_or_else(|| Self::default(), Self::DeltaContent)
-            },
-        )
+        s.choices.pop().map_or_else(Self::default, |c| {
+            c.delta
+                .content
+                .map_or_else(Self::default, Self::DeltaContent)
+        })
     }
 }
 
@@ -369,4 +379,18 @@ mod tests {
             assert!(!received.is_empty());
         }
     }
+    #[test]
+    fn conversation_roles_are_mapped_to_chat_roles() {
+        let mut conversation = Conversation::new();
+        conversation.add_role_play_message("You are tom");
+        conversation.add_user_message("What your name?");
+        conversation.add_ai_message("I am tom.");
+
+        let messages = Vec::<Message>::from(Prompt::with_conversation(conversation));
+
+        assert_eq!(
+            messages.iter().map(|m| m.role).collect::<Vec<_>>(),
+            [Role::System, Role::User, Role::Assistant]
+        );
+    }
 }
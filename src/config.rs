@@ -0,0 +1,112 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::Context;
+
+use crate::limiter::{Limits, RateLimiters};
+
+/// Settings read from `config.toml`.
+///
+/// ```toml
+/// [limits.default]
+/// max_concurrency = 4
+///
+/// [limits.openai]
+/// requests_per_minute = 500
+/// tokens_per_minute = 200000
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, serde::Deserialize)]
+#[serde(default)]
+pub struct Config {
+    // keyed by provider name ("openai", "claude", "gemini") or "default".
+    pub limits: HashMap<String, Limits>,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub struct ConfigError(anyhow::Error);
+crate::impl_from_error!(ConfigError);
+
+impl Config {
+    /// Loads the config from `default_path`. A missing file is not an error.
+    pub fn load() -> Result<Self, ConfigError> {
+        let path = Self::default_path();
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::from_toml(&content)
+    }
+    pub fn from_toml(content: &str) -> Result<Self, ConfigError> {
+        Ok(toml::from_str(content).context("Failed to parse config")?)
+    }
+    /// `$CAI_CONFIG`, `$XDG_CONFIG_HOME/cai/config.toml` or `$HOME/.config/cai/config.toml`.
+    pub fn default_path() -> PathBuf {
+        if let Ok(path) = std::env::var("CAI_CONFIG") {
+            return PathBuf::from(path);
+        }
+        let base = std::env::var("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| {
+                PathBuf::from(std::env::var("HOME").unwrap_or_else(|_| ".".to_string()))
+                    .join(".config")
+            });
+        base.join("cai").join("config.toml")
+    }
+    /// `$CAI_DATA_DIR`, `$XDG_DATA_HOME/cai` or `$HOME/.local/share/cai`.
+    pub fn data_dir() -> PathBuf {
+        if let Ok(dir) = std::env::var("CAI_DATA_DIR") {
+            return PathBuf::from(dir);
+        }
+        std::env::var("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| {
+                PathBuf::from(std::env::var("HOME").unwrap_or_else(|_| ".".to_string()))
+                    .join(".local")
+                    .join("share")
+            })
+            .join("cai")
+    }
+    /// Limits for the provider, falling back to `[limits.default]` for unset values.
+    pub fn limits(&self, provider: &str) -> Limits {
+        let default = self.limits.get("default").copied().unwrap_or_default();
+        match self.limits.get(provider) {
+            Some(limits) => limits.or(default),
+            None => default,
+        }
+    }
+    /// Builds the limiters for every provider. Values set in `overrides` win over the config.
+    pub fn rate_limiters(&self, overrides: Limits) -> RateLimiters {
+        ["openai", "claude", "gemini"].into_iter().fold(
+            RateLimiters::new(overrides.or(self.limits("default"))),
+            |limiters, provider| {
+                limiters.provider_limits(provider, overrides.or(self.limits(provider)))
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn provider_limits_fall_back_to_default() {
+        let config = Config::from_toml(
+            r#"
+            [limits.default]
+            max_concurrency = 4
+            requests_per_minute = 60
+
+            [limits.openai]
+            requests_per_minute = 500
+            "#,
+        )
+        .unwrap();
+
+        let openai = config.limits("openai");
+        assert_eq!(openai.max_concurrency, Some(4));
+        assert_eq!(openai.requests_per_minute, Some(500));
+        assert_eq!(openai.tokens_per_minute, None);
+        assert_eq!(config.limits("gemini").requests_per_minute, Some(60));
+    }
+}
@@ -1,3 +1,6 @@
-pub mod container;
+pub mod combinators;
+pub mod extractor;
+pub mod json;
+pub mod markdown;
 pub mod printer;
 pub mod recorder;
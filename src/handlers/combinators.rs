@@ -0,0 +1,565 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    time::{Duration, Instant},
+};
+
+use crate::{AIError, Flow, Handler, HandlerError, MutHandler, RequestMeta};
+
+type HandleFuture<'a, T = ()> = Pin<Box<dyn Future<Output = Result<T, HandlerError>> + 'a>>;
+
+/// Object safe version of `Handler`, so handlers can be chosen at runtime as `Box<dyn DynHandler>`.
+/// Every `Handler` implements this.
+pub trait DynHandler {
+    fn handle_flow_dyn<'a>(&'a self, resp: &'a str) -> HandleFuture<'a, Flow>;
+    fn on_start_dyn<'a>(&'a self, meta: &'a RequestMeta) -> HandleFuture<'a>;
+    fn on_finish_dyn<'a>(&'a self, meta: &'a RequestMeta) -> HandleFuture<'a>;
+    fn on_error_dyn<'a>(
+        &'a self,
+        meta: &'a RequestMeta,
+        error: &'a AIError,
+    ) -> Pin<Box<dyn Future<Output = ()> + 'a>>;
+}
+impl<H: Handler> DynHandler for H {
+    fn handle_flow_dyn<'a>(&'a self, resp: &'a str) -> HandleFuture<'a, Flow> {
+        Box::pin(self.handle_flow(resp))
+    }
+    fn on_start_dyn<'a>(&'a self, meta: &'a RequestMeta) -> HandleFuture<'a> {
+        Box::pin(self.on_start(meta))
+    }
+    fn on_finish_dyn<'a>(&'a self, meta: &'a RequestMeta) -> HandleFuture<'a> {
+        Box::pin(self.on_finish(meta))
+    }
+    fn on_error_dyn<'a>(
+        &'a self,
+        meta: &'a RequestMeta,
+        error: &'a AIError,
+    ) -> Pin<Box<dyn Future<Output = ()> + 'a>> {
+        Box::pin(self.on_error(meta, error))
+    }
+}
+
+/// Object safe version of `MutHandler`. Every `MutHandler` implements this.
+pub trait DynMutHandler {
+    fn handle_mut_flow_dyn<'a>(&'a mut self, resp: &'a str) -> HandleFuture<'a, Flow>;
+    fn on_start_dyn<'a>(&'a mut self, meta: &'a RequestMeta) -> HandleFuture<'a>;
+    fn on_finish_dyn<'a>(&'a mut self, meta: &'a RequestMeta) -> HandleFuture<'a>;
+    fn on_error_dyn<'a>(
+        &'a mut self,
+        meta: &'a RequestMeta,
+        error: &'a AIError,
+    ) -> Pin<Box<dyn Future<Output = ()> + 'a>>;
+}
+impl<H: MutHandler> DynMutHandler for H {
+    fn handle_mut_flow_dyn<'a>(&'a mut self, resp: &'a str) -> HandleFuture<'a, Flow> {
+        Box::pin(self.handle_mut_flow(resp))
+    }
+    fn on_start_dyn<'a>(&'a mut self, meta: &'a RequestMeta) -> HandleFuture<'a> {
+        Box::pin(self.on_start(meta))
+    }
+    fn on_finish_dyn<'a>(&'a mut self, meta: &'a RequestMeta) -> HandleFuture<'a> {
+        Box::pin(self.on_finish(meta))
+    }
+    fn on_error_dyn<'a>(
+        &'a mut self,
+        meta: &'a RequestMeta,
+        error: &'a AIError,
+    ) -> Pin<Box<dyn Future<Output = ()> + 'a>> {
+        Box::pin(self.on_error(meta, error))
+    }
+}
+
+impl Handler for Box<dyn DynHandler + '_> {
+    async fn handle(&self, resp: &str) -> Result<(), HandlerError> {
+        self.handle_flow(resp).await.map(|_| ())
+    }
+    async fn handle_flow(&self, resp: &str) -> Result<Flow, HandlerError> {
+        self.as_ref().handle_flow_dyn(resp).await
+    }
+    async fn on_start(&self, meta: &RequestMeta) -> Result<(), HandlerError> {
+        self.as_ref().on_start_dyn(meta).await
+    }
+    async fn on_finish(&self, meta: &RequestMeta) -> Result<(), HandlerError> {
+        self.as_ref().on_finish_dyn(meta).await
+    }
+    async fn on_error(&self, meta: &RequestMeta, error: &AIError) {
+        self.as_ref().on_error_dyn(meta, error).await
+    }
+}
+impl MutHandler for Box<dyn DynMutHandler + '_> {
+    async fn handle_mut(&mut self, resp: &str) -> Result<(), HandlerError> {
+        self.handle_mut_flow(resp).await.map(|_| ())
+    }
+    async fn handle_mut_flow(&mut self, resp: &str) -> Result<Flow, HandlerError> {
+        self.as_mut().handle_mut_flow_dyn(resp).await
+    }
+    async fn on_start(&mut self, meta: &RequestMeta) -> Result<(), HandlerError> {
+        self.as_mut().on_start_dyn(meta).await
+    }
+    async fn on_finish(&mut self, meta: &RequestMeta) -> Result<(), HandlerError> {
+        self.as_mut().on_finish_dyn(meta).await
+    }
+    async fn on_error(&mut self, meta: &RequestMeta, error: &AIError) {
+        self.as_mut().on_error_dyn(meta, error).await
+    }
+}
+
+// every handler receives the chunk, and the stream stops if any of them returns `Flow::Stop`.
+impl<H: Handler> Handler for Vec<H> {
+    async fn handle(&self, resp: &str) -> Result<(), HandlerError> {
+        self.handle_flow(resp).await.map(|_| ())
+    }
+    async fn handle_flow(&self, resp: &str) -> Result<Flow, HandlerError> {
+        let mut flow = Flow::Continue;
+        for handler in self {
+            flow = flow.and(handler.handle_flow(resp).await?);
+        }
+        Ok(flow)
+    }
+    async fn on_start(&self, meta: &RequestMeta) -> Result<(), HandlerError> {
+        for handler in self {
+            handler.on_start(meta).await?;
+        }
+        Ok(())
+    }
+    async fn on_finish(&self, meta: &RequestMeta) -> Result<(), HandlerError> {
+        for handler in self {
+            handler.on_finish(meta).await?;
+        }
+        Ok(())
+    }
+    async fn on_error(&self, meta: &RequestMeta, error: &AIError) {
+        for handler in self {
+            handler.on_error(meta, error).await;
+        }
+    }
+}
+impl<H: MutHandler> MutHandler for Vec<H> {
+    async fn handle_mut(&mut self, resp: &str) -> Result<(), HandlerError> {
+        self.handle_mut_flow(resp).await.map(|_| ())
+    }
+    async fn handle_mut_flow(&mut self, resp: &str) -> Result<Flow, HandlerError> {
+        let mut flow = Flow::Continue;
+        for handler in self {
+            flow = flow.and(handler.handle_mut_flow(resp).await?);
+        }
+        Ok(flow)
+    }
+    async fn on_start(&mut self, meta: &RequestMeta) -> Result<(), HandlerError> {
+        for handler in self {
+            handler.on_start(meta).await?;
+        }
+        Ok(())
+    }
+    async fn on_finish(&mut self, meta: &RequestMeta) -> Result<(), HandlerError> {
+        for handler in self {
+            handler.on_finish(meta).await?;
+        }
+        Ok(())
+    }
+    async fn on_error(&mut self, meta: &RequestMeta, error: &AIError) {
+        for handler in self {
+            handler.on_error(meta, error).await;
+        }
+    }
+}
+
+// handlers can be borrowed into a pipeline and used again after the response.
+impl<H: Handler> Handler for &H {
+    async fn handle(&self, resp: &str) -> Result<(), HandlerError> {
+        (**self).handle(resp).await
+    }
+    async fn handle_flow(&self, resp: &str) -> Result<Flow, HandlerError> {
+        (**self).handle_flow(resp).await
+    }
+    async fn on_start(&self, meta: &RequestMeta) -> Result<(), HandlerError> {
+        (**self).on_start(meta).await
+    }
+    async fn on_finish(&self, meta: &RequestMeta) -> Result<(), HandlerError> {
+        (**self).on_finish(meta).await
+    }
+    async fn on_error(&self, meta: &RequestMeta, error: &AIError) {
+        (**self).on_error(meta, error).await
+    }
+}
+impl<H: MutHandler> MutHandler for &mut H {
+    async fn handle_mut(&mut self, resp: &str) -> Result<(), HandlerError> {
+        (**self).handle_mut(resp).await
+    }
+    async fn handle_mut_flow(&mut self, resp: &str) -> Result<Flow, HandlerError> {
+        (**self).handle_mut_flow(resp).await
+    }
+    async fn on_start(&mut self, meta: &RequestMeta) -> Result<(), HandlerError> {
+        (**self).on_start(meta).await
+    }
+    async fn on_finish(&mut self, meta: &RequestMeta) -> Result<(), HandlerError> {
+        (**self).on_finish(meta).await
+    }
+    async fn on_error(&mut self, meta: &RequestMeta, error: &AIError) {
+        (**self).on_error(meta, error).await
+    }
+}
+
+macro_rules! impl_handler_for_tuple {
+    ($($name:ident:$index:tt),*) => {
+        impl<$($name: Handler),*> Handler for ($($name,)*) {
+            async fn handle(&self, resp: &str) -> Result<(), HandlerError> {
+                self.handle_flow(resp).await.map(|_| ())
+            }
+            async fn handle_flow(&self, resp: &str) -> Result<Flow, HandlerError> {
+                let mut flow = Flow::Continue;
+                $(
+                    flow = flow.and(self.$index.handle_flow(resp).await?);
+                )*
+                Ok(flow)
+            }
+            async fn on_start(&self, meta: &RequestMeta) -> Result<(), HandlerError> {
+                $(
+                    self.$index.on_start(meta).await?;
+                )*
+                Ok(())
+            }
+            async fn on_finish(&self, meta: &RequestMeta) -> Result<(), HandlerError> {
+                $(
+                    self.$index.on_finish(meta).await?;
+                )*
+                Ok(())
+            }
+            async fn on_error(&self, meta: &RequestMeta, error: &AIError) {
+                $(
+                    self.$index.on_error(meta, error).await;
+                )*
+            }
+        }
+        impl<$($name: MutHandler),*> MutHandler for ($($name,)*) {
+            async fn handle_mut(&mut self, resp: &str) -> Result<(), HandlerError> {
+                self.handle_mut_flow(resp).await.map(|_| ())
+            }
+            async fn handle_mut_flow(&mut self, resp: &str) -> Result<Flow, HandlerError> {
+                let mut flow = Flow::Continue;
+                $(
+                    flow = flow.and(self.$index.handle_mut_flow(resp).await?);
+                )*
+                Ok(flow)
+            }
+            async fn on_start(&mut self, meta: &RequestMeta) -> Result<(), HandlerError> {
+                $(
+                    self.$index.on_start(meta).await?;
+                )*
+                Ok(())
+            }
+            async fn on_finish(&mut self, meta: &RequestMeta) -> Result<(), HandlerError> {
+                $(
+                    self.$index.on_finish(meta).await?;
+                )*
+                Ok(())
+            }
+            async fn on_error(&mut self, meta: &RequestMeta, error: &AIError) {
+                $(
+                    self.$index.on_error(meta, error).await;
+                )*
+            }
+        }
+    };
+}
+impl_handler_for_tuple!(A:0, B:1);
+impl_handler_for_tuple!(A:0, B:1, C:2);
+impl_handler_for_tuple!(A:0, B:1, C:2, D:3);
+impl_handler_for_tuple!(A:0, B:1, C:2, D:3, E:4);
+
+/// Adds combinators to every handler.
+pub trait HandlerExt: Sized {
+    /// Passes each chunk to `self` and then to `other`.
+    fn tee<H>(self, other: H) -> (Self, H) {
+        (self, other)
+    }
+    /// Passes `f(chunk)` instead of the chunk.
+    fn map<F: Fn(&str) -> String>(self, f: F) -> Map<Self, F> {
+        Map { inner: self, f }
+    }
+    /// Passes only the chunks for which `f` returns true.
+    fn filter<F: Fn(&str) -> bool>(self, f: F) -> Filter<Self, F> {
+        Filter { inner: self, f }
+    }
+    /// Passes whole lines instead of chunks. Call `flush` for the last line.
+    fn buffered_by_line(self) -> BufferedByLine<Self> {
+        BufferedByLine {
+            inner: self,
+            buf: String::new(),
+        }
+    }
+    /// Passes the chunks received in `interval` at once. Call `flush` for the rest.
+    fn throttle(self, interval: Duration) -> Throttle<Self> {
+        Throttle {
+            inner: self,
+            interval,
+            buf: String::new(),
+            last: None,
+        }
+    }
+}
+impl<H> HandlerExt for H {}
+
+// passes the lifecycle hooks to `self.inner`.
+macro_rules! forward_hooks {
+    ($($mut:tt)?) => {
+        async fn on_start(&$($mut)? self, meta: &RequestMeta) -> Result<(), HandlerError> {
+            self.inner.on_start(meta).await
+        }
+        async fn on_finish(&$($mut)? self, meta: &RequestMeta) -> Result<(), HandlerError> {
+            self.inner.on_finish(meta).await
+        }
+        async fn on_error(&$($mut)? self, meta: &RequestMeta, error: &AIError) {
+            self.inner.on_error(meta, error).await
+        }
+    };
+}
+
+pub struct Map<H, F> {
+    inner: H,
+    f: F,
+}
+impl<H: Handler, F: Fn(&str) -> String> Handler for Map<H, F> {
+    async fn handle(&self, resp: &str) -> Result<(), HandlerError> {
+        self.inner.handle((self.f)(resp).as_str()).await
+    }
+    async fn handle_flow(&self, resp: &str) -> Result<Flow, HandlerError> {
+        self.inner.handle_flow((self.f)(resp).as_str()).await
+    }
+    forward_hooks!();
+}
+impl<H: MutHandler, F: Fn(&str) -> String> MutHandler for Map<H, F> {
+    async fn handle_mut(&mut self, resp: &str) -> Result<(), HandlerError> {
+        let resp = (self.f)(resp);
+        self.inner.handle_mut(resp.as_str()).await
+    }
+    async fn handle_mut_flow(&mut self, resp: &str) -> Result<Flow, HandlerError> {
+        let resp = (self.f)(resp);
+        self.inner.handle_mut_flow(resp.as_str()).await
+    }
+    forward_hooks!(mut);
+}
+
+pub struct Filter<H, F> {
+    inner: H,
+    f: F,
+}
+impl<H: Handler, F: Fn(&str) -> bool> Handler for Filter<H, F> {
+    async fn handle(&self, resp: &str) -> Result<(), HandlerError> {
+        self.handle_flow(resp).await.map(|_| ())
+    }
+    async fn handle_flow(&self, resp: &str) -> Result<Flow, HandlerError> {
+        if !(self.f)(resp) {
+            return Ok(Flow::Continue);
+        }
+        self.inner.handle_flow(resp).await
+    }
+    forward_hooks!();
+}
+impl<H: MutHandler, F: Fn(&str) -> bool> MutHandler for Filter<H, F> {
+    async fn handle_mut(&mut self, resp: &str) -> Result<(), HandlerError> {
+        self.handle_mut_flow(resp).await.map(|_| ())
+    }
+    async fn handle_mut_flow(&mut self, resp: &str) -> Result<Flow, HandlerError> {
+        if !(self.f)(resp) {
+            return Ok(Flow::Continue);
+        }
+        self.inner.handle_mut_flow(resp).await
+    }
+    forward_hooks!(mut);
+}
+
+/// Passes whole lines to the inner handler. The last line is passed by `flush` or `on_finish`.
+pub struct BufferedByLine<H> {
+    inner: H,
+    buf: String,
+}
+impl<H: MutHandler> BufferedByLine<H> {
+    /// Passes the buffered incomplete line.
+    pub async fn flush(&mut self) -> Result<Flow, HandlerError> {
+        if self.buf.is_empty() {
+            return Ok(Flow::Continue);
+        }
+        let rest = std::mem::take(&mut self.buf);
+        self.inner.handle_mut_flow(rest.as_str()).await
+    }
+    pub fn into_inner(self) -> H {
+        self.inner
+    }
+}
+impl<H: MutHandler> MutHandler for BufferedByLine<H> {
+    async fn handle_mut(&mut self, resp: &str) -> Result<(), HandlerError> {
+        self.handle_mut_flow(resp).await.map(|_| ())
+    }
+    async fn handle_mut_flow(&mut self, resp: &str) -> Result<Flow, HandlerError> {
+        self.buf.push_str(resp);
+        while let Some(pos) = self.buf.find('\n') {
+            let line = self.buf.drain(..=pos).collect::<String>();
+            if self.inner.handle_mut_flow(line.as_str()).await? == Flow::Stop {
+                return Ok(Flow::Stop);
+            }
+        }
+        Ok(Flow::Continue)
+    }
+    async fn on_start(&mut self, meta: &RequestMeta) -> Result<(), HandlerError> {
+        self.buf.clear();
+        self.inner.on_start(meta).await
+    }
+    async fn on_finish(&mut self, meta: &RequestMeta) -> Result<(), HandlerError> {
+        self.flush().await?;
+        self.inner.on_finish(meta).await
+    }
+    async fn on_error(&mut self, meta: &RequestMeta, error: &AIError) {
+        self.inner.on_error(meta, error).await
+    }
+}
+
+/// Passes the chunks received in an interval at once. The rest is passed by `flush` or
+/// `on_finish`.
+pub struct Throttle<H> {
+    inner: H,
+    interval: Duration,
+    buf: String,
+    last: Option<Instant>,
+}
+impl<H: MutHandler> Throttle<H> {
+    /// Passes the buffered chunks.
+    pub async fn flush(&mut self) -> Result<Flow, HandlerError> {
+        if self.buf.is_empty() {
+            return Ok(Flow::Continue);
+        }
+        let buf = std::mem::take(&mut self.buf);
+        self.last = Some(Instant::now());
+        self.inner.handle_mut_flow(buf.as_str()).await
+    }
+    pub fn into_inner(self) -> H {
+        self.inner
+    }
+}
+impl<H: MutHandler> MutHandler for Throttle<H> {
+    async fn handle_mut(&mut self, resp: &str) -> Result<(), HandlerError> {
+        self.handle_mut_flow(resp).await.map(|_| ())
+    }
+    async fn handle_mut_flow(&mut self, resp: &str) -> Result<Flow, HandlerError> {
+        self.buf.push_str(resp);
+        if self.last.is_none_or(|last| last.elapsed() >= self.interval) {
+            return self.flush().await;
+        }
+        Ok(Flow::Continue)
+    }
+    async fn on_start(&mut self, meta: &RequestMeta) -> Result<(), HandlerError> {
+        self.buf.clear();
+        self.last = None;
+        self.inner.on_start(meta).await
+    }
+    async fn on_finish(&mut self, meta: &RequestMeta) -> Result<(), HandlerError> {
+        self.flush().await?;
+        self.inner.on_finish(meta).await
+    }
+    async fn on_error(&mut self, meta: &RequestMeta, error: &AIError) {
+        self.inner.on_error(meta, error).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{clients::mocks::MockHandler, handlers::recorder::Recorder};
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Chunks(Vec<String>);
+    impl MutHandler for Chunks {
+        async fn handle_mut(&mut self, resp: &str) -> Result<(), HandlerError> {
+            self.0.push(resp.to_string());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn tuples_and_boxed_handlers_receive_every_chunk() {
+        let mut recorder = Recorder::new();
+        let mut mock = MockHandler::new();
+        {
+            let mut pipeline: Vec<Box<dyn DynMutHandler>> =
+                vec![Box::new(&mut recorder), Box::new(&mut mock)];
+            pipeline.handle_mut("a").await.unwrap();
+            pipeline.handle_mut("b").await.unwrap();
+        }
+        let mut tuple = (Recorder::new(), MockHandler::new());
+        tuple.handle_mut("c").await.unwrap();
+
+        assert_eq!(recorder.message(), "ab");
+        assert_eq!(mock.received, "ab");
+        assert_eq!(tuple.0.message(), "c");
+        assert!(tuple.1.has_received);
+    }
+    #[tokio::test]
+    async fn pipeline_stops_if_any_handler_stops_and_forwards_hooks() {
+        struct StopAt(&'static str);
+        impl MutHandler for StopAt {
+            async fn handle_mut(&mut self, _: &str) -> Result<(), HandlerError> {
+                Ok(())
+            }
+            async fn handle_mut_flow(&mut self, resp: &str) -> Result<Flow, HandlerError> {
+                Ok(if resp == self.0 {
+                    Flow::Stop
+                } else {
+                    Flow::Continue
+                })
+            }
+        }
+        let meta = RequestMeta::new("model", &crate::Prompt::ask("hi"));
+        let mut recorder = Recorder::new();
+        recorder.handle_mut("previous response").await.unwrap();
+        let mut sut = (
+            (&mut recorder).buffered_by_line(),
+            StopAt("b").map(|s| s.trim().to_string()),
+        );
+
+        sut.on_start(&meta).await.unwrap();
+        let flows = [
+            sut.handle_mut_flow("a\n").await.unwrap(),
+            sut.handle_mut_flow("b\n").await.unwrap(),
+        ];
+        sut.handle_mut_flow("c").await.unwrap();
+        sut.on_finish(&meta).await.unwrap();
+
+        assert_eq!(flows, [Flow::Continue, Flow::Stop]);
+        assert_eq!(recorder.message(), "a\nb\nc");
+    }
+    #[tokio::test]
+    async fn map_and_filter_transform_chunks() {
+        let mut sut = Recorder::new()
+            .map(|s| s.to_uppercase())
+            .filter(|s| !s.contains('x'))
+            .tee(MockHandler::new());
+
+        for chunk in ["a", "x", "b"] {
+            sut.handle_mut(chunk).await.unwrap();
+        }
+
+        assert_eq!(sut.0.inner.inner.message(), "AB");
+        assert_eq!(sut.1.received, "axb");
+    }
+    #[tokio::test]
+    async fn buffered_by_line_passes_whole_lines() {
+        let mut sut = Chunks::default().buffered_by_line();
+        for chunk in ["he", "llo\nwor", "ld\n!"] {
+            sut.handle_mut(chunk).await.unwrap();
+        }
+        sut.flush().await.unwrap();
+
+        assert_eq!(sut.into_inner().0, vec!["hello\n", "world\n", "!"]);
+    }
+    #[tokio::test]
+    async fn throttle_passes_chunks_at_most_once_per_interval() {
+        let mut sut = Chunks::default().throttle(Duration::from_secs(60));
+        for chunk in ["a", "b", "c"] {
+            sut.handle_mut(chunk).await.unwrap();
+        }
+        sut.flush().await.unwrap();
+
+        assert_eq!(sut.into_inner().0, vec!["a", "bc"]);
+    }
+}
@@ -0,0 +1,313 @@
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::{Flow, HandlerError, MutHandler, RequestMeta};
+
+/// A fenced code block found in the response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeBlock {
+    pub lang: String,
+    // file name given in the info string, like "```rust path=src/main.rs".
+    pub path: Option<String>,
+    pub content: String,
+}
+
+impl CodeBlock {
+    fn from_info(info: &str) -> Self {
+        let mut lang = String::new();
+        let mut path = None;
+        for (i, token) in info.split_whitespace().enumerate() {
+            let token = token.trim_matches(|c| c == '"' || c == '\'');
+            if let Some((key, value)) = token.split_once('=') {
+                if matches!(key, "path" | "file" | "filename" | "title") {
+                    path = Some(value.trim_matches(|c| c == '"' || c == '\'').to_string());
+                }
+                continue;
+            }
+            if i != 0 {
+                continue;
+            }
+            // "```rust:src/main.rs" or "```src/main.rs"
+            match token.split_once(':') {
+                Some((l, p)) => {
+                    lang = l.to_string();
+                    path = Some(p.to_string());
+                }
+                None if is_path(token) => path = Some(token.to_string()),
+                None => lang = token.to_string(),
+            }
+        }
+        Self {
+            lang,
+            path,
+            content: String::new(),
+        }
+    }
+    fn extension(&self) -> &str {
+        lang_extension(&self.lang).unwrap_or("txt")
+    }
+}
+
+fn lang_extension(lang: &str) -> Option<&'static str> {
+    let extension = match lang {
+        "rust" | "rs" => "rs",
+        "python" | "py" => "py",
+        "javascript" | "js" => "js",
+        "typescript" | "ts" => "ts",
+        "go" => "go",
+        "sh" | "bash" | "shell" | "zsh" => "sh",
+        "json" => "json",
+        "toml" => "toml",
+        "yaml" | "yml" => "yaml",
+        "html" => "html",
+        "css" => "css",
+        "sql" => "sql",
+        "markdown" | "md" => "md",
+        "text" | "txt" => "txt",
+        _ => return None,
+    };
+    Some(extension)
+}
+
+// "src/app", or "main.rs" whose extension is known. "python3.11" is a language.
+fn is_path(token: &str) -> bool {
+    token.contains(['/', '\\'])
+        || token.rsplit_once('.').is_some_and(|(name, extension)| {
+            !name.is_empty()
+                && lang_extension(extension).is_some()
+                && lang_extension(token).is_none()
+        })
+}
+
+/// Writes fenced code blocks of the response into a directory.
+///
+/// A block is written as soon as it is closed. If the file already exists, the block is kept
+/// until `finish`, which asks whether it may be overwritten.
+pub struct CodeExtractor {
+    dir: PathBuf,
+    pending: String,
+    current: Option<CodeBlock>,
+    count: usize,
+    // if you set this value, the response is stopped after this number of blocks.
+    max_blocks: Option<usize>,
+    written: Vec<PathBuf>,
+    conflicts: Vec<(PathBuf, CodeBlock)>,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub struct ExtractError(anyhow::Error);
+crate::impl_from_error!(ExtractError);
+
+impl CodeExtractor {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            pending: String::new(),
+            current: None,
+            count: 0,
+            max_blocks: None,
+            written: Vec::new(),
+            conflicts: Vec::new(),
+        }
+    }
+    pub fn max_blocks(mut self, max_blocks: Option<usize>) -> Self {
+        self.max_blocks = max_blocks;
+        self
+    }
+    /// Files written so far.
+    pub fn written(&self) -> &[PathBuf] {
+        &self.written
+    }
+    /// Resolves blocks whose file already exists. `confirm` decides if the file is overwritten.
+    /// Returns all written files.
+    pub fn finish<F>(&mut self, mut confirm: F) -> Result<&[PathBuf], ExtractError>
+    where
+        F: FnMut(&Path) -> bool,
+    {
+        self.close_pending()?;
+        for (path, block) in std::mem::take(&mut self.conflicts) {
+            if confirm(&path) {
+                self.write(path, &block)?;
+            }
+        }
+        Ok(&self.written)
+    }
+    fn close_pending(&mut self) -> Result<(), ExtractError> {
+        if !self.pending.is_empty() {
+            let line = std::mem::take(&mut self.pending);
+            self.line(&line)?;
+        }
+        // an unclosed block at the end of the response is still useful.
+        if let Some(block) = self.current.take() {
+            self.close(block)?;
+        }
+        Ok(())
+    }
+    fn line(&mut self, line: &str) -> Result<(), ExtractError> {
+        let fence = line.trim_start().strip_prefix("```");
+        match (&mut self.current, fence) {
+            (Some(_), Some(_)) => {
+                if let Some(block) = self.current.take() {
+                    self.close(block)?;
+                }
+            }
+            (Some(block), None) => {
+                block.content.push_str(line);
+                block.content.push('\n');
+            }
+            (None, Some(info)) => self.current = Some(CodeBlock::from_info(info)),
+            (None, None) => {}
+        }
+        Ok(())
+    }
+    fn close(&mut self, block: CodeBlock) -> Result<(), ExtractError> {
+        self.count += 1;
+        // a hint pointing outside of the directory is ignored.
+        let path = match block.path.as_deref().and_then(safe_relative_path) {
+            Some(path) => self.dir.join(path),
+            None => self
+                .dir
+                .join(format!("snippet-{}.{}", self.count, block.extension())),
+        };
+        if path.exists() {
+            self.conflicts.push((path, block));
+            return Ok(());
+        }
+        self.write(path, &block)
+    }
+    fn write(&mut self, path: PathBuf, block: &CodeBlock) -> Result<(), ExtractError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        std::fs::write(&path, &block.content)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        self.written.push(path);
+        Ok(())
+    }
+}
+
+impl MutHandler for CodeExtractor {
+    async fn handle_mut(&mut self, resp: &str) -> Result<(), HandlerError> {
+        self.handle_mut_flow(resp).await.map(|_| ())
+    }
+    async fn handle_mut_flow(&mut self, resp: &str) -> Result<Flow, HandlerError> {
+        self.pending.push_str(resp);
+        while let Some(pos) = self.pending.find('\n') {
+            let line = self.pending[..pos].to_string();
+            self.pending.drain(..=pos);
+            self.line(&line).context("Failed to extract code block")?;
+            if self.max_blocks.is_some_and(|max| self.count >= max) {
+                self.pending.clear();
+                return Ok(Flow::Stop);
+            }
+        }
+        Ok(Flow::Continue)
+    }
+    async fn on_finish(&mut self, _meta: &RequestMeta) -> Result<(), HandlerError> {
+        Ok(self
+            .close_pending()
+            .context("Failed to extract code block")?)
+    }
+}
+
+// the path comes from the model, so it must not point outside of the target directory.
+fn safe_relative_path(path: &str) -> Option<PathBuf> {
+    let path = Path::new(path);
+    path.components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        .then(|| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn info_string_hints_are_parsed() {
+        let cases = [
+            ("rust", "rust", None),
+            ("rust path=src/main.rs", "rust", Some("src/main.rs")),
+            ("python filename=\"app.py\"", "python", Some("app.py")),
+            ("rust:src/lib.rs", "rust", Some("src/lib.rs")),
+            ("Cargo.toml", "", Some("Cargo.toml")),
+        ];
+        for (info, lang, path) in cases {
+            let block = CodeBlock::from_info(info);
+            assert_eq!(block.lang, lang, "{}", info);
+            assert_eq!(block.path.as_deref(), path, "{}", info);
+        }
+    }
+    #[test]
+    fn language_with_version_is_not_a_path() {
+        let block = CodeBlock::from_info("python3.11");
+        assert_eq!(block.lang, "python3.11");
+        assert_eq!(block.path, None);
+
+        let block = CodeBlock::from_info("scripts/setup");
+        assert_eq!(block.path.as_deref(), Some("scripts/setup"));
+    }
+    #[tokio::test]
+    async fn blocks_are_written_and_conflicts_are_confirmed() {
+        let dir = std::env::temp_dir().join(format!("cai-extract-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("keep.txt"), "old").unwrap();
+        let mut sut = CodeExtractor::new(&dir);
+
+        for chunk in [
+            "here:\n```rust path=src/main.",
+            "rs\nfn main() {}\n```\n```sh\necho hi\n```\n",
+            "```txt path=keep.txt\nnew\n```\n```txt path=../x.txt\nx\n```\n",
+        ] {
+            sut.handle_mut(chunk).await.unwrap();
+        }
+        let mut asked = vec![];
+        let written = sut
+            .finish(|path| {
+                asked.push(path.to_path_buf());
+                false
+            })
+            .unwrap()
+            .to_vec();
+
+        assert_eq!(
+            written,
+            vec![
+                dir.join("src/main.rs"),
+                dir.join("snippet-2.sh"),
+                dir.join("snippet-4.txt")
+            ]
+        );
+        assert_eq!(asked, vec![dir.join("keep.txt")]);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("src/main.rs")).unwrap(),
+            "fn main() {}\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("keep.txt")).unwrap(),
+            "old"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    #[tokio::test]
+    async fn response_is_stopped_after_max_blocks() {
+        let dir = std::env::temp_dir().join(format!("cai-extract-max-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut sut = CodeExtractor::new(&dir).max_blocks(Some(1));
+
+        let flows = [
+            sut.handle_mut_flow("```sh\necho 1\n").await.unwrap(),
+            sut.handle_mut_flow("```\ntext\n```sh\n").await.unwrap(),
+        ];
+        sut.on_finish(&RequestMeta::new("model", &crate::Prompt::ask("hi")))
+            .await
+            .unwrap();
+
+        assert_eq!(flows, [Flow::Continue, Flow::Stop]);
+        assert_eq!(sut.written(), [dir.join("snippet-1.sh")]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
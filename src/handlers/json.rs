@@ -0,0 +1,197 @@
+use std::{io::Write, time::Instant};
+
+use anyhow::Context;
+
+use crate::{AIError, HandlerError, MutHandler, Prompt, RequestMeta, limiter::estimate_tokens};
+
+/// Summary of a response for scripts.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct Report {
+    pub text: String,
+    pub engine: String,
+    pub model: String,
+    // the clients do not tell why the response ended, so this is "stop" or "error".
+    pub finish_reason: String,
+    pub usage: Usage,
+    pub timing: Timing,
+    pub cached: bool,
+}
+
+/// Estimated by `limiter::estimate_tokens` because the clients do not report usage.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub total_tokens: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize)]
+pub struct Timing {
+    // unix time in seconds
+    pub started_at: u64,
+    pub first_chunk_ms: Option<u64>,
+    pub duration_ms: u64,
+}
+
+/// Events written as JSON lines while the response is streamed.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event<'a> {
+    Start {
+        engine: &'a str,
+        model: &'a str,
+        cached: bool,
+    },
+    Delta {
+        text: &'a str,
+    },
+    Finish(&'a Report),
+    Error {
+        message: String,
+    },
+}
+
+/// Collects the response into a `Report`. With `jsonl`, every event is also printed to stdout
+/// as a JSON line.
+pub struct JsonReporter {
+    report: Report,
+    started: Option<Instant>,
+    jsonl: bool,
+}
+
+impl JsonReporter {
+    pub fn new(engine: &str) -> Self {
+        Self {
+            report: Report {
+                engine: engine.to_string(),
+                ..Default::default()
+            },
+            started: None,
+            jsonl: false,
+        }
+    }
+    pub fn jsonl(mut self, jsonl: bool) -> Self {
+        self.jsonl = jsonl;
+        self
+    }
+    pub fn report(&self) -> &Report {
+        &self.report
+    }
+    pub fn into_report(self) -> Report {
+        self.report
+    }
+    fn elapsed_ms(&self) -> u64 {
+        self.started
+            .map(|started| started.elapsed().as_millis() as u64)
+            .unwrap_or_default()
+    }
+    fn emit(&self, event: &Event) -> Result<(), HandlerError> {
+        if !self.jsonl {
+            return Ok(());
+        }
+        let line = serde_json::to_string(event).context("Failed to serialize event")?;
+        let mut stdout = std::io::stdout().lock();
+        writeln!(stdout, "{}", line).context("Failed to write event")?;
+        Ok(stdout.flush().context("Failed to flush stdout")?)
+    }
+}
+
+impl MutHandler for JsonReporter {
+    async fn handle_mut(&mut self, resp: &str) -> Result<(), HandlerError> {
+        if resp.is_empty() {
+            return Ok(());
+        }
+        if self.report.timing.first_chunk_ms.is_none() {
+            self.report.timing.first_chunk_ms = Some(self.elapsed_ms());
+        }
+        self.report.text.push_str(resp);
+        self.emit(&Event::Delta { text: resp })
+    }
+    async fn on_start(&mut self, meta: &RequestMeta) -> Result<(), HandlerError> {
+        self.report = Report {
+            engine: std::mem::take(&mut self.report.engine),
+            model: meta.model.clone(),
+            usage: Usage {
+                prompt_tokens: meta.prompt_tokens,
+                ..Default::default()
+            },
+            timing: Timing {
+                started_at: meta.started_at,
+                ..Default::default()
+            },
+            cached: meta.cached,
+            ..Default::default()
+        };
+        self.started = Some(Instant::now());
+        self.emit(&Event::Start {
+            engine: &self.report.engine,
+            model: &self.report.model,
+            cached: self.report.cached,
+        })
+    }
+    async fn on_finish(&mut self, _meta: &RequestMeta) -> Result<(), HandlerError> {
+        let completion_tokens = estimate_tokens(&Prompt::ask(&self.report.text));
+        self.report.finish_reason = "stop".to_string();
+        self.report.usage.completion_tokens = completion_tokens;
+        self.report.usage.total_tokens = self.report.usage.prompt_tokens + completion_tokens;
+        self.report.timing.duration_ms = self.elapsed_ms();
+        self.emit(&Event::Finish(&self.report))
+    }
+    async fn on_error(&mut self, _meta: &RequestMeta, error: &AIError) {
+        self.report.finish_reason = "error".to_string();
+        self.report.timing.duration_ms = self.elapsed_ms();
+        let _ = self.emit(&Event::Error {
+            message: error.to_string(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn report_is_built_from_hooks_and_chunks() {
+        let meta = RequestMeta::new("gpt-4o-mini", &Prompt::ask("12345678")).cached(true);
+        let mut sut = JsonReporter::new("gpt4-o-mini");
+
+        sut.on_start(&meta).await.unwrap();
+        for chunk in ["hel", "", "lo!"] {
+            sut.handle_mut(chunk).await.unwrap();
+        }
+        sut.on_finish(&meta).await.unwrap();
+        let report = sut.into_report();
+
+        assert_eq!(report.text, "hello!");
+        assert_eq!(report.engine, "gpt4-o-mini");
+        assert_eq!(report.model, "gpt-4o-mini");
+        assert_eq!(report.finish_reason, "stop");
+        assert_eq!(
+            report.usage,
+            Usage {
+                prompt_tokens: 2,
+                completion_tokens: 2,
+                total_tokens: 4
+            }
+        );
+        assert_eq!(report.timing.started_at, meta.started_at);
+        assert!(report.timing.first_chunk_ms.is_some());
+        assert!(report.cached);
+    }
+    #[test]
+    fn events_are_tagged_by_type() {
+        let report = Report {
+            text: "hi".to_string(),
+            finish_reason: "stop".to_string(),
+            ..Default::default()
+        };
+
+        let delta = serde_json::to_value(Event::Delta { text: "hi" }).unwrap();
+        let finish = serde_json::to_value(Event::Finish(&report)).unwrap();
+
+        assert_eq!(delta, serde_json::json!({"type": "delta", "text": "hi"}));
+        assert_eq!(finish["type"], "finish");
+        assert_eq!(finish["text"], "hi");
+        assert_eq!(finish["usage"]["total_tokens"], 0);
+    }
+}
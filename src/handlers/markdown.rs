@@ -0,0 +1,545 @@
+use std::{
+    io::{IsTerminal, Write},
+    sync::Mutex,
+};
+
+use anyhow::Context;
+
+use crate::{Handler, HandlerError, MutHandler, RequestMeta};
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const ITALIC: &str = "\x1b[3m";
+const UNDERLINE: &str = "\x1b[4m";
+const CYAN: &str = "\x1b[36m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const MAGENTA: &str = "\x1b[35m";
+const GRAY: &str = "\x1b[90m";
+
+/// Prints the response to stdout with markdown rendered for the terminal.
+/// If stdout is not a terminal, the response is printed as it is like `Printer`.
+pub struct MarkdownPrinter {
+    renderer: Mutex<MarkdownRenderer>,
+    terminal: bool,
+    render: bool,
+}
+
+impl MarkdownPrinter {
+    pub fn new() -> Self {
+        let terminal = std::io::stdout().is_terminal();
+        Self {
+            renderer: Mutex::new(MarkdownRenderer::new()),
+            terminal,
+            render: terminal,
+        }
+    }
+    /// If you set false, the response is printed as it is even on a terminal.
+    pub fn render(mut self, render: bool) -> Self {
+        self.render = self.terminal && render;
+        self
+    }
+    /// Renders the buffered incomplete line. This is called by `on_finish`.
+    pub fn finish(&self) {
+        if !self.render {
+            if self.terminal {
+                println!();
+            }
+            return;
+        }
+        let rendered = self
+            .renderer
+            .lock()
+            .map(|mut r| r.finish())
+            .unwrap_or_default();
+        print!("{}", rendered);
+        if !rendered.ends_with('\n') {
+            println!();
+        }
+        let _ = std::io::stdout().flush();
+    }
+}
+
+impl Default for MarkdownPrinter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Handler for MarkdownPrinter {
+    async fn handle(&self, resp: &str) -> Result<(), HandlerError> {
+        if self.render {
+            let rendered = self
+                .renderer
+                .lock()
+                .map(|mut r| r.push(resp))
+                .unwrap_or_default();
+            print!("{}", rendered);
+        } else {
+            print!("{}", resp);
+        }
+        Ok(std::io::stdout()
+            .flush()
+            .context("Failed to flush stdout")?)
+    }
+    async fn on_finish(&self, _meta: &RequestMeta) -> Result<(), HandlerError> {
+        self.finish();
+        Ok(())
+    }
+}
+impl MutHandler for MarkdownPrinter {
+    async fn handle_mut(&mut self, resp: &str) -> Result<(), HandlerError> {
+        self.handle(resp).await
+    }
+    async fn on_finish(&mut self, meta: &RequestMeta) -> Result<(), HandlerError> {
+        Handler::on_finish(self, meta).await
+    }
+}
+
+/// Converts streamed markdown into text with ANSI escape sequences.
+/// Lines are rendered as soon as they are complete. Tables are buffered until they end
+/// because the width of the columns is not known before.
+#[derive(Debug, Default)]
+pub struct MarkdownRenderer {
+    pending: String,
+    // language of the fenced code block being rendered.
+    code: Option<String>,
+    table: Vec<String>,
+}
+
+impl MarkdownRenderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn push(&mut self, chunk: &str) -> String {
+        self.pending.push_str(chunk);
+        let mut rendered = String::new();
+        while let Some(pos) = self.pending.find('\n') {
+            let line = self.pending[..pos].to_string();
+            self.pending.drain(..=pos);
+            rendered.push_str(&self.line(&line));
+        }
+        rendered
+    }
+    pub fn finish(&mut self) -> String {
+        let mut rendered = String::new();
+        if !self.pending.is_empty() {
+            let line = std::mem::take(&mut self.pending);
+            rendered.push_str(&self.line(&line));
+        }
+        rendered.push_str(&self.flush_table());
+        self.code = None;
+        rendered
+    }
+    fn line(&mut self, line: &str) -> String {
+        if let Some(lang) = &self.code {
+            if line.trim_start().starts_with("```") {
+                self.code = None;
+                return format!("{}{}{}\n", DIM, line, RESET);
+            }
+            return format!("{}\n", highlight(line, lang));
+        }
+        let trimmed = line.trim_start();
+        if trimmed.starts_with('|') {
+            self.table.push(trimmed.to_string());
+            return String::new();
+        }
+        let mut rendered = self.flush_table();
+        if let Some(info) = trimmed.strip_prefix("```") {
+            let lang = info.split_whitespace().next().unwrap_or_default();
+            self.code = Some(lang.to_string());
+            rendered.push_str(&format!("{}{}{}\n", DIM, line, RESET));
+            return rendered;
+        }
+        rendered.push_str(&render_block_line(line));
+        rendered.push('\n');
+        rendered
+    }
+    fn flush_table(&mut self) -> String {
+        if self.table.is_empty() {
+            return String::new();
+        }
+        let rows = std::mem::take(&mut self.table)
+            .into_iter()
+            .map(|row| {
+                row.trim()
+                    .trim_start_matches('|')
+                    .trim_end_matches('|')
+                    .split('|')
+                    .map(|cell| cell.trim().to_string())
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let is_separator = |row: &[String]| {
+            row.iter()
+                .all(|c| !c.is_empty() && c.chars().all(|ch| matches!(ch, '-' | ':' | ' ')))
+        };
+        let columns = rows.iter().map(Vec::len).max().unwrap_or_default();
+        let widths = (0..columns)
+            .map(|i| {
+                rows.iter()
+                    .filter(|row| !is_separator(row))
+                    .filter_map(|row| row.get(i))
+                    .map(|cell| cell.chars().count())
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<_>>();
+        rows.iter()
+            .enumerate()
+            .map(|(i, row)| {
+                if is_separator(row) {
+                    let line = widths
+                        .iter()
+                        .map(|w| "─".repeat(*w))
+                        .collect::<Vec<_>>()
+                        .join("─┼─");
+                    return format!("{}{}{}\n", DIM, line, RESET);
+                }
+                let line = widths
+                    .iter()
+                    .enumerate()
+                    .map(|(j, w)| {
+                        let cell = row.get(j).map(String::as_str).unwrap_or_default();
+                        let padding = " ".repeat(w - cell.chars().count());
+                        let cell = render_inline(cell);
+                        if i == 0 {
+                            format!("{}{}{}{}", BOLD, cell, RESET, padding)
+                        } else {
+                            format!("{}{}", cell, padding)
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join(&format!(" {}│{} ", DIM, RESET));
+                format!("{}\n", line.trim_end())
+            })
+            .collect()
+    }
+}
+
+fn render_block_line(line: &str) -> String {
+    let trimmed = line.trim_start();
+    let indent = &line[..line.len() - trimmed.len()];
+    let level = trimmed.chars().take_while(|c| *c == '#').count();
+    if (1..=6).contains(&level) && trimmed[level..].starts_with(' ') {
+        let style = if level == 1 {
+            format!("{}{}{}", BOLD, UNDERLINE, CYAN)
+        } else {
+            format!("{}{}", BOLD, CYAN)
+        };
+        return format!(
+            "{}{}{}",
+            style,
+            render_inline(trimmed[level..].trim()),
+            RESET
+        );
+    }
+    if !trimmed.is_empty()
+        && trimmed.chars().all(|c| c == '-' || c == '*' || c == '_')
+        && trimmed.len() >= 3
+    {
+        return format!("{}{}{}", DIM, "─".repeat(40), RESET);
+    }
+    if let Some(quote) = trimmed.strip_prefix('>') {
+        return format!(
+            "{}{}│{} {}",
+            indent,
+            DIM,
+            RESET,
+            render_inline(quote.trim_start())
+        );
+    }
+    for bullet in ["- ", "* ", "+ "] {
+        if let Some(item) = trimmed.strip_prefix(bullet) {
+            return format!("{}{}•{} {}", indent, YELLOW, RESET, render_inline(item));
+        }
+    }
+    let digits = trimmed.chars().take_while(char::is_ascii_digit).count();
+    if digits > 0 && trimmed[digits..].starts_with(". ") {
+        return format!(
+            "{}{}{}{} {}",
+            indent,
+            YELLOW,
+            &trimmed[..digits + 1],
+            RESET,
+            render_inline(&trimmed[digits + 2..])
+        );
+    }
+    format!("{}{}", indent, render_inline(trimmed))
+}
+
+/// Renders `**bold**`, `*italic*`, `_italic_` and `` `code` ``. Unclosed markers are kept as they are.
+/// Like CommonMark, a marker followed or preceded by a space is not emphasis, and `_` inside a
+/// word, like in `snake_case`, is kept.
+pub fn render_inline(text: &str) -> String {
+    let mut rendered = String::new();
+    let mut rest = text;
+    // the character before `rest`, which tells whether a marker is inside a word.
+    let mut prev = None;
+    while !rest.is_empty() {
+        let styled = [("**", BOLD), ("`", GREEN), ("*", ITALIC), ("_", ITALIC)]
+            .into_iter()
+            .find_map(|(marker, style)| {
+                let inner = rest.strip_prefix(marker)?;
+                let end = if marker == "`" {
+                    inner.find(marker)?
+                } else {
+                    emphasis_end(prev, inner, marker)?
+                };
+                if end == 0 {
+                    return None;
+                }
+                let content = &inner[..end];
+                let content = if marker == "`" {
+                    content.to_string()
+                } else {
+                    render_inline(content)
+                };
+                Some((
+                    format!("{}{}{}", style, content, RESET),
+                    marker.len() * 2 + end,
+                ))
+            });
+        match styled {
+            Some((styled, consumed)) => {
+                rendered.push_str(&styled);
+                prev = rest[..consumed].chars().last();
+                rest = &rest[consumed..];
+            }
+            None => {
+                let c = rest.chars().next().unwrap_or_default();
+                rendered.push(c);
+                prev = Some(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    rendered
+}
+
+// where the emphasis which starts with `marker` before `inner` is closed.
+fn emphasis_end(prev: Option<char>, inner: &str, marker: &str) -> Option<usize> {
+    let in_word = |c: Option<char>| c.is_some_and(char::is_alphanumeric);
+    if inner.starts_with(char::is_whitespace) || (marker == "_" && in_word(prev)) {
+        return None;
+    }
+    inner.match_indices(marker).map(|(i, _)| i).find(|&i| {
+        let before = inner[..i].chars().last();
+        let after = inner[i + marker.len()..].chars().next();
+        let after_space = before.is_some_and(char::is_whitespace);
+        let closes_in_word = marker == "_" && in_word(after);
+        !(after_space || closes_in_word)
+    })
+}
+
+/// Colors keywords, strings, numbers and comments of a line of code.
+pub fn highlight(line: &str, lang: &str) -> String {
+    let keywords = keywords(lang);
+    let comment = match lang {
+        "python" | "py" | "sh" | "bash" | "shell" | "zsh" | "toml" | "yaml" | "yml" | "ruby"
+        | "rb" => "#",
+        "sql" | "lua" | "haskell" | "hs" => "--",
+        _ => "//",
+    };
+    let mut rendered = String::new();
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with(comment) {
+            rendered.push_str(&format!("{}{}{}", GRAY, rest, RESET));
+            break;
+        }
+        if c == '"' || c == '\'' || c == '`' {
+            let end = rest[1..]
+                .char_indices()
+                .scan(false, |escaped, (i, ch)| {
+                    let found = !*escaped && ch == c;
+                    *escaped = !*escaped && ch == '\\';
+                    Some((i, found))
+                })
+                .find(|(_, found)| *found)
+                .map(|(i, _)| i + 2)
+                .unwrap_or(rest.len());
+            rendered.push_str(&format!("{}{}{}", GREEN, &rest[..end], RESET));
+            rest = &rest[end..];
+            continue;
+        }
+        if c.is_alphanumeric() || c == '_' {
+            let end = rest
+                .char_indices()
+                .find(|(_, ch)| !(ch.is_alphanumeric() || *ch == '_'))
+                .map(|(i, _)| i)
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            if word.chars().next().is_some_and(|ch| ch.is_ascii_digit()) {
+                rendered.push_str(&format!("{}{}{}", YELLOW, word, RESET));
+            } else if keywords.contains(&word) {
+                rendered.push_str(&format!("{}{}{}", MAGENTA, word, RESET));
+            } else {
+                rendered.push_str(word);
+            }
+            rest = &rest[end..];
+            continue;
+        }
+        rendered.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    rendered
+}
+
+fn keywords(lang: &str) -> &'static [&'static str] {
+    match lang {
+        "rust" | "rs" => &[
+            "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+            "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut",
+            "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true",
+            "type", "unsafe", "use", "where", "while",
+        ],
+        "python" | "py" => &[
+            "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del",
+            "elif", "else", "except", "False", "finally", "for", "from", "if", "import", "in",
+            "is", "lambda", "None", "not", "or", "pass", "raise", "return", "True", "try", "while",
+            "with", "yield",
+        ],
+        "go" => &[
+            "break",
+            "case",
+            "chan",
+            "const",
+            "continue",
+            "default",
+            "defer",
+            "else",
+            "false",
+            "for",
+            "func",
+            "go",
+            "if",
+            "import",
+            "interface",
+            "map",
+            "nil",
+            "package",
+            "range",
+            "return",
+            "select",
+            "struct",
+            "switch",
+            "true",
+            "type",
+            "var",
+        ],
+        "sh" | "bash" | "shell" | "zsh" => &[
+            "case", "do", "done", "echo", "elif", "else", "esac", "export", "fi", "for",
+            "function", "if", "in", "local", "return", "then", "while",
+        ],
+        _ => &[
+            "async",
+            "await",
+            "break",
+            "case",
+            "catch",
+            "class",
+            "const",
+            "continue",
+            "default",
+            "else",
+            "export",
+            "extends",
+            "false",
+            "finally",
+            "for",
+            "function",
+            "if",
+            "import",
+            "in",
+            "interface",
+            "let",
+            "new",
+            "null",
+            "return",
+            "static",
+            "switch",
+            "this",
+            "throw",
+            "true",
+            "try",
+            "type",
+            "typeof",
+            "undefined",
+            "var",
+            "void",
+            "while",
+        ],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_rendered_when_complete() {
+        let mut sut = MarkdownRenderer::new();
+
+        assert_eq!(sut.push("# Ti"), "");
+        assert_eq!(
+            sut.push("tle\n- **a"),
+            format!("{}{}{}Title{}\n", BOLD, UNDERLINE, CYAN, RESET)
+        );
+        assert_eq!(
+            sut.push("** b\n"),
+            format!("{}•{} {}a{} b\n", YELLOW, RESET, BOLD, RESET)
+        );
+        assert_eq!(sut.finish(), "");
+    }
+    #[test]
+    fn code_blocks_are_highlighted() {
+        let mut sut = MarkdownRenderer::new();
+        let rendered = sut.push("```rust\nlet x = \"*a*\"; // c\n```\n");
+
+        assert_eq!(
+            rendered,
+            format!(
+                "{DIM}```rust{RESET}\n{MAGENTA}let{RESET} x = {GREEN}\"*a*\"{RESET}; {GRAY}// c{RESET}\n{DIM}```{RESET}\n"
+            )
+        );
+    }
+    #[test]
+    fn tables_are_aligned_after_they_end() {
+        let mut sut = MarkdownRenderer::new();
+
+        assert_eq!(sut.push("| a | bb |\n|---|---|\n| ccc | d |\n"), "");
+        let rendered = sut.push("end\n");
+
+        let sep = format!(" {}│{} ", DIM, RESET);
+        assert_eq!(
+            rendered,
+            format!("{BOLD}a{RESET}  {sep}{BOLD}bb{RESET}\n{DIM}────┼───{RESET}\nccc{sep}d\nend\n")
+        );
+    }
+    #[test]
+    fn unclosed_inline_markers_are_kept() {
+        assert_eq!(render_inline("2 * 3 = 6"), "2 * 3 = 6");
+        assert_eq!(
+            render_inline("use `a_b` and _c_"),
+            format!("use {GREEN}a_b{RESET} and {ITALIC}c{RESET}")
+        );
+    }
+    #[test]
+    fn markers_in_words_and_around_spaces_are_kept() {
+        assert_eq!(
+            render_inline("call snake_case_name"),
+            "call snake_case_name"
+        );
+        assert_eq!(render_inline("a * b * c"), "a * b * c");
+        assert_eq!(
+            render_inline("2 * 3 *4*"),
+            format!("2 * 3 {ITALIC}4{RESET}")
+        );
+        assert_eq!(
+            render_inline("_a_b_ and *x*"),
+            format!("{ITALIC}a_b{RESET} and {ITALIC}x{RESET}")
+        );
+    }
+}
@@ -1,14 +1,35 @@
-use std::io::Write;
+use std::io::{IsTerminal, Write};
 
 use anyhow::Context;
 
-use crate::{Handler, HandlerError, MutHandler};
+use crate::{Handler, HandlerError, MutHandler, RequestMeta};
 
-pub struct Printer {}
+pub struct Printer {
+    // if stdout is piped, the response is written as it is without any decoration.
+    terminal: bool,
+}
 
 impl Printer {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            terminal: std::io::stdout().is_terminal(),
+        }
+    }
+    pub fn is_terminal(&self) -> bool {
+        self.terminal
+    }
+    /// Ends the response with a newline so the shell prompt starts on a new line.
+    /// Nothing is written when stdout is piped. This is called by `on_finish`.
+    pub fn finish(&self) {
+        if self.terminal {
+            println!();
+        }
+    }
+}
+
+impl Default for Printer {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -19,11 +40,18 @@ impl Handler for Printer {
             .flush()
             .context("Failed to flush stdout")?)
     }
+    async fn on_finish(&self, _meta: &RequestMeta) -> Result<(), HandlerError> {
+        self.finish();
+        Ok(())
+    }
 }
 impl MutHandler for Printer {
     async fn handle_mut(&mut self, resp: &str) -> Result<(), HandlerError> {
         self.handle(resp).await
     }
+    async fn on_finish(&mut self, meta: &RequestMeta) -> Result<(), HandlerError> {
+        Handler::on_finish(self, meta).await
+    }
 }
 
 #[cfg(test)]
@@ -1,4 +1,4 @@
-use crate::{HandlerError, MutHandler};
+use crate::{HandlerError, MutHandler, RequestMeta};
 
 pub struct Recorder {
     buf: String,
@@ -17,11 +17,22 @@ impl Recorder {
     }
 }
 
+impl Default for Recorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl MutHandler for Recorder {
     async fn handle_mut(&mut self, resp: &str) -> Result<(), HandlerError> {
         self.buf.push_str(resp);
         Ok(())
     }
+    // the recorder can be reused for the next request.
+    async fn on_start(&mut self, _meta: &RequestMeta) -> Result<(), HandlerError> {
+        self.buf.clear();
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -1,9 +1,14 @@
+pub mod cache;
 pub mod clients;
+pub mod config;
 pub mod handlers;
+pub mod limiter;
 pub mod server;
 pub mod sse;
 pub mod tools;
 
+use anyhow::Context;
+
 pub trait GenerativeAIInterface {
     #[allow(async_fn_in_trait)]
     async fn request<H: Handler>(&self, prompt: Prompt, handler: &H) -> Result<(), AIError>;
@@ -19,6 +24,14 @@ pub trait GenerativeAIInterface {
 #[error(transparent)]
 pub struct AIError(anyhow::Error);
 
+/// Current unix time in seconds.
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
 #[macro_export]
 macro_rules! impl_from_error {
     ($($error:ty),*) => {
@@ -34,14 +47,132 @@ macro_rules! impl_from_error {
 
 impl_from_error!(AIError, HandlerError);
 
+/// Handles the chunks of a streamed response.
+///
+/// `GAIEngines` and `CachedAI` call `on_start` before the first chunk and `on_finish` or
+/// `on_error` after the last one, so handlers can reset or flush their state by themselves.
 pub trait Handler {
     #[allow(async_fn_in_trait)]
     async fn handle(&self, resp: &str) -> Result<(), HandlerError>;
+    /// Called by the clients for each chunk. Return `Flow::Stop` to abort the upstream stream.
+    #[allow(async_fn_in_trait)]
+    async fn handle_flow(&self, resp: &str) -> Result<Flow, HandlerError> {
+        self.handle(resp).await.map(|_| Flow::Continue)
+    }
+    #[allow(async_fn_in_trait)]
+    async fn on_start(&self, _meta: &RequestMeta) -> Result<(), HandlerError> {
+        Ok(())
+    }
+    /// Called when the response ends, including when it is stopped by `Flow::Stop`.
+    #[allow(async_fn_in_trait)]
+    async fn on_finish(&self, _meta: &RequestMeta) -> Result<(), HandlerError> {
+        Ok(())
+    }
+    #[allow(async_fn_in_trait)]
+    async fn on_error(&self, _meta: &RequestMeta, _error: &AIError) {}
 }
 
+/// `Handler` which can change its state. The hooks are called in the same way.
 pub trait MutHandler {
     #[allow(async_fn_in_trait)]
     async fn handle_mut(&mut self, resp: &str) -> Result<(), HandlerError>;
+    /// Called by the clients for each chunk. Return `Flow::Stop` to abort the upstream stream.
+    #[allow(async_fn_in_trait)]
+    async fn handle_mut_flow(&mut self, resp: &str) -> Result<Flow, HandlerError> {
+        self.handle_mut(resp).await.map(|_| Flow::Continue)
+    }
+    #[allow(async_fn_in_trait)]
+    async fn on_start(&mut self, _meta: &RequestMeta) -> Result<(), HandlerError> {
+        Ok(())
+    }
+    /// Called when the response ends, including when it is stopped by `Flow::Stop`.
+    #[allow(async_fn_in_trait)]
+    async fn on_finish(&mut self, _meta: &RequestMeta) -> Result<(), HandlerError> {
+        Ok(())
+    }
+    #[allow(async_fn_in_trait)]
+    async fn on_error(&mut self, _meta: &RequestMeta, _error: &AIError) {}
+}
+
+/// Tells the client whether to keep reading the response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Flow {
+    #[default]
+    Continue,
+    Stop,
+}
+
+impl Flow {
+    /// `Stop` if either of them is `Stop`.
+    pub fn and(self, other: Flow) -> Flow {
+        if self == Flow::Stop || other == Flow::Stop {
+            return Flow::Stop;
+        }
+        Flow::Continue
+    }
+}
+
+/// Describes the request passed to the lifecycle hooks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestMeta {
+    pub model: String,
+    // estimated by `limiter::estimate_tokens`.
+    pub prompt_tokens: u32,
+    // unix time in seconds
+    pub started_at: u64,
+    // true if the response is replayed from the cache.
+    pub cached: bool,
+}
+
+impl RequestMeta {
+    pub fn new(model: &str, prompt: &Prompt) -> Self {
+        Self {
+            model: model.to_string(),
+            prompt_tokens: limiter::estimate_tokens(prompt),
+            started_at: unix_now(),
+            cached: false,
+        }
+    }
+    pub fn cached(mut self, cached: bool) -> Self {
+        self.cached = cached;
+        self
+    }
+}
+
+/// Calls `on_finish` or `on_error` of the handler with the result of the request.
+pub async fn finish_request<H: Handler>(
+    handler: &H,
+    meta: &RequestMeta,
+    result: Result<(), AIError>,
+) -> Result<(), AIError> {
+    match result {
+        Ok(()) => Ok(handler
+            .on_finish(meta)
+            .await
+            .context("Failed to finish handler")?),
+        Err(e) => {
+            handler.on_error(meta, &e).await;
+            Err(e)
+        }
+    }
+}
+
+/// `finish_request` for `MutHandler`.
+pub async fn finish_request_mut<H: MutHandler>(
+    handler: &mut H,
+    meta: &RequestMeta,
+    result: Result<(), AIError>,
+) -> Result<(), AIError> {
+    match result {
+        Ok(()) => Ok(handler
+            .on_finish(meta)
+            .await
+            .context("Failed to finish handler")?),
+        Err(e) => {
+            handler.on_error(meta, &e).await;
+            Err(e)
+        }
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -85,6 +216,29 @@ impl Prompt {
             }
         }
     }
+    /// Replaces only the user messages.
+    pub fn replace_questions<F>(self, f: F) -> Self
+    where
+        F: Fn(String) -> String,
+    {
+        match self {
+            Prompt::Ask(ask) => Self::Ask(Ask {
+                question: f(ask.question),
+                role_play: ask.role_play,
+            }),
+            Prompt::Conversation(conversation) => {
+                let mut new_conversation = Conversation::new();
+                for message in conversation.messages {
+                    match message.role {
+                        Role::User => new_conversation.add_user_message(&f(message.content)),
+                        Role::AI => new_conversation.add_ai_message(&message.content),
+                        Role::RolePlay => new_conversation.add_role_play_message(&message.content),
+                    }
+                }
+                Self::Conversation(new_conversation)
+            }
+        }
+    }
     pub fn ask_with_role_play(question: &str, role_play: &str) -> Self {
         Self::Ask(Ask {
             question: question.to_string(),
@@ -102,16 +256,27 @@ impl Prompt {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct Message {
     role: Role,
     content: String,
 }
+impl Message {
+    pub fn role(&self) -> Role {
+        self.role
+    }
+    pub fn content(&self) -> &str {
+        self.content.as_str()
+    }
+}
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum Role {
+    #[serde(rename = "user")]
     User,
+    #[serde(rename = "ai")]
     AI,
+    #[serde(rename = "role_play")]
     RolePlay,
 }
 
@@ -141,11 +306,17 @@ impl Ask {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct Conversation {
     messages: Vec<Message>,
 }
 
+impl Default for Conversation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Conversation {
     pub fn new() -> Self {
         Self {
@@ -173,6 +344,38 @@ impl Conversation {
         });
     }
 
+    /// Sets the role play message at the head of the conversation, replacing the existing one.
+    pub fn set_role_play_message(&mut self, content: &str) {
+        let message = Message {
+            role: Role::RolePlay,
+            content: content.to_string(),
+        };
+        match self.messages.first_mut() {
+            Some(first) if first.role == Role::RolePlay => *first = message,
+            _ => self.messages.insert(0, message),
+        }
+    }
+
+    pub fn pop(&mut self) -> Option<Message> {
+        self.messages.pop()
+    }
+
+    pub fn last(&self) -> Option<&Message> {
+        self.messages.last()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Message> {
+        self.messages.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
     pub fn messages(self) -> Vec<Message> {
         self.messages
     }
@@ -195,6 +398,18 @@ mod tests {
         }
     }
     #[test]
+    fn prompt_can_replace_only_questions() {
+        let prompt = Prompt::ask_with_role_play("question", "role play");
+        let prompt = prompt.replace_questions(|q| q.to_uppercase());
+        match prompt {
+            Prompt::Ask(ask) => {
+                assert_eq!(ask.question, "QUESTION");
+                assert_eq!(ask.role_play, Some("role play".to_string()));
+            }
+            _ => panic!("Unexpected prompt type"),
+        }
+    }
+    #[test]
     fn conversation() {
         let mut conversation = Conversation::new();
         conversation.add_role_play_message("You are a teacher.");
@@ -210,4 +425,17 @@ mod tests {
         assert_eq!(messages[2].role, Role::AI);
         assert_eq!(messages[2].content, "The meaning of life is 42.");
     }
+    #[test]
+    fn role_play_message_is_replaced_at_head() {
+        let mut conversation = Conversation::new();
+        conversation.add_user_message("hi");
+        conversation.set_role_play_message("You are a teacher.");
+        conversation.set_role_play_message("You are a doctor.");
+
+        assert_eq!(conversation.len(), 2);
+        let messages = conversation.messages();
+        assert_eq!(messages[0].role, Role::RolePlay);
+        assert_eq!(messages[0].content, "You are a doctor.");
+        assert_eq!(messages[1].role, Role::User);
+    }
 }
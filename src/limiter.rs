@@ -0,0 +1,265 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{AIError, GenerativeAIInterface, Handler, MutHandler, Prompt};
+
+/// Budgets for requests sent to one provider. `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    pub max_concurrency: Option<usize>,
+    pub requests_per_minute: Option<u32>,
+    pub tokens_per_minute: Option<u32>,
+}
+
+impl Limits {
+    /// Fills unset values of `self` with values of `other`.
+    pub fn or(self, other: Limits) -> Self {
+        Self {
+            max_concurrency: self.max_concurrency.or(other.max_concurrency),
+            requests_per_minute: self.requests_per_minute.or(other.requests_per_minute),
+            tokens_per_minute: self.tokens_per_minute.or(other.tokens_per_minute),
+        }
+    }
+}
+
+/// Limits in-flight requests and the requests / tokens sent per minute.
+/// Cloning shares the same budgets, so one limiter can be used by many tasks.
+#[derive(Clone)]
+pub struct RateLimiter {
+    semaphore: Option<Arc<Semaphore>>,
+    limits: Limits,
+    window: Duration,
+    sent: Arc<Mutex<VecDeque<(Instant, u32)>>>,
+}
+
+/// Held while a request is in flight.
+pub struct Permit {
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: Limits) -> Self {
+        Self::with_window(limits, Duration::from_secs(60))
+    }
+    fn with_window(limits: Limits, window: Duration) -> Self {
+        Self {
+            semaphore: limits
+                .max_concurrency
+                .map(|n| Arc::new(Semaphore::new(n.max(1)))),
+            limits,
+            window,
+            sent: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+    /// Waits until a request of `tokens` tokens fits in every budget.
+    pub async fn acquire(&self, tokens: u32) -> Permit {
+        let permit = match &self.semaphore {
+            Some(semaphore) => semaphore.clone().acquire_owned().await.ok(),
+            None => None,
+        };
+        while let Some(wait) = self.try_consume(tokens) {
+            tokio::time::sleep(wait).await;
+        }
+        Permit { _permit: permit }
+    }
+    // returns how long to wait if the budgets are exhausted.
+    fn try_consume(&self, tokens: u32) -> Option<Duration> {
+        let now = Instant::now();
+        let mut sent = self.sent.lock().unwrap_or_else(|e| e.into_inner());
+        while sent
+            .front()
+            .is_some_and(|(at, _)| now.duration_since(*at) >= self.window)
+        {
+            sent.pop_front();
+        }
+        let requests_ok = self
+            .limits
+            .requests_per_minute
+            .is_none_or(|rpm| sent.len() < rpm as usize);
+        // a request larger than the whole budget is let through when nothing else was sent.
+        let used = sent.iter().map(|(_, t)| *t).sum::<u32>();
+        let tokens_ok = self
+            .limits
+            .tokens_per_minute
+            .is_none_or(|tpm| sent.is_empty() || used.saturating_add(tokens) <= tpm);
+        if requests_ok && tokens_ok {
+            sent.push_back((now, tokens));
+            return None;
+        }
+        let oldest = sent.front().map(|(at, _)| *at).unwrap_or(now);
+        Some((oldest + self.window).saturating_duration_since(now))
+    }
+}
+
+/// One `RateLimiter` per provider, shared by every client of the provider.
+#[derive(Clone, Default)]
+pub struct RateLimiters {
+    limiters: Arc<Mutex<HashMap<String, RateLimiter>>>,
+    limits: HashMap<String, Limits>,
+    default: Limits,
+}
+
+impl RateLimiters {
+    pub fn new(default: Limits) -> Self {
+        Self {
+            default,
+            ..Default::default()
+        }
+    }
+    pub fn provider_limits(mut self, provider: &str, limits: Limits) -> Self {
+        self.limits.insert(provider.to_string(), limits);
+        self
+    }
+    pub fn get(&self, provider: &str) -> RateLimiter {
+        let mut limiters = self.limiters.lock().unwrap_or_else(|e| e.into_inner());
+        limiters
+            .entry(provider.to_string())
+            .or_insert_with(|| {
+                let limits = self.limits.get(provider).copied().unwrap_or(self.default);
+                RateLimiter::new(limits)
+            })
+            .clone()
+    }
+}
+
+/// Wraps any `GenerativeAIInterface` so every request waits for the `RateLimiter`.
+pub struct LimitedAI<AI: GenerativeAIInterface> {
+    inner: AI,
+    limiter: RateLimiter,
+}
+
+impl<AI: GenerativeAIInterface> LimitedAI<AI> {
+    pub fn new(inner: AI, limiter: RateLimiter) -> Self {
+        Self { inner, limiter }
+    }
+    pub fn inner(&self) -> &AI {
+        &self.inner
+    }
+}
+
+impl<AI: GenerativeAIInterface> GenerativeAIInterface for LimitedAI<AI> {
+    async fn request<H: Handler>(&self, prompt: Prompt, handler: &H) -> Result<(), AIError> {
+        let _permit = self.limiter.acquire(estimate_tokens(&prompt)).await;
+        self.inner.request(prompt, handler).await
+    }
+    async fn request_mut<H: MutHandler>(
+        &self,
+        prompt: Prompt,
+        handler: &mut H,
+    ) -> Result<(), AIError> {
+        let _permit = self.limiter.acquire(estimate_tokens(&prompt)).await;
+        self.inner.request_mut(prompt, handler).await
+    }
+}
+
+/// Rough token count of the prompt. About 4 characters are one token for most models.
+pub fn estimate_tokens(prompt: &Prompt) -> u32 {
+    let chars = prompt
+        .clone()
+        .messages()
+        .iter()
+        .map(|m| m.content.chars().count())
+        .sum::<usize>();
+    chars.div_ceil(4) as u32
+}
+
+/// `estimate_tokens` of a text.
+pub fn estimate_text_tokens(text: &str) -> u32 {
+    text.chars().count().div_ceil(4) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn max_concurrency_bounds_in_flight_requests() {
+        let limiter = RateLimiter::new(Limits {
+            max_concurrency: Some(2),
+            ..Default::default()
+        });
+        let in_flight = Arc::new(AtomicUsize::new(0));
+        let max = Arc::new(AtomicUsize::new(0));
+
+        let tasks = (0..6).map(|_| {
+            let limiter = limiter.clone();
+            let in_flight = in_flight.clone();
+            let max = max.clone();
+            async move {
+                let _permit = limiter.acquire(1).await;
+                let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                max.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(5)).await;
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+            }
+        });
+        futures::future::join_all(tasks).await;
+
+        assert_eq!(max.load(Ordering::SeqCst), 2);
+    }
+    #[test]
+    fn budgets_wait_until_window_passes() {
+        let sut = RateLimiter::with_window(
+            Limits {
+                requests_per_minute: Some(2),
+                tokens_per_minute: Some(100),
+                ..Default::default()
+            },
+            Duration::from_secs(60),
+        );
+
+        assert!(sut.try_consume(10).is_none());
+        assert!(sut.try_consume(10).is_none());
+        assert!(sut.try_consume(10).is_some());
+
+        let sut = RateLimiter::with_window(
+            Limits {
+                tokens_per_minute: Some(100),
+                ..Default::default()
+            },
+            Duration::from_secs(60),
+        );
+        assert!(sut.try_consume(150).is_none());
+        assert!(sut.try_consume(1).is_some());
+    }
+    #[tokio::test]
+    async fn acquire_resumes_after_window() {
+        let sut = RateLimiter::with_window(
+            Limits {
+                requests_per_minute: Some(1),
+                ..Default::default()
+            },
+            Duration::from_millis(20),
+        );
+        let start = Instant::now();
+        sut.acquire(1).await;
+        sut.acquire(1).await;
+
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+    #[test]
+    fn limiters_are_shared_per_provider() {
+        let limiters = RateLimiters::new(Limits::default()).provider_limits(
+            "openai",
+            Limits {
+                requests_per_minute: Some(1),
+                ..Default::default()
+            },
+        );
+
+        assert!(limiters.get("openai").try_consume(1).is_none());
+        assert!(limiters.get("openai").try_consume(1).is_some());
+        assert!(limiters.get("gemini").try_consume(1).is_none());
+    }
+}
@@ -1,18 +1,58 @@
 use anyhow::Context;
+use std::{collections::HashMap, path::Path, sync::OnceLock, time::Duration};
+
 use cai::{
-    AIError, Conversation, Prompt,
-    clients::gai::{GAIEngines, engine_to_default_key_from_env},
-    handlers::printer::Printer,
+    AIError, Conversation, GenerativeAIInterface, Prompt,
+    cache::{CacheStore, CachedAI},
+    clients::gai::{ENGINES, GAIEngines, engine_to_default_key_from_env, engine_to_provider},
+    config::Config,
+    handlers::{
+        combinators::DynMutHandler, extractor::CodeExtractor, json::JsonReporter,
+        markdown::MarkdownPrinter, printer::Printer, recorder::Recorder,
+    },
+    limiter::{LimitedAI, Limits, RateLimiters, estimate_tokens},
     server::AIServer,
-    tools::translator::{TargetLang, TranslateRequests, translate},
+    tools::{
+        annotations::{to_github, to_rdjson, to_sarif},
+        bilingual::{to_html, to_json, to_tsv, to_xliff2, to_xliff12},
+        chat::{Chat, ChatCommand, HELP},
+        commit::{
+            commit_editmsg_path, commit_message_prompt, git_log, pr_description_prompt, strip_fence,
+        },
+        glossary::Glossary,
+        i18n::{Resource, ResourceFormat, translate_resource},
+        input::{
+            STDIN_PATH, attach_context, collect_files, confirm, read_path_or_stdin,
+            read_piped_stdin,
+        },
+        lang::Lang,
+        memory::TranslationMemory,
+        patch::{apply_patch, request_fix, to_diff},
+        review::{
+            Finding, Focus, ReviewChunk, ReviewOptions, ReviewReport, Rubric, Severity,
+            cross_file_observations, git_diff, load_guidelines, parse_diff, review_diff,
+            split_by_budget,
+        },
+        session::{Session, SessionStore, Turn},
+        subtitle::{Subtitle, SubtitleFormat, translate_subtitle},
+        translator::{
+            TranslateRequests, TranslatedDocument, retry_failed, translate, translate_markdown,
+            verify_translations,
+        },
+    },
+    unix_now,
 };
 use clap::{Parser, Subcommand};
+use rustyline::error::ReadlineError;
+
+const DEFAULT_ENGINE: &str = "gpt4-o-mini";
 
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse();
     if let Err(e) = cli.run().await {
         eprintln!("{}", e);
+        std::process::exit(1);
     }
 }
 
@@ -20,6 +60,36 @@ async fn main() {
 struct Cli {
     #[clap(subcommand)]
     sub: SubCommand,
+    /// Print responses as raw markdown instead of rendering them.
+    #[clap(long = "plain", global = true)]
+    plain: bool,
+    /// Reuse responses stored in the on-disk cache and store new ones.
+    #[clap(long = "cache", global = true)]
+    cache: bool,
+    /// Disable the cache even if it is enabled by `--cache` or `CAI_CACHE`.
+    #[clap(long = "no-cache", global = true)]
+    no_cache: bool,
+    /// Seconds after which cached responses are ignored.
+    #[clap(long = "cache-ttl", global = true)]
+    cache_ttl: Option<u64>,
+    /// Replay cached responses with this delay in milliseconds between chunks.
+    #[clap(long = "replay-delay", global = true)]
+    replay_delay: Option<u64>,
+    /// Maximum number of requests in flight per provider.
+    #[clap(long = "max-concurrency", global = true)]
+    max_concurrency: Option<usize>,
+    /// Maximum number of requests per minute per provider.
+    #[clap(long = "rpm", global = true)]
+    requests_per_minute: Option<u32>,
+    /// Maximum number of estimated tokens per minute per provider.
+    #[clap(long = "tpm", global = true)]
+    tokens_per_minute: Option<u32>,
+    /// Print a JSON object at the end, or JSON lines while the response is streamed.
+    #[clap(long = "output", global = true, value_enum, default_value = "text")]
+    output: OutputFormat,
+    // built on first use, so every client of a provider shares the same budget.
+    #[clap(skip)]
+    limiters: OnceLock<RateLimiters>,
 }
 impl Cli {
     async fn run(&self) -> Result<(), AIError> {
@@ -28,27 +98,45 @@ impl Cli {
                 question,
                 engine,
                 role_play,
+                extract_code,
+                overwrite,
+                max_blocks,
             } => {
-                self.ask(engine.to_string(), question.to_string(), role_play.clone())
-                    .await
-            }
-            SubCommand::Translate {
-                source,
-                target_lang,
-                engine,
-                separate_per_limit,
-            } => {
-                self.translate(
+                self.ask(
                     engine.to_string(),
-                    source.to_string(),
-                    target_lang.to_string(),
-                    *separate_per_limit,
+                    question.to_string(),
+                    role_play.clone(),
+                    extract_code.clone(),
+                    *overwrite,
+                    *max_blocks,
                 )
                 .await
             }
-            SubCommand::CodeReview { engine, path } => {
-                self.code_review(engine.to_string(), path.to_string()).await
+            SubCommand::Translate { engine, translate } => self.translate(engine, translate).await,
+            SubCommand::CodeReview {
+                engine,
+                paths,
+                review,
+            } => {
+                // a single file or stdin gets the free-form review.
+                if let [path] = paths.as_slice()
+                    && !review.is_structured()
+                    && (path == STDIN_PATH || std::path::Path::new(path).is_file())
+                {
+                    return self.code_review(engine, path, review).await;
+                }
+                self.review(engine, paths, review).await
             }
+            SubCommand::CommitMsg {
+                engine,
+                write,
+                token_budget,
+            } => self.commit_msg(engine, *write, *token_budget).await,
+            SubCommand::PrDesc {
+                engine,
+                base,
+                token_budget,
+            } => self.pr_desc(engine, base, *token_budget).await,
             SubCommand::Conversation {
                 engine,
                 conversation,
@@ -56,78 +144,577 @@ impl Cli {
                 self.conversation(engine.to_string(), conversation.to_string())
                     .await
             }
+            SubCommand::Chat {
+                engine,
+                system,
+                session,
+            } => {
+                self.chat(engine.clone(), system.clone(), session.clone())
+                    .await
+            }
+            SubCommand::Sessions { sub } => self.sessions(sub).await,
             SubCommand::Server { port } => self.server(*port).await,
+            SubCommand::Cache { sub } => self.cache(sub),
+            SubCommand::Memory { sub } => self.memory(sub),
+        }
+    }
+
+    /// Streams the reply to stdout, rendering markdown unless `--plain` or `--output` is set.
+    async fn print_reply<AI: GenerativeAIInterface>(
+        &self,
+        engine: &str,
+        ai: &AI,
+        prompt: Prompt,
+    ) -> Result<(), AIError> {
+        if let Some(mut reporter) = self.reporter(engine) {
+            ai.request_mut(prompt, &mut reporter).await?;
+            return self.print_report(reporter);
+        }
+        let mut printer = MarkdownPrinter::new().render(!self.plain);
+        ai.request_mut(prompt, &mut printer).await?;
+        Ok(())
+    }
+    /// Returns a reporter if `--output` is json or jsonl.
+    fn reporter(&self, engine: &str) -> Option<JsonReporter> {
+        match self.output {
+            OutputFormat::Text => None,
+            output => Some(JsonReporter::new(engine).jsonl(output == OutputFormat::Jsonl)),
+        }
+    }
+    /// Prints the report for `--output json`. JSON lines are already printed while streaming.
+    fn print_report(&self, reporter: JsonReporter) -> Result<(), AIError> {
+        if self.output == OutputFormat::Json {
+            print_json(&reporter.into_report(), true)?;
+        }
+        Ok(())
+    }
+    /// Prints `value` in the format of `--output`. `text` is used for `--output text`.
+    fn print_value<T: serde::Serialize>(
+        &self,
+        value: &T,
+        text: impl Fn(&T) -> String,
+    ) -> Result<(), AIError> {
+        match self.output {
+            OutputFormat::Text => println!("{}", text(value)),
+            OutputFormat::Json => print_json(value, true)?,
+            OutputFormat::Jsonl => print_json(value, false)?,
         }
+        Ok(())
+    }
+    /// Prints `values` as an array for json, and one line per value for jsonl.
+    fn print_values<T: serde::Serialize>(
+        &self,
+        values: &[T],
+        text: impl Fn(&T) -> String,
+    ) -> Result<(), AIError> {
+        match self.output {
+            OutputFormat::Text => values.iter().for_each(|v| println!("{}", text(v))),
+            OutputFormat::Json => print_json(&values, true)?,
+            OutputFormat::Jsonl => {
+                for value in values {
+                    print_json(value, false)?;
+                }
+            }
+        }
+        Ok(())
+    }
+    fn ai(&self, engine: &str) -> CachedAI<LimitedAI<GAIEngines>> {
+        let key = engine_to_default_key_from_env(engine);
+        let ai = GAIEngines::from_str(engine, key);
+        let model = ai.model();
+        let limiter = self.rate_limiters().get(engine_to_provider(engine));
+        CachedAI::new(LimitedAI::new(ai, limiter), engine)
+            .model(model)
+            .store(self.cache_store())
+            .replay_delay(self.replay_delay.map(Duration::from_millis))
+    }
+    fn rate_limiters(&self) -> RateLimiters {
+        self.limiters
+            .get_or_init(|| {
+                let config = Config::load().unwrap_or_else(|e| {
+                    eprintln!("{}", e);
+                    Config::default()
+                });
+                config.rate_limiters(Limits {
+                    max_concurrency: self.max_concurrency,
+                    requests_per_minute: self.requests_per_minute,
+                    tokens_per_minute: self.tokens_per_minute,
+                })
+            })
+            .clone()
+    }
+    fn cache_store(&self) -> Option<CacheStore> {
+        let enabled = self.cache || std::env::var("CAI_CACHE").is_ok_and(|v| v == "1");
+        if !enabled || self.no_cache {
+            return None;
+        }
+        Some(
+            CacheStore::new(CacheStore::default_dir()).ttl(self.cache_ttl.map(Duration::from_secs)),
+        )
+    }
+    fn cache(&self, sub: &CacheCommand) -> Result<(), AIError> {
+        let store =
+            CacheStore::new(CacheStore::default_dir()).ttl(self.cache_ttl.map(Duration::from_secs));
+        match sub {
+            CacheCommand::Clear => {
+                let removed = store.clear().context("Failed to clear cache")?;
+                let value = serde_json::json!({ "removed": removed, "dir": store.dir() });
+                self.print_value(&value, |_| {
+                    format!("removed {} entries from {}", removed, store.dir().display())
+                })?;
+            }
+            CacheCommand::Stats => {
+                let stats = store.stats().context("Failed to read cache")?;
+                let mut value = serde_json::to_value(stats).context("Failed to serialize stats")?;
+                value["dir"] = serde_json::json!(store.dir());
+                self.print_value(&value, |_| {
+                    format!("dir: {}\n{}", store.dir().display(), stats)
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    fn memory(&self, sub: &MemoryCommand) -> Result<(), AIError> {
+        let mut memory = TranslationMemory::open(TranslationMemory::default_path())
+            .map_err(anyhow::Error::from)?;
+        match sub {
+            MemoryCommand::Import { path } => {
+                let tmx = read_path_or_stdin(path).context("Failed to read TMX")?;
+                let added = memory.import_tmx(&tmx).map_err(anyhow::Error::from)?;
+                memory.save().map_err(anyhow::Error::from)?;
+                let value = serde_json::json!({ "added": added, "path": memory.path() });
+                self.print_value(&value, |_| {
+                    format!("added {} segments to {}", added, memory.path().display())
+                })?;
+            }
+            MemoryCommand::Export { out } => {
+                self.write_or_print(out.as_deref(), &memory.to_tmx())?;
+            }
+        }
+        Ok(())
     }
 
     async fn conversation(&self, engine: String, conversation: String) -> Result<(), AIError> {
-        let key = engine_to_default_key_from_env(engine.as_str());
-        let ai = GAIEngines::from_str(&engine, key);
+        let ai = self.ai(&engine);
 
         let conversation: ConversationInput =
             serde_json::from_str(conversation.as_str()).context("Failed to parse conversation")?;
 
-        let mut printer = Printer::new();
-
         let prompt = Prompt::Conversation(conversation.into());
-        ai.run_mut(&mut printer, prompt).await?;
+        if let Some(mut reporter) = self.reporter(&engine) {
+            ai.request_mut(prompt, &mut reporter).await?;
+            return self.print_report(reporter);
+        }
+        let mut printer = Printer::new();
+        ai.request_mut(prompt, &mut printer).await?;
 
         Ok(())
     }
-    async fn code_review(&self, engine: String, path: String) -> Result<(), AIError> {
-        let key = engine_to_default_key_from_env(engine.as_str());
-        let ai = GAIEngines::from_str(&engine, key);
+    async fn code_review(
+        &self,
+        engine: &str,
+        path: &str,
+        args: &ReviewArgs,
+    ) -> Result<(), AIError> {
+        let ai = self.ai(engine);
 
-        let file_contents =
-            std::fs::read_to_string(path.as_str()).context("Failed to read file")?;
+        let file_contents = read_path_or_stdin(path).context("Failed to read file")?;
 
-        let prompt = Prompt::ask(
-            format!(
-                "このファイルの内容をレビューしてください。\n{}",
-                file_contents
+        let prompt = args.options()?.file_prompt(&file_contents);
+        self.print_reply(engine, &ai, prompt).await
+    }
+    /// Reviews a diff, or the files at `paths`, and prints the report in `--format`.
+    async fn review(
+        &self,
+        engine: &str,
+        paths: &[String],
+        args: &ReviewArgs,
+    ) -> Result<(), AIError> {
+        let ai = self.ai(engine);
+        let chunks = if args.diff.is_some() || args.staged {
+            let diff = git_diff(args.diff.as_deref(), args.staged, args.context)
+                .context("Failed to get diff")?;
+            split_by_budget(parse_diff(&diff), args.token_budget)
+        } else if paths == [STDIN_PATH] {
+            let content = read_path_or_stdin(STDIN_PATH).context("Failed to read stdin")?;
+            ReviewChunk::split_file("stdin", &content, args.token_budget)
+        } else {
+            let mut chunks = vec![];
+            for file in collect_files(paths).map_err(anyhow::Error::from)? {
+                let path = file.to_string_lossy();
+                let Ok(content) = std::fs::read_to_string(&file) else {
+                    eprintln!("skip {}: not UTF-8 text", path);
+                    continue;
+                };
+                chunks.extend(ReviewChunk::split_file(&path, &content, args.token_budget));
+            }
+            chunks
+        };
+        if chunks.is_empty() {
+            eprintln!("no changes to review");
+            return Ok(());
+        }
+        // the files are read before the review, so changes made during it are detected.
+        let snapshots = if args.fix {
+            chunks
+                .iter()
+                .filter_map(|chunk| {
+                    let content = std::fs::read_to_string(&chunk.path).ok()?;
+                    Some((chunk.path.clone(), content))
+                })
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        let options = args.options()?;
+        let (findings, failures) = review_diff(&ai, &chunks, &options).await?;
+        let mut report = ReviewReport::new(&chunks, findings).failures(failures);
+        if report.files.len() > 1 {
+            let observations =
+                cross_file_observations(&ai, &chunks, &report.findings, &options).await?;
+            report = report.observations(observations);
+        }
+        let findings = &report.findings;
+        match args.format {
+            ReviewFormat::Text => {
+                self.print_value(&report, |report| report.to_string().trim_end().to_string())?
+            }
+            ReviewFormat::Sarif => print_json(&to_sarif(findings), true)?,
+            ReviewFormat::Rdjson => print_json(&to_rdjson(findings), true)?,
+            ReviewFormat::Github => print!("{}", to_github(findings)),
+        }
+        if args.fix {
+            self.fix(&ai, findings, &snapshots, &options).await?;
+        }
+        if let Some(threshold) = args.fail_on {
+            let failed = findings.iter().filter(|f| f.severity <= threshold).count();
+            if failed > 0 {
+                return Err(anyhow::anyhow!(
+                    "{} findings are {} or more severe",
+                    failed,
+                    threshold.to_str()
+                )
+                .into());
+            }
+        }
+        if !report.failures.is_empty() {
+            return Err(anyhow::anyhow!(
+                "{} of {} chunks failed to review",
+                report.failures.len(),
+                chunks.len()
             )
-            .as_str(),
+            .into());
+        }
+        Ok(())
+    }
+    async fn commit_msg(
+        &self,
+        engine: &str,
+        write: bool,
+        token_budget: u32,
+    ) -> Result<(), AIError> {
+        let ai = self.ai(engine);
+        let diff = git_diff(None, true, 3).context("Failed to get diff")?;
+        let chunks = split_by_budget(parse_diff(&diff), token_budget);
+        if chunks.is_empty() {
+            return Err(anyhow::anyhow!("No staged changes").into());
+        }
+        let prompt = commit_message_prompt(&ai, &chunks, token_budget).await?;
+        if !write {
+            return self.print_reply(engine, &ai, prompt).await;
+        }
+        let mut recorder = Recorder::new();
+        ai.request_mut(prompt, &mut recorder).await?;
+        let path = commit_editmsg_path().context("Failed to find .git")?;
+        std::fs::write(&path, strip_fence(&recorder.take()))
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        eprintln!(
+            "written to {0}. commit with `git commit -eF {0}`",
+            path.display()
         );
-        let mut printer = Printer::new();
-        ai.run_mut(&mut printer, prompt).await
+        Ok(())
     }
-    async fn translate(
+    async fn pr_desc(&self, engine: &str, base: &str, token_budget: u32) -> Result<(), AIError> {
+        let ai = self.ai(engine);
+        let log = git_log(base).context("Failed to get log")?;
+        let diff =
+            git_diff(Some(&format!("{}...HEAD", base)), false, 3).context("Failed to get diff")?;
+        let chunks = split_by_budget(parse_diff(&diff), token_budget);
+        if chunks.is_empty() {
+            return Err(anyhow::anyhow!("No changes from {}", base).into());
+        }
+        let prompt = pr_description_prompt(&ai, &log, &chunks, token_budget).await?;
+        self.print_reply(engine, &ai, prompt).await
+    }
+    /// Asks a patch for each finding and writes the accepted ones. A file is not written if it
+    /// differs from its snapshot taken before the review.
+    async fn fix<AI: GenerativeAIInterface>(
         &self,
-        engine: String,
-        source: String,
-        target_lang: String,
-        separate_per_limit: usize,
+        ai: &AI,
+        findings: &[Finding],
+        snapshots: &HashMap<String, String>,
+        options: &ReviewOptions,
     ) -> Result<(), AIError> {
-        let key = engine_to_default_key_from_env(engine.as_str());
-        let ai = GAIEngines::from_str(&engine, key);
-        let separators = vec!['.', '!', '?'];
-        if target_lang == "ja" {
-            let request = TranslateRequests::new(source, TargetLang::Japanese)
-                .separate_per_limit(separate_per_limit)
-                .separators(separators);
-            let response = translate(ai, request).await?;
-            for res in response {
-                println!("{}", res);
+        let mut paths = findings.iter().map(|f| f.path.as_str()).collect::<Vec<_>>();
+        paths.dedup();
+        for path in paths {
+            let Some(original) = snapshots.get(path) else {
+                eprintln!("skip {}: the file can not be read", path);
+                continue;
+            };
+            let mut content = original.clone();
+            for finding in findings.iter().filter(|f| f.path == path) {
+                let hunks = request_fix(ai, finding, &content, options).await?;
+                if hunks.is_empty() {
+                    eprintln!("no patch for {}", finding);
+                    continue;
+                }
+                let patched = match apply_patch(&content, &hunks) {
+                    Ok(patched) => patched,
+                    Err(e) => {
+                        eprintln!("skip the patch for {}: {}", finding, e);
+                        continue;
+                    }
+                };
+                eprintln!("\n{}\n{}", finding, to_diff(path, &hunks));
+                if confirm("Apply this patch?") {
+                    content = patched;
+                }
+            }
+            if content == *original {
+                continue;
+            }
+            let current = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read file: {}", path))?;
+            if current != *original {
+                eprintln!(
+                    "{} changed since the review started, so the patches are not written",
+                    path
+                );
+                continue;
             }
+            std::fs::write(path, content)
+                .with_context(|| format!("Failed to write file: {}", path))?;
+            eprintln!("fixed {}", path);
+        }
+        Ok(())
+    }
+    async fn translate(&self, engine: &str, args: &TranslateArgs) -> Result<(), AIError> {
+        let ai = self.ai(engine);
+        let format = args
+            .input_format
+            .unwrap_or_else(|| TranslateFormat::from_path(&args.source));
+        let source = if args.source == STDIN_PATH || Path::new(&args.source).is_file() {
+            read_path_or_stdin(&args.source).context("Failed to read source")?
         } else {
-            let request = TranslateRequests::new(source, TargetLang::English)
-                .separate_per_limit(separate_per_limit)
-                .separators(separators);
-            let response = translate(ai, request).await?;
-            for res in response {
-                println!("{}", res);
+            args.source.clone()
+        };
+        let source_lang = args.source_lang.clone();
+        let glossary = args
+            .glossary
+            .as_deref()
+            .map(Glossary::load)
+            .transpose()
+            .map_err(anyhow::Error::from)?;
+        let segmented = glossary.is_some()
+            || args.memory
+            || args.verify
+            || args.format != BilingualFormat::Text;
+        if segmented && format != TranslateFormat::Text {
+            return Err(anyhow::anyhow!(
+                "--glossary, --memory, --verify and --format are supported only for text input"
+            )
+            .into());
+        }
+        if let Some(resource_format) = format.resource() {
+            let resource =
+                Resource::parse(resource_format, &source).map_err(anyhow::Error::from)?;
+            let existing = match &args.out {
+                Some(out) if args.missing_only && Path::new(out).is_file() => {
+                    let content = std::fs::read_to_string(out)
+                        .with_context(|| format!("Failed to read {}", out))?;
+                    Some(Resource::parse(resource_format, &content).map_err(anyhow::Error::from)?)
+                }
+                _ => None,
+            };
+            let file = translate_resource(
+                &ai,
+                &resource,
+                existing.as_ref(),
+                source_lang,
+                &args.target_lang,
+            )
+            .await?;
+            for key in &file.failed {
+                eprintln!("skip {}: not translated", key);
             }
+            eprintln!(
+                "translated {} messages, kept {}",
+                file.translated, file.kept
+            );
+            self.write_or_print(args.out.as_deref(), &file.content)?;
+            if !file.failed.is_empty() {
+                return Err(anyhow::anyhow!(
+                    "{} of {} messages failed to translate",
+                    file.failed.len(),
+                    file.failed.len() + file.translated
+                )
+                .into());
+            }
+            return Ok(());
+        }
+        if let Some(subtitle_format) = format.subtitle() {
+            let subtitle =
+                Subtitle::parse(subtitle_format, &source).map_err(anyhow::Error::from)?;
+            let translated = translate_subtitle(
+                &ai,
+                &subtitle,
+                source_lang,
+                &args.target_lang,
+                args.max_line_chars,
+            )
+            .await?;
+            return self.write_document(args.out.as_deref(), &translated);
+        }
+        if format == TranslateFormat::Markdown {
+            let translated =
+                translate_markdown(&ai, &source, source_lang, &args.target_lang).await?;
+            return self.write_document(args.out.as_deref(), &translated);
+        }
+        let separators = vec!['.', '!', '?'];
+        let memory = args
+            .memory
+            .then(|| TranslationMemory::open(TranslationMemory::default_path()))
+            .transpose()
+            .map_err(anyhow::Error::from)?;
+        let mut request = TranslateRequests::new(source, args.target_lang.clone())
+            .source_lang(source_lang)
+            .separate_per_limit(args.separate_per_limit)
+            .separators(separators)
+            .glossary(glossary)
+            .retry_violations(args.glossary_retry)
+            .context(args.context)
+            .memory(memory, engine)
+            .fuzzy_threshold(args.fuzzy_threshold);
+        let mut response = translate(&ai, &request).await?;
+        for _ in 0..args.retries {
+            let failed = response.iter().filter(|res| res.error().is_some()).count();
+            if failed == 0 {
+                break;
+            }
+            eprintln!("retrying {} failed segments", failed);
+            retry_failed(&ai, &request, &mut response).await;
+        }
+        let reused = response.iter().filter(|res| res.from_memory()).count();
+        if reused > 0 {
+            eprintln!("reused {} segments from the translation memory", reused);
+        }
+        if args.verify {
+            let judge = self.ai(args.judge.as_deref().unwrap_or(engine));
+            let unverified = verify_translations(&ai, &judge, &mut response, args.min_score).await;
+            if unverified > 0 {
+                eprintln!("could not verify {} segments", unverified);
+            }
+        }
+        // after the verification, so flagged segments are not remembered.
+        request
+            .save_memory(&response)
+            .map_err(anyhow::Error::from)?;
+        match args.format {
+            // without `--out`, `--output json` prints the results as objects.
+            BilingualFormat::Text if args.out.is_none() => {
+                self.print_values(&response, |res| res.to_string())?
+            }
+            BilingualFormat::Text => {
+                let text = response
+                    .iter()
+                    .map(|res| format!("{}\n", res))
+                    .collect::<String>();
+                self.write_or_print(args.out.as_deref(), &text)?
+            }
+            BilingualFormat::Tsv => self.write_or_print(args.out.as_deref(), &to_tsv(&response))?,
+            BilingualFormat::Json => {
+                let json = serde_json::to_string_pretty(&to_json(&response))
+                    .context("Failed to serialize translations")?;
+                self.write_or_print(args.out.as_deref(), &json)?
+            }
+            BilingualFormat::Xliff12 => {
+                self.write_or_print(args.out.as_deref(), &to_xliff12(&response))?
+            }
+            BilingualFormat::Xliff2 => {
+                self.write_or_print(args.out.as_deref(), &to_xliff2(&response))?
+            }
+            BilingualFormat::Html => {
+                self.write_or_print(args.out.as_deref(), &to_html(&response))?
+            }
+        }
+        for res in &response {
+            for violation in res.violations() {
+                eprintln!("glossary: {} in {:?}", violation, res.source());
+            }
+        }
+        let flagged = response
+            .iter()
+            .filter(|res| res.quality().is_some_and(|quality| quality.flagged))
+            .count();
+        if flagged > 0 {
+            eprintln!(
+                "{} of {} segments need review: the back-translation scored below {}",
+                flagged,
+                response.len(),
+                args.min_score
+            );
+        }
+        let failed = response.iter().filter(|res| res.error().is_some()).count();
+        if failed > 0 {
+            return Err(anyhow::anyhow!(
+                "{} of {} segments failed to translate",
+                failed,
+                response.len()
+            )
+            .into());
         }
         Ok(())
     }
+    /// Writes a translated document like `write_or_print`, and fails if some texts could not be
+    /// translated, after reporting them.
+    fn write_document(&self, out: Option<&str>, doc: &TranslatedDocument) -> Result<(), AIError> {
+        self.write_or_print(out, &doc.content)?;
+        for text in &doc.untranslated {
+            eprintln!("untranslated: {}", text.replace('\n', " "));
+        }
+        if !doc.untranslated.is_empty() {
+            return Err(anyhow::anyhow!(
+                "{} of {} segments failed to translate",
+                doc.untranslated.len(),
+                doc.segments
+            )
+            .into());
+        }
+        Ok(())
+    }
+    /// Writes a translated file to `out`, or prints it.
+    fn write_or_print(&self, out: Option<&str>, content: &str) -> Result<(), AIError> {
+        let Some(out) = out else {
+            return self.print_value(&content, |content| {
+                content.trim_end_matches('\n').to_string()
+            });
+        };
+        std::fs::write(out, content).with_context(|| format!("Failed to write {}", out))?;
+        eprintln!("written to {}", out);
+        Ok(())
+    }
     async fn ask(
         &self,
         engine: String,
         question: String,
         role_play: Option<String>,
+        extract_code: Option<String>,
+        overwrite: bool,
+        max_blocks: Option<usize>,
     ) -> Result<(), AIError> {
-        let key = engine_to_default_key_from_env(engine.as_str());
-        let ai = GAIEngines::from_str(&engine, key);
+        let ai = self.ai(&engine);
         let prompt = if let Some(role_play) = role_play {
             Prompt::ask_with_role_play(question.as_str(), role_play.as_str())
                 .replace_messages(replace_remote_path_to_content)
@@ -137,11 +724,252 @@ impl Cli {
                 .replace_messages(replace_remote_path_to_content)
                 .replace_messages(replace_paths_to_content)
         };
-        let mut printer = Printer::new();
-        ai.run_mut(&mut printer, prompt).await
+        // piped input is attached after the replacement, so braces in it are kept as they are.
+        let prompt = match read_piped_stdin().context("Failed to read stdin")? {
+            Some(stdin) => prompt.replace_questions(|q| attach_context(&q, "stdin", &stdin)),
+            None => prompt,
+        };
+        // the handlers are chosen by the flags.
+        let mut printer = MarkdownPrinter::new().render(!self.plain);
+        let mut reporter = self.reporter(&engine);
+        let mut extractor = extract_code.map(|dir| CodeExtractor::new(dir).max_blocks(max_blocks));
+        {
+            let mut pipeline: Vec<Box<dyn DynMutHandler>> = vec![];
+            match reporter.as_mut() {
+                Some(reporter) => pipeline.push(Box::new(reporter)),
+                None => pipeline.push(Box::new(&mut printer)),
+            }
+            if let Some(extractor) = extractor.as_mut() {
+                pipeline.push(Box::new(extractor));
+            }
+            ai.request_mut(prompt, &mut pipeline).await?;
+        }
+        if let Some(reporter) = reporter {
+            self.print_report(reporter)?;
+        }
+        if let Some(mut extractor) = extractor {
+            let written = extractor
+                .finish(|path| overwrite || confirm(&format!("overwrite {}?", path.display())))
+                .context("Failed to extract code")?;
+            for path in written {
+                eprintln!("wrote {}", path.display());
+            }
+        }
+        Ok(())
+    }
+    async fn chat(
+        &self,
+        engine: Option<String>,
+        system: Option<String>,
+        session: Option<String>,
+    ) -> Result<(), AIError> {
+        if self.output != OutputFormat::Text {
+            return Err(anyhow::anyhow!("--output is not supported by interactive chat").into());
+        }
+        let store = SessionStore::new(SessionStore::default_dir());
+        let mut session = match session {
+            Some(name) => Some(match store.load(&name).context("Failed to load session")? {
+                Some(session) => session,
+                None => Session::new(&name, engine.as_deref().unwrap_or(DEFAULT_ENGINE)),
+            }),
+            None => None,
+        };
+        let mut engine = engine
+            .or_else(|| session.as_ref().map(|s| s.engine.clone()))
+            .unwrap_or_else(|| DEFAULT_ENGINE.to_string());
+        let mut ai = self.ai(&engine);
+        let mut chat = match &session {
+            Some(session) => Chat::with_conversation(session.conversation.clone()),
+            None => Chat::new(None),
+        };
+        if let Some(system) = system {
+            chat.set_system(&system);
+        }
+        let mut editor =
+            rustyline::DefaultEditor::new().context("Failed to start the line editor")?;
+        let history = Config::data_dir().join("chat_history");
+        let _ = editor.load_history(&history);
+
+        match &session {
+            Some(session) => println!(
+                "chat with {} in session {} ({} messages). type /help for commands.",
+                engine,
+                session.name,
+                session.conversation.len()
+            ),
+            None => println!("chat with {}. type /help for commands.", engine),
+        }
+        loop {
+            // commands also change the conversation, so the session is saved before every input.
+            if let Some(session) = &mut session {
+                save_session(&store, session, &chat);
+            }
+            let line = match editor.readline("> ") {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted | ReadlineError::Eof) => break,
+                Err(e) => return Err(anyhow::Error::from(e).context("Failed to read line").into()),
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let _ = editor.add_history_entry(line.as_str());
+
+            let prompt = match ChatCommand::parse(&line) {
+                ChatCommand::Message(message) => chat.user_message(&message),
+                ChatCommand::Retry => match chat.retry() {
+                    Some(prompt) => prompt,
+                    None => {
+                        eprintln!("nothing to retry");
+                        continue;
+                    }
+                },
+                ChatCommand::Engine(new_engine) => {
+                    if !ENGINES.contains(&new_engine.as_str()) {
+                        eprintln!(
+                            "unknown engine: {}. available engines: {}",
+                            new_engine,
+                            ENGINES.join(", ")
+                        );
+                        continue;
+                    }
+                    ai = self.ai(&new_engine);
+                    println!("switched to {}", new_engine);
+                    engine = new_engine;
+                    continue;
+                }
+                ChatCommand::System(system) => {
+                    chat.set_system(&system);
+                    continue;
+                }
+                ChatCommand::Save(path) => {
+                    match chat.save(&path) {
+                        Ok(()) => println!("saved to {}", path),
+                        Err(e) => eprintln!("{}", e),
+                    }
+                    continue;
+                }
+                ChatCommand::Load(path) => {
+                    match Chat::load(&path) {
+                        Ok(loaded) => {
+                            chat = loaded;
+                            println!("loaded {} messages", chat.conversation().len());
+                        }
+                        Err(e) => eprintln!("{}", e),
+                    }
+                    continue;
+                }
+                ChatCommand::Include(path) => {
+                    match std::fs::read_to_string(&path) {
+                        Ok(content) => chat.include(&path, &content),
+                        Err(e) => eprintln!("Failed to read {}: {}", path, e),
+                    }
+                    continue;
+                }
+                ChatCommand::Undo => {
+                    if !chat.undo() {
+                        eprintln!("nothing to undo");
+                    }
+                    continue;
+                }
+                ChatCommand::Clear => {
+                    chat.clear();
+                    continue;
+                }
+                ChatCommand::Help => {
+                    println!("{}", HELP);
+                    continue;
+                }
+                ChatCommand::Unknown(command) => {
+                    eprintln!("unknown command: {}. type /help for commands.", command);
+                    continue;
+                }
+                ChatCommand::Exit => break,
+            };
+            let started_at = unix_now();
+            let prompt_tokens = estimate_tokens(&prompt);
+            match reply(&ai, prompt, !self.plain).await {
+                Ok(reply) => {
+                    chat.ai_message(&reply);
+                    if let Some(session) = &mut session {
+                        session.set_conversation(chat.conversation().clone());
+                        session.record_turn(Turn {
+                            engine: engine.clone(),
+                            started_at,
+                            finished_at: unix_now(),
+                            prompt_tokens,
+                            completion_tokens: estimate_tokens(&Prompt::ask(&reply)),
+                        });
+                    }
+                }
+                Err(e) => eprintln!("{}\ntype /retry to ask again.", e),
+            }
+        }
+        if let Some(session) = &mut session {
+            save_session(&store, session, &chat);
+        }
+
+        if let Some(dir) = history.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        let _ = editor.save_history(&history);
+        Ok(())
+    }
+    async fn sessions(&self, sub: &SessionCommand) -> Result<(), AIError> {
+        let store = SessionStore::new(SessionStore::default_dir());
+        let load = |name: &str| -> Result<Session, AIError> {
+            store
+                .load(name)
+                .context("Failed to load session")?
+                .ok_or_else(|| anyhow::anyhow!("Session not found: {}", name).into())
+        };
+        match sub {
+            SessionCommand::List => {
+                let sessions = store.list().context("Failed to list sessions")?;
+                self.print_values(&sessions, |session| {
+                    format!(
+                        "{}\t{}\t{} messages\t{} tokens\tupdated at {}",
+                        session.name,
+                        session.engine,
+                        session.conversation.len(),
+                        session.total_tokens(),
+                        session.updated_at
+                    )
+                })?;
+            }
+            SessionCommand::Show { name } => {
+                self.print_value(&load(name)?, |session| session.to_markdown())?
+            }
+            SessionCommand::Resume { name } => {
+                load(name)?;
+                self.chat(None, None, Some(name.to_string())).await?;
+            }
+            SessionCommand::Delete { name } => {
+                if !store.delete(name).context("Failed to delete session")? {
+                    return Err(anyhow::anyhow!("Session not found: {}", name).into());
+                }
+                self.print_value(&serde_json::json!({ "deleted": name }), |_| {
+                    format!("deleted {}", name)
+                })?;
+            }
+            SessionCommand::Export { name, format } => {
+                let session = load(name)?;
+                match format {
+                    ExportFormat::Json => println!(
+                        "{}",
+                        serde_json::to_string_pretty(&session)
+                            .context("Failed to serialize session")?
+                    ),
+                    ExportFormat::Markdown => println!("{}", session.to_markdown()),
+                }
+            }
+        }
+        Ok(())
     }
     async fn server(&self, port: u16) -> Result<(), AIError> {
-        let server = AIServer::new(port);
+        if self.output != OutputFormat::Text {
+            return Err(anyhow::anyhow!("--output is not supported by server").into());
+        }
+        let server = AIServer::new(port).limiters(self.rate_limiters());
         server.start().await;
         Ok(())
     }
@@ -155,6 +983,38 @@ enum SubCommand {
         engine: String,
         #[clap(short = 'r')]
         role_play: Option<String>,
+        /// Write fenced code blocks of the answer into this directory.
+        #[clap(long = "extract-code")]
+        extract_code: Option<String>,
+        /// Overwrite existing files without asking when extracting code.
+        #[clap(long = "overwrite", requires = "extract_code")]
+        overwrite: bool,
+        /// Stop the answer after this number of code blocks, e.g. 1 for the first block only.
+        #[clap(long = "max-blocks", requires = "extract_code")]
+        max_blocks: Option<usize>,
+    },
+    /// Write a Conventional Commits message of the staged changes.
+    #[clap(name = "commit-msg")]
+    CommitMsg {
+        #[clap(long = "engine", short = 'e', default_value = "gpt4-o-mini")]
+        engine: String,
+        /// Write the message into `.git/COMMIT_EDITMSG` instead of printing it.
+        #[clap(long = "write", short = 'w')]
+        write: bool,
+        /// Estimated tokens of the diff sent in one request. Larger diffs are summarized first.
+        #[clap(long = "token-budget", default_value_t = 4000)]
+        token_budget: u32,
+    },
+    /// Write a pull request description of the commits and changes since `base`.
+    #[clap(name = "pr-desc")]
+    PrDesc {
+        #[clap(long = "engine", short = 'e', default_value = "gpt4-o-mini")]
+        engine: String,
+        /// The branch which the pull request is merged into, like `main`.
+        base: String,
+        /// Estimated tokens of the diff sent in one request. Larger diffs are summarized first.
+        #[clap(long = "token-budget", default_value_t = 4000)]
+        token_budget: u32,
     },
     #[clap(name = "conversation", alias = "conv")]
     Conversation {
@@ -166,23 +1026,302 @@ enum SubCommand {
     CodeReview {
         #[clap(long = "engine", short = 'e', default_value = "gpt4-o-mini")]
         engine: String,
-        path: String,
+        /// Files, directories or globs like `src/**/*.rs`. `-` reads the code from stdin.
+        #[clap(required_unless_present_any = ["diff", "staged"])]
+        paths: Vec<String>,
+        #[clap(flatten)]
+        review: ReviewArgs,
     },
     #[clap(name = "translate", alias = "t")]
     Translate {
-        source: String,
-        #[clap(long = "target-lang", short = 't', default_value = "ja")]
-        target_lang: String,
         #[clap(long = "engine", short = 'e', default_value = "gpt4-o-mini")]
         engine: String,
-        #[clap(short = 'l', default_value = "1")]
-        separate_per_limit: usize,
+        #[clap(flatten)]
+        translate: TranslateArgs,
+    },
+    #[clap(name = "chat")]
+    Chat {
+        /// Defaults to the engine of the session or gpt4-o-mini.
+        #[clap(long = "engine", short = 'e')]
+        engine: Option<String>,
+        #[clap(long = "system", short = 's')]
+        system: Option<String>,
+        /// Continue the named session, or create it if it does not exist.
+        #[clap(long = "session")]
+        session: Option<String>,
+    },
+    #[clap(name = "sessions")]
+    Sessions {
+        #[clap(subcommand)]
+        sub: SessionCommand,
     },
     #[clap(name = "server")]
     Server {
         #[clap(long = "port", short = 'p', default_value = "9999")]
         port: u16,
     },
+    #[clap(name = "cache")]
+    Cache {
+        #[clap(subcommand)]
+        sub: CacheCommand,
+    },
+    /// Manage the translation memory of `translate --memory`.
+    #[clap(name = "memory")]
+    Memory {
+        #[clap(subcommand)]
+        sub: MemoryCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum SessionCommand {
+    /// List sessions, most recently updated first.
+    List,
+    /// Print the session as markdown.
+    Show {
+        name: String,
+    },
+    /// Continue the session in the chat.
+    Resume {
+        name: String,
+    },
+    Delete {
+        name: String,
+    },
+    /// Print the session in the given format.
+    Export {
+        name: String,
+        #[clap(long = "format", short = 'f', value_enum, default_value = "json")]
+        format: ExportFormat,
+    },
+}
+
+#[derive(clap::Args)]
+struct ReviewArgs {
+    /// Review the changes of `git diff <rev-range>` instead of a file, like `main..HEAD`.
+    #[clap(long = "diff", conflicts_with = "paths")]
+    diff: Option<String>,
+    /// Review the staged changes.
+    #[clap(long = "staged", conflicts_with = "paths")]
+    staged: bool,
+    /// Number of unchanged lines around each change.
+    #[clap(long = "context", default_value_t = 3)]
+    context: usize,
+    /// Estimated tokens of the code sent in one request. Larger files are split at functions.
+    #[clap(long = "token-budget", default_value_t = 4000)]
+    token_budget: u32,
+    /// Format of the findings. Other than text, the findings are requested as structured data.
+    #[clap(long = "format", value_enum, default_value = "text")]
+    format: ReviewFormat,
+    /// Exit with an error if there is a finding of this severity or more severe.
+    #[clap(long = "fail-on")]
+    fail_on: Option<Severity>,
+    /// Language of the review comments.
+    #[clap(long = "lang", default_value = "ja")]
+    lang: String,
+    /// Preset of the review: standard, strict, lenient or security.
+    #[clap(long = "rubric", default_value = "standard")]
+    rubric: Rubric,
+    /// Comma separated aspects to focus on: security, performance, readability and tests.
+    #[clap(long = "focus", value_delimiter = ',')]
+    focus: Vec<Focus>,
+    /// Project guidelines for the reviewer. `.cai/review.md` is used if it exists.
+    #[clap(long = "guidelines")]
+    guidelines: Option<String>,
+    /// Ask a patch for each finding and apply the accepted ones to the files.
+    #[clap(long = "fix")]
+    fix: bool,
+}
+impl ReviewArgs {
+    fn options(&self) -> Result<ReviewOptions, AIError> {
+        let guidelines =
+            load_guidelines(self.guidelines.as_deref()).context("Failed to load guidelines")?;
+        Ok(ReviewOptions::new()
+            .language(Some(&self.lang))
+            .rubric(self.rubric)
+            .focus(self.focus.clone())
+            .guidelines(guidelines))
+    }
+    /// False for the free-form review of a file.
+    fn is_structured(&self) -> bool {
+        self.diff.is_some()
+            || self.staged
+            || self.format != ReviewFormat::Text
+            || self.fail_on.is_some()
+            || self.fix
+    }
+}
+
+#[derive(clap::Args)]
+struct TranslateArgs {
+    /// The text to translate. `-` reads stdin, and a path to an existing file reads the file.
+    source: String,
+    /// BCP-47 language tag like `ja`, `en-US` or `zh-Hant`.
+    #[clap(long = "target-lang", short = 't', default_value = "ja")]
+    target_lang: Lang,
+    /// Detected from the source if it is not given.
+    #[clap(long = "source-lang", short = 's')]
+    source_lang: Option<Lang>,
+    /// Defaults to the format of the file extension, or text.
+    #[clap(long = "input-format", short = 'i', value_enum)]
+    input_format: Option<TranslateFormat>,
+    /// How the segments of a text are written, like XLIFF for CAT tools.
+    #[clap(long = "format", short = 'f', value_enum, default_value = "text")]
+    format: BilingualFormat,
+    #[clap(short = 'l', default_value = "1")]
+    separate_per_limit: usize,
+    /// Write the translated file to this path instead of printing it.
+    #[clap(long = "out")]
+    out: Option<String>,
+    /// Keep the messages which are already in the `--out` file and translate only the others.
+    #[clap(long = "missing-only", requires = "out")]
+    missing_only: bool,
+    /// Give each segment the segments before and after it as context.
+    #[clap(long = "context")]
+    context: bool,
+    /// How many times failed segments are translated again.
+    #[clap(long = "retries", default_value = "2")]
+    retries: usize,
+    /// Reuse translations in the translation memory, and add new ones to it.
+    #[clap(long = "memory")]
+    memory: bool,
+    /// Similar segments in the memory whose similarity is at least this are given as hints.
+    #[clap(long = "fuzzy-threshold", default_value = "0.75", requires = "memory")]
+    fuzzy_threshold: f64,
+    /// Translate each segment back and let the judge engine score how well the meaning is kept.
+    #[clap(long = "verify")]
+    verify: bool,
+    /// The engine which scores the back-translations. Defaults to `--engine`.
+    #[clap(long = "judge", requires = "verify")]
+    judge: Option<String>,
+    /// Segments scored below this, from 1 to 5, are flagged for review.
+    #[clap(long = "min-score", default_value = "4", requires = "verify")]
+    min_score: u8,
+    /// CSV or TOML of terms which must be translated in the same way, or kept untranslated.
+    #[clap(long = "glossary")]
+    glossary: Option<String>,
+    /// Translate a chunk again if it does not follow the glossary.
+    #[clap(long = "glossary-retry", requires = "glossary")]
+    glossary_retry: bool,
+    /// Maximum characters per line of translated subtitles.
+    #[clap(long = "max-line-chars", default_value = "42")]
+    max_line_chars: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum BilingualFormat {
+    /// Each source and its translation on consecutive lines.
+    Text,
+    /// Tab separated id, source, target and notes.
+    Tsv,
+    /// The languages and the aligned segments.
+    Json,
+    /// XLIFF 1.2.
+    Xliff12,
+    /// XLIFF 2.0.
+    Xliff2,
+    /// A side by side report for reviewers.
+    Html,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum TranslateFormat {
+    /// Split at sentence separators.
+    Text,
+    /// Translate only the prose and keep code, URLs and the structure.
+    Markdown,
+    /// i18n messages in JSON.
+    Json,
+    /// i18n messages in YAML, like Rails locales.
+    Yaml,
+    /// gettext `.po` or `.pot`.
+    Po,
+    /// Fluent `.ftl`.
+    Fluent,
+    /// SubRip subtitles.
+    Srt,
+    /// WebVTT subtitles.
+    Vtt,
+}
+impl TranslateFormat {
+    fn from_path(path: &str) -> Self {
+        if let Some(format) = ResourceFormat::from_path(path) {
+            return match format {
+                ResourceFormat::Json => Self::Json,
+                ResourceFormat::Yaml => Self::Yaml,
+                ResourceFormat::Po => Self::Po,
+                ResourceFormat::Fluent => Self::Fluent,
+            };
+        }
+        if let Some(format) = SubtitleFormat::from_path(path) {
+            return match format {
+                SubtitleFormat::Srt => Self::Srt,
+                SubtitleFormat::Vtt => Self::Vtt,
+            };
+        }
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("md" | "markdown") => Self::Markdown,
+            _ => Self::Text,
+        }
+    }
+    fn resource(self) -> Option<ResourceFormat> {
+        match self {
+            Self::Json => Some(ResourceFormat::Json),
+            Self::Yaml => Some(ResourceFormat::Yaml),
+            Self::Po => Some(ResourceFormat::Po),
+            Self::Fluent => Some(ResourceFormat::Fluent),
+            _ => None,
+        }
+    }
+    fn subtitle(self) -> Option<SubtitleFormat> {
+        match self {
+            Self::Srt => Some(SubtitleFormat::Srt),
+            Self::Vtt => Some(SubtitleFormat::Vtt),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum ReviewFormat {
+    Text,
+    Sarif,
+    Rdjson,
+    /// GitHub Actions annotations.
+    Github,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+    Jsonl,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum ExportFormat {
+    Json,
+    Markdown,
+}
+
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// Remove all cached responses.
+    Clear,
+    /// Show the number and size of cached responses.
+    Stats,
+}
+
+#[derive(Subcommand)]
+enum MemoryCommand {
+    /// Add the translation units of a TMX file.
+    Import { path: String },
+    /// Write the translation memory as TMX.
+    Export {
+        /// Write to this path instead of printing it.
+        #[clap(long = "out")]
+        out: Option<String>,
+    },
 }
 
 impl From<ConversationInput> for Conversation {
@@ -213,6 +1352,35 @@ enum Role {
     User,
 }
 
+fn save_session(store: &SessionStore, session: &mut Session, chat: &Chat) {
+    session.set_conversation(chat.conversation().clone());
+    if let Err(e) = store.save(session) {
+        eprintln!("{}", e);
+    }
+}
+
+fn print_json<T: serde::Serialize + ?Sized>(value: &T, pretty: bool) -> Result<(), AIError> {
+    let json = if pretty {
+        serde_json::to_string_pretty(value)
+    } else {
+        serde_json::to_string(value)
+    }
+    .context("Failed to serialize output")?;
+    println!("{}", json);
+    Ok(())
+}
+
+/// Streams the reply to stdout and returns the whole reply.
+async fn reply<AI: GenerativeAIInterface>(
+    ai: &AI,
+    prompt: Prompt,
+    render: bool,
+) -> Result<String, AIError> {
+    let mut handler = (Recorder::new(), MarkdownPrinter::new().render(render));
+    ai.request_mut(prompt, &mut handler).await?;
+    Ok(handler.0.take())
+}
+
 fn replace_paths_to_content(message: String) -> String {
     let Ok(re) = regex::Regex::new(r"\{([^}]+)\}") else {
         return message.to_string();
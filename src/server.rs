@@ -1,33 +1,46 @@
-use actix_web::{HttpResponse, HttpServer, Responder, web::Json};
+use actix_web::{
+    HttpResponse, HttpServer, Responder,
+    web::{Data, Json},
+};
 
 use crate::{
-    GenerativeAIInterface, Handler, HandlerError, MutHandler, Prompt,
+    GenerativeAIInterface, Prompt,
     clients::{
-        gai::{GAIEngines, engine_to_default_key_from_env},
+        gai::{GAIEngines, engine_to_default_key_from_env, engine_to_provider},
         gemini::GeminiAPIClient,
         openai::GPTCompletionsClient,
     },
-    container_handler,
     handlers::{printer::Printer, recorder::Recorder},
+    limiter::{LimitedAI, RateLimiters, estimate_tokens},
 };
 
 pub struct AIServer {
     port: u16,
+    limiters: RateLimiters,
 }
 
 impl AIServer {
     pub fn new(port: u16) -> Self {
-        Self { port }
+        Self {
+            port,
+            limiters: RateLimiters::default(),
+        }
+    }
+    pub fn limiters(mut self, limiters: RateLimiters) -> Self {
+        self.limiters = limiters;
+        self
     }
 
     pub async fn start(self) {
-        HttpServer::new(|| {
+        let limiters = Data::new(self.limiters);
+        HttpServer::new(move || {
             let cors = actix_cors::Cors::default()
                 .allow_any_origin()
                 .allow_any_method()
                 .allow_any_header()
                 .max_age(3600);
             actix_web::App::new()
+                .app_data(limiters.clone())
                 .service(request_to)
                 .service(request_to_gemini2)
                 .service(request_to_gemini15)
@@ -43,12 +56,16 @@ impl AIServer {
 }
 
 #[actix_web::post("/")]
-async fn request_to(body: Json<PromptRequest>) -> impl Responder {
+async fn request_to(body: Json<PromptRequest>, limiters: Data<RateLimiters>) -> impl Responder {
     let client = GeminiAPIClient::new(
         engine_to_default_key_from_env("gemini2flashexp"),
         crate::clients::gemini::GeminiModel::Gemini2FlashExp,
     );
     let prompt = Prompt::ask(body.prompt.as_str());
+    let _permit = limiters
+        .get("gemini")
+        .acquire(estimate_tokens(&prompt))
+        .await;
     let resp = client.request(prompt).await.unwrap();
     let resp = Response {
         result: resp.into(),
@@ -56,12 +73,19 @@ async fn request_to(body: Json<PromptRequest>) -> impl Responder {
     HttpResponse::Ok().body(serde_json::to_string(&resp).unwrap())
 }
 #[actix_web::post("/gemini2flashexp")]
-async fn request_to_gemini2(body: Json<PromptRequest>) -> impl Responder {
+async fn request_to_gemini2(
+    body: Json<PromptRequest>,
+    limiters: Data<RateLimiters>,
+) -> impl Responder {
     let client = GeminiAPIClient::new(
         engine_to_default_key_from_env("gemini2flashexp"),
         crate::clients::gemini::GeminiModel::Gemini2FlashExp,
     );
     let prompt = Prompt::ask(body.prompt.as_str());
+    let _permit = limiters
+        .get("gemini")
+        .acquire(estimate_tokens(&prompt))
+        .await;
     let resp = client.request(prompt).await.unwrap();
     let resp = Response {
         result: resp.into(),
@@ -69,12 +93,19 @@ async fn request_to_gemini2(body: Json<PromptRequest>) -> impl Responder {
     HttpResponse::Ok().body(serde_json::to_string(&resp).unwrap())
 }
 #[actix_web::post("/gpt4o-mini")]
-async fn request_to_gpt4omini(body: Json<PromptRequest>) -> impl Responder {
+async fn request_to_gpt4omini(
+    body: Json<PromptRequest>,
+    limiters: Data<RateLimiters>,
+) -> impl Responder {
     let client = GPTCompletionsClient::new(
         engine_to_default_key_from_env("gpt4o-mini"),
         crate::clients::openai::ChatCompletionsModel::Gpt4oMini,
     );
     let prompt = Prompt::ask(body.prompt.as_str());
+    let _permit = limiters
+        .get("openai")
+        .acquire(estimate_tokens(&prompt))
+        .await;
     let resp = client.request(prompt).await.unwrap();
     let resp = Response {
         result: resp.content(),
@@ -83,21 +114,23 @@ async fn request_to_gpt4omini(body: Json<PromptRequest>) -> impl Responder {
 }
 
 #[actix_web::post("/gemini15flash")]
-async fn request_to_gemini15(body: Json<PromptRequest>) -> impl Responder {
-    let res = handle_prompt::<Response>("gemini15flash", &body.prompt).await;
+async fn request_to_gemini15(
+    body: Json<PromptRequest>,
+    limiters: Data<RateLimiters>,
+) -> impl Responder {
+    let res = handle_prompt::<Response>("gemini15flash", &body.prompt, &limiters).await;
     HttpResponse::Ok().body(serde_json::to_string(&res).unwrap())
 }
 
-async fn handle_prompt<T: From<String>>(name: &str, prompt: &str) -> T {
-    container_handler!(recorder:Recorder,printer:Printer);
-    let mut handler = Container {
-        recorder: Recorder::new(),
-        printer: Printer::new(),
-    };
-    let ai = GAIEngines::from_str(name, engine_to_default_key_from_env(name));
+async fn handle_prompt<T: From<String>>(name: &str, prompt: &str, limiters: &RateLimiters) -> T {
+    let mut handler = (Recorder::new(), Printer::new());
+    let ai = LimitedAI::new(
+        GAIEngines::from_str(name, engine_to_default_key_from_env(name)),
+        limiters.get(engine_to_provider(name)),
+    );
     let prompt = Prompt::ask(prompt);
     ai.request_mut(prompt, &mut handler).await.unwrap();
-    let response = handler.recorder.take();
+    let response = handler.0.take();
     T::from(response)
 }
 
@@ -2,7 +2,7 @@ use anyhow::Context;
 use std::future::Future;
 use tokio_stream::StreamExt as _;
 
-use crate::{MutHandler, impl_from_error};
+use crate::{Flow, MutHandler, impl_from_error};
 
 pub struct SseClient {
     url: String,
@@ -94,7 +94,11 @@ impl Response {
                         continue;
                     };
                     for s in responses {
-                        handler.handle(s).await.context("Failed to handle stream")?
+                        // dropping the stream closes the connection.
+                        if handler.handle(s).await.context("Failed to handle stream")? == Flow::Stop
+                        {
+                            return Ok(());
+                        }
                     }
                 }
                 Err(error) => {
@@ -130,10 +134,13 @@ impl Response {
 
                     for s in responses {
                         let s = f(s)?;
-                        handler
-                            .handle_mut(s.as_str())
+                        let flow = handler
+                            .handle_mut_flow(s.as_str())
                             .await
-                            .context("Failed to handle stream")?
+                            .context("Failed to handle stream")?;
+                        if flow == Flow::Stop {
+                            return Ok(());
+                        }
                     }
                 }
                 Err(error) => {
@@ -176,7 +183,7 @@ impl Response {
 
 pub trait SseHandler {
     #[allow(async_fn_in_trait)]
-    async fn handle(&self, stream: SseResponse) -> Result<(), SseHandlerError>;
+    async fn handle(&self, stream: SseResponse) -> Result<Flow, SseHandlerError>;
 }
 pub trait SseMutHandler {
     #[allow(async_fn_in_trait)]
@@ -189,10 +196,10 @@ pub struct SseHandlerError(anyhow::Error);
 
 impl<F, AsyncOutput> SseHandler for F
 where
-    AsyncOutput: Future<Output = Result<(), SseHandlerError>>,
+    AsyncOutput: Future<Output = Result<Flow, SseHandlerError>>,
     F: Fn(SseResponse) -> AsyncOutput,
 {
-    async fn handle(&self, stream: SseResponse) -> Result<(), SseHandlerError> {
+    async fn handle(&self, stream: SseResponse) -> Result<Flow, SseHandlerError> {
         self(stream).await
     }
 }
@@ -248,10 +255,10 @@ impl SseResponse {
         // If the last character is a delimiter, it is good to divide.
         // In that case, the line becomes an empty string.
         // If the last line is not an empty string, perform a judgment because the delimiters are inappropriate.
-        if let Some(last_line) = for_interrupted_data.last() {
-            if !last_line.is_empty() {
-                return Err(SseResponseParseError::InterruptedData(chunk.to_string()));
-            }
+        if let Some(last_line) = for_interrupted_data.last()
+            && !last_line.is_empty()
+        {
+            return Err(SseResponseParseError::InterruptedData(chunk.to_string()));
         }
         Ok(result)
     }
@@ -373,9 +380,9 @@ mod tests {
         }
     }
     impl SseHandler for GptHandler {
-        async fn handle(&self, stream: SseResponse) -> Result<(), SseHandlerError> {
-            assert!(stream.data().unwrap().len() > 0);
-            Ok(())
+        async fn handle(&self, stream: SseResponse) -> Result<Flow, SseHandlerError> {
+            assert!(!stream.data().unwrap().is_empty());
+            Ok(Flow::Continue)
         }
     }
     impl SseMutHandler for GptHandler {
@@ -501,13 +508,13 @@ mod tests {
         role: Role,
         content: String,
     }
-    #[derive(Debug, Clone, serde::Deserialize, PartialEq, Eq)]
+    #[derive(Debug, Clone, Copy, serde::Deserialize, PartialEq, Eq)]
     pub enum Role {
         User,
         Assistant,
     }
     impl Role {
-        fn into_str(&self) -> &'static str {
+        fn into_str(self) -> &'static str {
             match self {
                 Self::User => "user",
                 Self::Assistant => "assistant",
@@ -523,7 +530,7 @@ mod tests {
             serializer.serialize_str(role)
         }
     }
-    #[derive(Debug, Clone, serde::Deserialize)]
+    #[derive(Debug, Clone, Copy, serde::Deserialize)]
     pub enum OpenAIModel {
         Gpt3Dot5Turbo,
         Gpt4o,
@@ -538,7 +545,7 @@ mod tests {
     }
 
     impl OpenAIModel {
-        pub fn into_str(&self) -> &'static str {
+        pub fn into_str(self) -> &'static str {
             match self {
                 Self::Gpt3Dot5Turbo => "gpt-3.5-turbo",
                 Self::Gpt4o => "gpt-4o",
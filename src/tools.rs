@@ -1 +1,15 @@
+pub mod annotations;
+pub mod bilingual;
+pub mod chat;
+pub mod commit;
+pub mod glossary;
+pub mod i18n;
+pub mod input;
+pub mod lang;
+pub mod markdown;
+pub mod memory;
+pub mod patch;
+pub mod review;
+pub mod session;
+pub mod subtitle;
 pub mod translator;
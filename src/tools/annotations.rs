@@ -0,0 +1,180 @@
+use serde_json::{Value, json};
+
+use crate::tools::review::{Finding, Severity};
+
+const TOOL_NAME: &str = "cai";
+
+/// SARIF 2.1.0 log with one run. Each category is a rule.
+pub fn to_sarif(findings: &[Finding]) -> Value {
+    let mut rules = findings
+        .iter()
+        .map(|f| f.category.as_str())
+        .collect::<Vec<_>>();
+    rules.sort_unstable();
+    rules.dedup();
+    let results = findings
+        .iter()
+        .map(|f| {
+            let mut result = json!({
+                "ruleId": f.category,
+                "level": sarif_level(f.severity),
+                "message": { "text": f.message },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": { "uri": f.path },
+                        "region": { "startLine": f.start_line, "endLine": f.end_line }
+                    }
+                }]
+            });
+            if let Some(suggestion) = &f.suggestion {
+                result["properties"] = json!({ "suggestion": suggestion });
+            }
+            result
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": TOOL_NAME,
+                    "rules": rules.iter().map(|id| json!({ "id": id })).collect::<Vec<_>>()
+                }
+            },
+            "results": results
+        }]
+    })
+}
+
+fn sarif_level(severity: Severity) -> &'static str {
+    match severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+        Severity::Info => "note",
+    }
+}
+
+/// reviewdog diagnostic format. Suggestions replace the whole line range.
+pub fn to_rdjson(findings: &[Finding]) -> Value {
+    let diagnostics = findings
+        .iter()
+        .map(|f| {
+            let range = json!({
+                "start": { "line": f.start_line },
+                "end": { "line": f.end_line }
+            });
+            let mut diagnostic = json!({
+                "message": f.message,
+                "location": { "path": f.path, "range": range },
+                "severity": f.severity.to_str().to_uppercase(),
+                "code": { "value": f.category }
+            });
+            if let Some(suggestion) = &f.suggestion {
+                // the range must cover the whole lines to replace them.
+                let lines = json!({
+                    "start": { "line": f.start_line, "column": 1 },
+                    "end": { "line": f.end_line + 1, "column": 1 }
+                });
+                let text = format!("{}\n", suggestion.trim_end_matches('\n'));
+                diagnostic["suggestions"] = json!([{ "range": lines, "text": text }]);
+            }
+            diagnostic
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "source": { "name": TOOL_NAME },
+        "diagnostics": diagnostics
+    })
+}
+
+/// GitHub Actions workflow commands, one line per finding.
+pub fn to_github(findings: &[Finding]) -> String {
+    findings
+        .iter()
+        .map(|f| {
+            let command = match f.severity {
+                Severity::Error => "error",
+                Severity::Warning => "warning",
+                Severity::Info => "notice",
+            };
+            format!(
+                "::{} file={},line={},endLine={},title={}::{}\n",
+                command,
+                escape_property(&f.path),
+                f.start_line,
+                f.end_line,
+                escape_property(&f.category),
+                escape_data(&f.message)
+            )
+        })
+        .collect()
+}
+
+// https://github.com/actions/toolkit/blob/main/packages/core/src/command.ts
+fn escape_data(s: &str) -> String {
+    s.replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+fn escape_property(s: &str) -> String {
+    escape_data(s).replace(':', "%3A").replace(',', "%2C")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn findings() -> Vec<Finding> {
+        vec![
+            Finding {
+                path: "src/lib.rs".to_string(),
+                start_line: 3,
+                end_line: 4,
+                severity: Severity::Warning,
+                category: "bug".to_string(),
+                message: "off by one\nin loop".to_string(),
+                suggestion: Some("for i in 0..n {".to_string()),
+            },
+            Finding {
+                path: "README.md".to_string(),
+                start_line: 1,
+                end_line: 1,
+                severity: Severity::Info,
+                category: "docs".to_string(),
+                message: "typo".to_string(),
+                suggestion: None,
+            },
+        ]
+    }
+
+    #[test]
+    fn findings_are_converted_to_sarif_and_rdjson() {
+        let sarif = to_sarif(&findings());
+        let rdjson = to_rdjson(&findings());
+
+        let run = &sarif["runs"][0];
+        assert_eq!(sarif["version"], "2.1.0");
+        assert_eq!(
+            run["tool"]["driver"]["rules"],
+            json!([{"id": "bug"}, {"id": "docs"}])
+        );
+        assert_eq!(run["results"][1]["level"], "note");
+        assert_eq!(
+            run["results"][0]["locations"][0]["physicalLocation"]["region"],
+            json!({"startLine": 3, "endLine": 4})
+        );
+        let diagnostic = &rdjson["diagnostics"][0];
+        assert_eq!(diagnostic["severity"], "WARNING");
+        assert_eq!(diagnostic["suggestions"][0]["text"], "for i in 0..n {\n");
+        assert_eq!(diagnostic["suggestions"][0]["range"]["end"]["line"], 5);
+        assert!(rdjson["diagnostics"][1].get("suggestions").is_none());
+    }
+    #[test]
+    fn github_annotations_are_escaped() {
+        assert_eq!(
+            to_github(&findings()),
+            "::warning file=src/lib.rs,line=3,endLine=4,title=bug::off by one%0Ain loop\n::notice file=README.md,line=1,endLine=1,title=docs::typo\n"
+        );
+    }
+}
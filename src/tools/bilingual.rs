@@ -0,0 +1,281 @@
+use serde_json::{Value, json};
+
+use crate::tools::translator::{MAX_SCORE, TranslateResult};
+
+// the language of a segment whose source language is not known.
+const UNDETERMINED: &str = "und";
+
+/// Escapes the text for XML content and attribute values.
+pub(crate) fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn langs(results: &[TranslateResult]) -> (String, String) {
+    let Some(first) = results.first() else {
+        return (UNDETERMINED.to_string(), UNDETERMINED.to_string());
+    };
+    let source = first
+        .source_lang()
+        .map_or(UNDETERMINED.to_string(), |lang| lang.tag().to_string());
+    (source, first.target_lang().tag().to_string())
+}
+
+// what a reviewer should know about the segment.
+fn notes(result: &TranslateResult) -> Vec<String> {
+    let mut notes = vec![];
+    if let Some(error) = result.error() {
+        notes.push(format!("failed: {}", error));
+    }
+    for violation in result.violations() {
+        notes.push(format!("glossary: {}", violation));
+    }
+    if let Some(quality) = result.quality().filter(|quality| quality.flagged) {
+        notes.push(format!(
+            "check: {}/{} {}",
+            quality.score, MAX_SCORE, quality.reason
+        ));
+    }
+    if result.from_memory() {
+        notes.push("from translation memory".to_string());
+    }
+    notes
+}
+
+fn needs_review(result: &TranslateResult) -> bool {
+    !result.violations().is_empty() || result.quality().is_some_and(|quality| quality.flagged)
+}
+
+/// Tab separated `id`, `source`, `target` and `notes` with a header, for spreadsheets.
+/// Tabs, line breaks and backslashes in the texts are escaped with a backslash.
+pub fn to_tsv(results: &[TranslateResult]) -> String {
+    let escape = |s: &str| {
+        s.replace('\\', "\\\\")
+            .replace('\t', "\\t")
+            .replace('\r', "\\r")
+            .replace('\n', "\\n")
+    };
+    let mut tsv = "id\tsource\ttarget\tnotes\n".to_string();
+    for (i, result) in results.iter().enumerate() {
+        tsv.push_str(&format!(
+            "{}\t{}\t{}\t{}\n",
+            i + 1,
+            escape(result.source()),
+            escape(result.translated().unwrap_or_default()),
+            escape(&notes(result).join("; "))
+        ));
+    }
+    tsv
+}
+
+/// The languages and the aligned segments. `target` is null for a failed segment.
+pub fn to_json(results: &[TranslateResult]) -> Value {
+    let (source_lang, target_lang) = langs(results);
+    let segments = results
+        .iter()
+        .enumerate()
+        .map(|(i, result)| {
+            json!({
+                "id": i + 1,
+                "source": result.source(),
+                "target": result.translated(),
+                "needs_review": needs_review(result),
+                "notes": notes(result),
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({
+        "source_lang": source_lang,
+        "target_lang": target_lang,
+        "segments": segments,
+    })
+}
+
+/// XLIFF 1.2 with a `trans-unit` per segment. A failed segment has no target, and a segment
+/// which needs review has the `needs-review-translation` state.
+pub fn to_xliff12(results: &[TranslateResult]) -> String {
+    let (source_lang, target_lang) = langs(results);
+    let mut xliff = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<xliff version=\"1.2\" xmlns=\"urn:oasis:names:tc:xliff:document:1.2\">\n  <file original=\"cai\" source-language=\"{}\" target-language=\"{}\" datatype=\"plaintext\">\n    <body>\n",
+        escape_xml(&source_lang),
+        escape_xml(&target_lang)
+    );
+    for (i, result) in results.iter().enumerate() {
+        xliff.push_str(&format!("      <trans-unit id=\"{}\">\n", i + 1));
+        xliff.push_str(&format!(
+            "        <source>{}</source>\n",
+            escape_xml(result.source())
+        ));
+        if let Some(translated) = result.translated() {
+            let state = if needs_review(result) {
+                "needs-review-translation"
+            } else {
+                "translated"
+            };
+            xliff.push_str(&format!(
+                "        <target state=\"{}\">{}</target>\n",
+                state,
+                escape_xml(translated)
+            ));
+        }
+        for note in notes(result) {
+            xliff.push_str(&format!("        <note>{}</note>\n", escape_xml(&note)));
+        }
+        xliff.push_str("      </trans-unit>\n");
+    }
+    xliff.push_str("    </body>\n  </file>\n</xliff>\n");
+    xliff
+}
+
+/// XLIFF 2.0 with a `unit` per segment. A failed segment is `initial` without target, and the
+/// notes tell which segments need review.
+pub fn to_xliff2(results: &[TranslateResult]) -> String {
+    let (source_lang, target_lang) = langs(results);
+    let mut xliff = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<xliff version=\"2.0\" xmlns=\"urn:oasis:names:tc:xliff:document:2.0\" srcLang=\"{}\" trgLang=\"{}\">\n  <file id=\"f1\">\n",
+        escape_xml(&source_lang),
+        escape_xml(&target_lang)
+    );
+    for (i, result) in results.iter().enumerate() {
+        xliff.push_str(&format!("    <unit id=\"u{}\">\n", i + 1));
+        let notes = notes(result);
+        if !notes.is_empty() {
+            xliff.push_str("      <notes>\n");
+            for note in notes {
+                xliff.push_str(&format!("        <note>{}</note>\n", escape_xml(&note)));
+            }
+            xliff.push_str("      </notes>\n");
+        }
+        let state = match result.translated() {
+            Some(_) => "translated",
+            None => "initial",
+        };
+        xliff.push_str(&format!("      <segment state=\"{}\">\n", state));
+        xliff.push_str(&format!(
+            "        <source>{}</source>\n",
+            escape_xml(result.source())
+        ));
+        if let Some(translated) = result.translated() {
+            xliff.push_str(&format!(
+                "        <target>{}</target>\n",
+                escape_xml(translated)
+            ));
+        }
+        xliff.push_str("      </segment>\n    </unit>\n");
+    }
+    xliff.push_str("  </file>\n</xliff>\n");
+    xliff
+}
+
+const HTML_STYLE: &str = "body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; width: 100%; }
+th, td { border: 1px solid #ccc; padding: 0.5em; vertical-align: top; text-align: left; white-space: pre-wrap; }
+tr.review { background: #fff4d6; }
+tr.failed { background: #fde2e2; }
+.notes { color: #666; font-size: 0.9em; }";
+
+/// A standalone page with the source and the translation side by side. Segments which need
+/// review or failed are highlighted.
+pub fn to_html(results: &[TranslateResult]) -> String {
+    let (source_lang, target_lang) = langs(results);
+    let reviews = results.iter().filter(|r| needs_review(r)).count();
+    let failures = results.iter().filter(|r| r.error().is_some()).count();
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Translation {source} → {target}</title>\n<style>\n{}\n</style>\n</head>\n<body>\n<h1>Translation {source} → {target}</h1>\n<p>{} segments, {} need review, {} failed</p>\n<table>\n<tr><th>#</th><th lang=\"{source}\">{source}</th><th lang=\"{target}\">{target}</th><th>notes</th></tr>\n",
+        HTML_STYLE,
+        results.len(),
+        reviews,
+        failures,
+        source = escape_xml(&source_lang),
+        target = escape_xml(&target_lang),
+    );
+    for (i, result) in results.iter().enumerate() {
+        let class = if result.error().is_some() {
+            " class=\"failed\""
+        } else if needs_review(result) {
+            " class=\"review\""
+        } else {
+            ""
+        };
+        let notes = notes(result)
+            .iter()
+            .map(|note| escape_xml(note))
+            .collect::<Vec<_>>();
+        html.push_str(&format!(
+            "<tr{}><td>{}</td><td>{}</td><td>{}</td><td class=\"notes\">{}</td></tr>\n",
+            class,
+            i + 1,
+            escape_xml(result.source()),
+            escape_xml(result.translated().unwrap_or_default()),
+            notes.join("<br>")
+        ));
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Prompt,
+        clients::mocks::FakeAI,
+        tools::translator::{TranslateRequests, translate},
+    };
+
+    async fn results() -> Vec<TranslateResult> {
+        let request = TranslateRequests::new("Tab\there. bad.".to_string(), "ja".parse().unwrap())
+            .source_lang(Some("en".parse().unwrap()))
+            .separators(vec!['.']);
+        // fails the segment "bad." and answers "<訳> & \"x\"" otherwise.
+        let ai = FakeAI::new(|prompt: Prompt| {
+            if prompt.messages()[0].content().contains("'bad.'") {
+                anyhow::bail!("timeout");
+            }
+            Ok("<訳> & \"x\"".to_string())
+        });
+        translate(&ai, &request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn segments_are_aligned_in_tsv_and_json() {
+        let results = results().await;
+
+        let tsv = to_tsv(&results);
+        let json = to_json(&results);
+
+        assert_eq!(
+            tsv,
+            "id\tsource\ttarget\tnotes\n1\tTab\\there.\t<訳> & \"x\"\t\n2\tbad.\t\tfailed: timeout\n"
+        );
+        assert_eq!(json["source_lang"], "en");
+        assert_eq!(json["target_lang"], "ja");
+        assert_eq!(json["segments"][0]["target"], "<訳> & \"x\"");
+        assert_eq!(json["segments"][1]["target"], Value::Null);
+    }
+    #[tokio::test]
+    async fn xliff_and_html_are_escaped() {
+        let results = results().await;
+
+        let xliff12 = to_xliff12(&results);
+        let xliff2 = to_xliff2(&results);
+        let html = to_html(&results);
+
+        assert!(xliff12.contains("source-language=\"en\" target-language=\"ja\""));
+        assert!(
+            xliff12
+                .contains("<target state=\"translated\">&lt;訳&gt; &amp; &quot;x&quot;</target>")
+        );
+        assert!(xliff12.contains(
+            "<trans-unit id=\"2\">\n        <source>bad.</source>\n        <note>failed: timeout</note>"
+        ));
+        assert!(xliff2.contains("srcLang=\"en\" trgLang=\"ja\""));
+        assert!(xliff2.contains(
+            "<segment state=\"initial\">\n        <source>bad.</source>\n      </segment>"
+        ));
+        assert!(html.contains("<p>2 segments, 0 need review, 1 failed</p>"));
+        assert!(html.contains("<tr class=\"failed\"><td>2</td><td>bad.</td><td></td>"));
+    }
+}
@@ -0,0 +1,256 @@
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::{Conversation, Prompt, Role};
+
+pub const HELP: &str = "\
+/engine <name>   switch the engine for the next replies
+/system <text>   set the system (role play) message
+/save <file>     save the conversation as json
+/load <file>     load a conversation saved by /save
+/undo            remove the last question and its reply
+/retry           ask the last question again
+/clear           remove all messages except the system message
+/include <file>  attach the file to the next message
+/help            show this help
+/exit            quit the chat";
+
+/// One line typed in the chat. Lines starting with `/` are commands, the others are messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChatCommand {
+    Message(String),
+    Engine(String),
+    System(String),
+    Save(String),
+    Load(String),
+    Undo,
+    Retry,
+    Clear,
+    Include(String),
+    Help,
+    Exit,
+    Unknown(String),
+}
+
+impl ChatCommand {
+    pub fn parse(line: &str) -> Self {
+        let line = line.trim();
+        let Some(command) = line.strip_prefix('/') else {
+            return Self::Message(line.to_string());
+        };
+        let (name, arg) = command
+            .split_once(char::is_whitespace)
+            .map(|(name, arg)| (name, arg.trim().to_string()))
+            .unwrap_or((command, String::new()));
+        match (name, arg.is_empty()) {
+            ("engine", false) => Self::Engine(arg),
+            ("system", false) => Self::System(arg),
+            ("save", false) => Self::Save(arg),
+            ("load", false) => Self::Load(arg),
+            ("include", false) => Self::Include(arg),
+            ("undo", _) => Self::Undo,
+            ("retry", _) => Self::Retry,
+            ("clear", _) => Self::Clear,
+            ("help", _) => Self::Help,
+            ("exit", _) | ("quit", _) => Self::Exit,
+            _ => Self::Unknown(line.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub struct ChatError(anyhow::Error);
+crate::impl_from_error!(ChatError);
+
+/// State of an interactive chat.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Chat {
+    conversation: Conversation,
+    // files attached by `/include`, sent with every message until one is answered.
+    includes: Vec<(String, String)>,
+}
+
+impl Chat {
+    pub fn new(system: Option<&str>) -> Self {
+        let mut conversation = Conversation::new();
+        if let Some(system) = system {
+            conversation.set_role_play_message(system);
+        }
+        Self {
+            conversation,
+            includes: Vec::new(),
+        }
+    }
+    pub fn with_conversation(conversation: Conversation) -> Self {
+        Self {
+            conversation,
+            includes: Vec::new(),
+        }
+    }
+    pub fn conversation(&self) -> &Conversation {
+        &self.conversation
+    }
+    /// Adds the message with the included files and returns the prompt to send.
+    /// A last message which was not answered, because the request failed, is replaced.
+    pub fn user_message(&mut self, message: &str) -> Prompt {
+        if self
+            .conversation
+            .last()
+            .is_some_and(|m| m.role() == Role::User)
+        {
+            self.conversation.pop();
+        }
+        let message =
+            self.includes
+                .iter()
+                .fold(message.to_string(), |mut message, (path, content)| {
+                    message.push_str(&format!("\n\n{}:\n```\n{}\n```", path, content));
+                    message
+                });
+        self.conversation.add_user_message(&message);
+        self.prompt()
+    }
+    pub fn ai_message(&mut self, message: &str) {
+        self.conversation.add_ai_message(message);
+        self.includes.clear();
+    }
+    pub fn set_system(&mut self, system: &str) {
+        self.conversation.set_role_play_message(system);
+    }
+    pub fn include(&mut self, path: &str, content: &str) {
+        self.includes.push((path.to_string(), content.to_string()));
+    }
+    /// Removes the last question and its reply. Returns false if there is no question.
+    pub fn undo(&mut self) -> bool {
+        if !self.conversation.iter().any(|m| m.role() == Role::User) {
+            return false;
+        }
+        while let Some(message) = self.conversation.pop() {
+            if message.role() == Role::User {
+                break;
+            }
+        }
+        true
+    }
+    /// Removes the last reply and returns the prompt to ask the last question again.
+    pub fn retry(&mut self) -> Option<Prompt> {
+        if self
+            .conversation
+            .last()
+            .is_some_and(|m| m.role() == Role::AI)
+        {
+            self.conversation.pop();
+        }
+        if self.conversation.last()?.role() != Role::User {
+            return None;
+        }
+        Some(self.prompt())
+    }
+    pub fn clear(&mut self) {
+        let system = self
+            .conversation
+            .iter()
+            .find(|m| m.role() == Role::RolePlay)
+            .map(|m| m.content().to_string());
+        *self = Self::new(system.as_deref());
+    }
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), ChatError> {
+        let path = path.as_ref();
+        let content = serde_json::to_string_pretty(&self.conversation)
+            .context("Failed to serialize conversation")?;
+        std::fs::write(path, content)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ChatError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let conversation = serde_json::from_str::<Conversation>(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        Ok(Self::with_conversation(conversation))
+    }
+    fn prompt(&self) -> Prompt {
+        Prompt::with_conversation(self.conversation.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn parse_slash_commands() {
+        assert_eq!(
+            ChatCommand::parse("hello /engine"),
+            ChatCommand::Message("hello /engine".to_string())
+        );
+        assert_eq!(
+            ChatCommand::parse("/engine  claude3-haiku "),
+            ChatCommand::Engine("claude3-haiku".to_string())
+        );
+        assert_eq!(
+            ChatCommand::parse("/system You are a teacher."),
+            ChatCommand::System("You are a teacher.".to_string())
+        );
+        assert_eq!(ChatCommand::parse("/undo"), ChatCommand::Undo);
+        assert_eq!(ChatCommand::parse("/quit"), ChatCommand::Exit);
+        assert_eq!(
+            ChatCommand::parse("/save"),
+            ChatCommand::Unknown("/save".to_string())
+        );
+    }
+    #[test]
+    fn undo_and_retry_edit_the_last_turn() {
+        let mut sut = Chat::new(Some("You are a teacher."));
+        sut.user_message("first");
+        sut.ai_message("first reply");
+        sut.user_message("second");
+        sut.ai_message("second reply");
+
+        assert!(sut.retry().is_some());
+        assert_eq!(sut.conversation().last().unwrap().content(), "second");
+
+        assert!(sut.undo());
+        assert_eq!(sut.conversation().len(), 3);
+        assert_eq!(sut.conversation().last().unwrap().content(), "first reply");
+
+        sut.clear();
+        assert_eq!(sut.conversation().len(), 1);
+        assert!(!sut.undo());
+        assert!(sut.retry().is_none());
+    }
+    #[test]
+    fn included_files_are_attached_to_next_message() {
+        let mut sut = Chat::new(None);
+        sut.include("main.rs", "fn main() {}");
+        sut.user_message("review this");
+        sut.ai_message("looks good");
+        sut.user_message("thanks");
+
+        let messages = sut.conversation().clone().messages();
+        assert_eq!(
+            messages[0].content(),
+            "review this\n\nmain.rs:\n```\nfn main() {}\n```"
+        );
+        assert_eq!(messages[2].content(), "thanks");
+    }
+    #[test]
+    fn unanswered_message_is_replaced_by_the_next_one() {
+        let mut sut = Chat::new(None);
+        sut.include("main.rs", "fn main() {}");
+        sut.user_message("review this");
+        // the request failed, so no reply was added.
+        sut.user_message("review this, please");
+        sut.ai_message("looks good");
+
+        let messages = sut.conversation().clone().messages();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            messages[0].content(),
+            "review this, please\n\nmain.rs:\n```\nfn main() {}\n```"
+        );
+        assert_eq!(messages[1].role(), Role::AI);
+    }
+}
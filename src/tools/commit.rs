@@ -0,0 +1,171 @@
+use std::{path::PathBuf, process::Command};
+
+use anyhow::Context;
+
+use crate::{
+    AIError, GenerativeAIInterface, Prompt,
+    handlers::recorder::Recorder,
+    limiter::estimate_text_tokens,
+    tools::{input::attach_context, review::ReviewChunk},
+};
+
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub struct CommitError(anyhow::Error);
+crate::impl_from_error!(CommitError);
+
+fn git(args: &[&str]) -> Result<String, CommitError> {
+    let output = Command::new("git")
+        .args(args)
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "git {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(String::from_utf8(output.stdout).context("git output is not valid utf-8")?)
+}
+
+/// Subjects and bodies of the commits in `base..HEAD`, oldest first.
+pub fn git_log(base: &str) -> Result<String, CommitError> {
+    git(&[
+        "log",
+        "--no-color",
+        "--reverse",
+        "--format=- %s%n%w(0,2,2)%b",
+        &format!("{}..HEAD", base),
+    ])
+}
+
+/// Where `git commit` reads the message prepared by `commit-msg --write`.
+pub fn commit_editmsg_path() -> Result<PathBuf, CommitError> {
+    let git_dir = git(&["rev-parse", "--git-dir"])?;
+    Ok(PathBuf::from(git_dir.trim()).join("COMMIT_EDITMSG"))
+}
+
+/// Joins the chunks into texts of about `token_budget` estimated tokens.
+fn group_chunks(chunks: &[ReviewChunk], token_budget: u32) -> Vec<String> {
+    let mut groups: Vec<(String, u32)> = vec![];
+    for chunk in chunks {
+        let text = format!("## {}\n{}", chunk.path, chunk.text());
+        let tokens = estimate_text_tokens(&text);
+        match groups.last_mut() {
+            Some((group, total)) if *total + tokens <= token_budget => {
+                group.push_str(&text);
+                *total += tokens;
+            }
+            _ => groups.push((text, tokens)),
+        }
+    }
+    groups.into_iter().map(|(group, _)| group).collect()
+}
+
+/// The diff itself if it fits in the budget. Otherwise each part is summarized first, so a
+/// large change can be described in one request.
+async fn changes<AI: GenerativeAIInterface>(
+    ai: &AI,
+    chunks: &[ReviewChunk],
+    token_budget: u32,
+) -> Result<String, AIError> {
+    let groups = group_chunks(chunks, token_budget);
+    if let [diff] = groups.as_slice() {
+        return Ok(attach_context("", "diff", diff).trim_start().to_string());
+    }
+    let tasks = groups.iter().map(|group| async move {
+        let question = attach_context(
+            "Summarize what this part of a git diff changes and why, in a few bullet points. \
+            Mention the names of changed functions and types.",
+            "diff",
+            group,
+        );
+        let mut recorder = Recorder::new();
+        ai.request_mut(Prompt::ask(&question), &mut recorder)
+            .await?;
+        Ok::<_, AIError>(recorder.take())
+    });
+    let summaries = futures::future::join_all(tasks)
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(attach_context("", "summaries", &summaries.join("\n"))
+        .trim_start()
+        .to_string())
+}
+
+/// Asks for a conventional commit message of the staged changes.
+pub async fn commit_message_prompt<AI: GenerativeAIInterface>(
+    ai: &AI,
+    chunks: &[ReviewChunk],
+    token_budget: u32,
+) -> Result<Prompt, AIError> {
+    let question = format!(
+        "Write a commit message for these changes in the Conventional Commits style. \
+        The first line is `type(scope): subject` like `fix(parser): handle empty input` within 72 characters, \
+        where type is one of feat, fix, docs, style, refactor, perf, test, build, ci and chore. \
+        After a blank line, explain what changed and why, wrapped at 72 characters. \
+        Answer only the message.\n\n{}",
+        changes(ai, chunks, token_budget).await?
+    );
+    Ok(Prompt::ask(&question))
+}
+
+/// Asks for a pull request description of a branch from its commits and diff.
+pub async fn pr_description_prompt<AI: GenerativeAIInterface>(
+    ai: &AI,
+    log: &str,
+    chunks: &[ReviewChunk],
+    token_budget: u32,
+) -> Result<Prompt, AIError> {
+    let question = format!(
+        "Write a pull request description of this branch in markdown. \
+        Start with a title line, then the sections `## Summary` with why the change is needed, \
+        `## Changes` with bullet points and `## Testing` with how to verify it. \
+        Answer only the description.\n\n{}\n\n{}",
+        attach_context("", "commits", log).trim_start(),
+        changes(ai, chunks, token_budget).await?
+    );
+    Ok(Prompt::ask(&question))
+}
+
+/// Removes a code fence around the whole reply, which models often add.
+pub fn strip_fence(reply: &str) -> String {
+    let reply = reply.trim();
+    let Some(inner) = reply.strip_prefix("```") else {
+        return format!("{}\n", reply);
+    };
+    let inner = inner.split_once('\n').map_or("", |(_, rest)| rest);
+    format!("{}\n", inner.trim_end().trim_end_matches("```").trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::review::{parse_diff, split_by_budget};
+
+    #[test]
+    fn chunks_are_grouped_within_budget() {
+        let diff = "diff --git a/a.rs b/a.rs\n--- a/a.rs\n+++ b/a.rs\n@@ -1 +1 @@\n-a\n+b\n\
+            diff --git a/b.rs b/b.rs\n--- a/b.rs\n+++ b/b.rs\n@@ -1 +1 @@\n-c\n+d\n";
+        let chunks = split_by_budget(parse_diff(diff), 1000);
+
+        let one = group_chunks(&chunks, 1000);
+        let two = group_chunks(&chunks, 10);
+
+        assert_eq!(one.len(), 1);
+        assert!(one[0].starts_with("## a.rs\n@@ -1 +1 @@\n"));
+        assert!(one[0].contains("## b.rs\n"));
+        assert_eq!(two.len(), 2);
+    }
+    #[test]
+    fn fence_around_message_is_removed() {
+        assert_eq!(
+            strip_fence("```text\nfix(cli): exit with 1 on error\n\nbody\n```\n"),
+            "fix(cli): exit with 1 on error\n\nbody\n"
+        );
+        assert_eq!(strip_fence(" feat: add x \n"), "feat: add x\n");
+    }
+}
@@ -0,0 +1,278 @@
+use std::{collections::BTreeMap, fmt::Display, path::Path};
+
+use anyhow::Context;
+use regex::Regex;
+
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub struct GlossaryError(anyhow::Error);
+crate::impl_from_error!(GlossaryError);
+
+/// A term of the glossary. A term without target is kept untranslated, like product names.
+#[derive(Debug, Clone)]
+pub struct Term {
+    pub source: String,
+    pub target: Option<String>,
+    // the source as a whole word, ignoring case.
+    pattern: Regex,
+}
+
+impl Term {
+    fn new(source: &str, target: Option<&str>) -> anyhow::Result<Self> {
+        let source = source.trim();
+        anyhow::ensure!(!source.is_empty(), "Empty term in glossary");
+        let boundary = |c: Option<char>| match c {
+            Some(c) if c.is_alphanumeric() || c == '_' => r"\b",
+            _ => "",
+        };
+        let pattern = format!(
+            "(?i){}{}{}",
+            boundary(source.chars().next()),
+            regex::escape(source),
+            boundary(source.chars().last())
+        );
+        Ok(Self {
+            source: source.to_string(),
+            target: target
+                .map(str::trim)
+                .filter(|t| !t.is_empty())
+                .map(str::to_string),
+            pattern: Regex::new(&pattern)?,
+        })
+    }
+    /// What the translation must contain.
+    pub fn expected(&self) -> &str {
+        self.target.as_deref().unwrap_or(&self.source)
+    }
+}
+
+/// Terms which must be translated in the same way in every chunk.
+///
+/// CSV has `source,target` rows, and an empty target keeps the term untranslated:
+///
+/// ```csv
+/// source,target
+/// pull request,プルリクエスト
+/// cai,
+/// ```
+///
+/// TOML has the same in a table and a list:
+///
+/// ```toml
+/// keep = ["cai", "GitHub"]
+///
+/// [terms]
+/// "pull request" = "プルリクエスト"
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Glossary {
+    terms: Vec<Term>,
+}
+
+#[derive(serde::Deserialize)]
+struct GlossaryToml {
+    #[serde(default)]
+    terms: BTreeMap<String, String>,
+    #[serde(default)]
+    keep: Vec<String>,
+}
+
+impl Glossary {
+    /// Loads a `.toml` file, or CSV otherwise.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, GlossaryError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let glossary = if path.extension().is_some_and(|ext| ext == "toml") {
+            Self::from_toml(&content)
+        } else {
+            Self::from_csv(&content)
+        };
+        Ok(glossary.with_context(|| format!("Failed to parse {}", path.display()))?)
+    }
+    pub fn from_toml(content: &str) -> Result<Self, GlossaryError> {
+        let toml = toml::from_str::<GlossaryToml>(content).context("Invalid glossary")?;
+        let terms = toml
+            .terms
+            .iter()
+            .map(|(source, target)| Term::new(source, Some(target)))
+            .chain(toml.keep.iter().map(|source| Term::new(source, None)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self { terms })
+    }
+    pub fn from_csv(content: &str) -> Result<Self, GlossaryError> {
+        let mut terms = vec![];
+        for (i, line) in content.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields = csv_fields(line);
+            if i == 0 && fields[0].trim().eq_ignore_ascii_case("source") {
+                continue;
+            }
+            let term = Term::new(&fields[0], fields.get(1).map(String::as_str))
+                .with_context(|| format!("Invalid glossary at line {}", i + 1))?;
+            terms.push(term);
+        }
+        Ok(Self { terms })
+    }
+    pub fn terms(&self) -> &[Term] {
+        &self.terms
+    }
+    /// Terms which appear in the text.
+    pub fn terms_in(&self, text: &str) -> Vec<&Term> {
+        self.terms
+            .iter()
+            .filter(|term| term.pattern.is_match(text))
+            .collect()
+    }
+    /// What to tell in the prompt for the terms in the text. `None` if there is none.
+    pub fn instruction(&self, text: &str) -> Option<String> {
+        let (translated, kept): (Vec<&Term>, Vec<&Term>) = self
+            .terms_in(text)
+            .into_iter()
+            .partition(|term| term.target.is_some());
+        let mut instruction = vec![];
+        if !translated.is_empty() {
+            let pairs = translated
+                .iter()
+                .map(|term| format!("\"{}\" → \"{}\"", term.source, term.expected()))
+                .collect::<Vec<_>>();
+            instruction.push(format!(
+                "Translate these terms as the glossary says: {}.",
+                pairs.join(", ")
+            ));
+        }
+        if !kept.is_empty() {
+            let kept = kept
+                .iter()
+                .map(|term| format!("\"{}\"", term.source))
+                .collect::<Vec<_>>();
+            instruction.push(format!(
+                "Keep these terms untranslated: {}.",
+                kept.join(", ")
+            ));
+        }
+        (!instruction.is_empty()).then(|| instruction.join(" "))
+    }
+    /// Terms in the source whose expected translation is not in the translated text.
+    pub fn violations(&self, source: &str, translated: &str) -> Vec<Violation> {
+        self.terms_in(source)
+            .into_iter()
+            .filter(|term| !translated.contains(term.expected()))
+            .map(|term| Violation {
+                term: term.source.clone(),
+                expected: term.expected().to_string(),
+            })
+            .collect()
+    }
+}
+
+/// A glossary term which is not translated as the glossary says.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Violation {
+    pub term: String,
+    pub expected: String,
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.term == self.expected {
+            write!(f, "`{}` should be kept", self.term)
+        } else {
+            write!(f, "`{}` should be `{}`", self.term, self.expected)
+        }
+    }
+}
+
+// fields of a CSV line. Quoted fields may have commas and `""` for a quote.
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        let field = fields.last_mut().unwrap();
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.trim().is_empty() => {
+                quoted = true;
+                field.clear();
+            }
+            ',' if !quoted => fields.push(String::new()),
+            _ => field.push(c),
+        }
+    }
+    fields
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_and_toml_glossaries_are_loaded() {
+        let csv = Glossary::from_csv(
+            "source,target\npull request,プルリクエスト\n\"Hello, World\",\"「こんにちは」\"\ncai,\n",
+        )
+        .unwrap();
+        let toml = Glossary::from_toml(
+            "keep = [\"cai\"]\n\n[terms]\n\"pull request\" = \"プルリクエスト\"\n",
+        )
+        .unwrap();
+
+        let pairs = |glossary: &Glossary| {
+            glossary
+                .terms()
+                .iter()
+                .map(|t| (t.source.clone(), t.target.clone()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            pairs(&csv),
+            [
+                (
+                    "pull request".to_string(),
+                    Some("プルリクエスト".to_string())
+                ),
+                (
+                    "Hello, World".to_string(),
+                    Some("「こんにちは」".to_string())
+                ),
+                ("cai".to_string(), None),
+            ]
+        );
+        assert_eq!(
+            pairs(&toml),
+            [pairs(&csv)[0].clone(), pairs(&csv)[2].clone()]
+        );
+    }
+    #[test]
+    fn terms_in_source_are_instructed_and_verified() {
+        let glossary =
+            Glossary::from_csv("pull request,プルリクエスト\ncai,\nissue,イシュー\n").unwrap();
+        let source = "Open a Pull Request with cai. The caisson is not a term.";
+
+        let instruction = glossary.instruction(source).unwrap();
+        let violations = glossary.violations(source, "CAIでプルリクを開く。");
+
+        assert_eq!(
+            instruction,
+            "Translate these terms as the glossary says: \"pull request\" → \"プルリクエスト\". Keep these terms untranslated: \"cai\"."
+        );
+        assert_eq!(
+            violations
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            [
+                "`pull request` should be `プルリクエスト`",
+                "`cai` should be kept"
+            ]
+        );
+        assert_eq!(glossary.instruction("nothing here"), None);
+    }
+}
@@ -0,0 +1,866 @@
+use std::{collections::HashMap, sync::LazyLock};
+
+use anyhow::Context;
+use regex::Regex;
+use serde_json::Value;
+
+use crate::{
+    AIError, GenerativeAIInterface,
+    tools::{
+        lang::Lang,
+        translator::{detect_lang, translate_keyed},
+    },
+};
+
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub struct I18nError(anyhow::Error);
+crate::impl_from_error!(I18nError);
+
+// `{{count}}`, `${name}`, `{name}`, `{0}`, `{ $name }`, printf like `%s` and `%1$d`, `%(name)s`
+// and HTML tags.
+static PLACEHOLDER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"\{\{[^{}]+\}\}|\$\{[^{}]+\}|\{[ \t]*[$-]?[\w.-]*[ \t]*\}|%\(\w+\)[sd]|%(?:\d+\$)?[-+ 0#]*\d*(?:\.\d+)?[sdifuxXoeEgGc@]|</?[A-Za-z][^<>]*>",
+    )
+    .unwrap()
+});
+static PO_LANGUAGE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?m)^"Language: [^"\\]*\\n""#).unwrap());
+static FLUENT_MESSAGE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^(-?[A-Za-z][\w-]*)[ \t]*=[ \t]*").unwrap());
+static FLUENT_ATTRIBUTE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[ \t]+\.([A-Za-z][\w-]*)[ \t]*=[ \t]*").unwrap());
+
+const INSTRUCTION: &str = "The values are messages of an app, and the keys are their identifiers. \
+    Keep placeholders like {name}, {{count}}, %s, %1$d and { $name }, HTML tags and escape sequences as they are.";
+const RETRY_INSTRUCTION: &str = "The values are messages of an app, and the keys are their identifiers. \
+    Some placeholders were lost in the last translation, so copy every placeholder like {name}, {{count}}, %s \
+    and { $name } and every HTML tag exactly once.";
+
+/// Placeholders in the text, sorted. A translation must have the same ones.
+pub fn placeholders(text: &str) -> Vec<&str> {
+    let mut placeholders = PLACEHOLDER
+        .find_iter(text)
+        .map(|m| m.as_str())
+        .collect::<Vec<_>>();
+    placeholders.sort_unstable();
+    placeholders
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceFormat {
+    Json,
+    Yaml,
+    /// gettext `.po` and `.pot`
+    Po,
+    /// Fluent `.ftl`
+    Fluent,
+}
+
+/// A message of a resource file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub key: String,
+    /// The text to translate. It is `msgid` for gettext and the value for the other formats.
+    pub source: String,
+    /// The message in the language of the file. It is `msgstr` for gettext, which is empty if
+    /// it is not translated yet.
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Keep(String),
+    // the top-level key like `en:` of Rails, which is renamed to the target language.
+    Root(String),
+    Value {
+        key: String,
+        prefix: String,
+        suffix: String,
+        style: Style,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Style {
+    YamlPlain,
+    YamlSingle,
+    YamlDouble,
+    // lines of a block scalar like `|`, indented by `indent`.
+    YamlBlock { indent: String },
+    // `msgstr` or `msgstr[n]`.
+    Po,
+    Fluent,
+}
+
+/// A resource file which is rebuilt with translated values. Comments, keys and everything
+/// which is not a message are kept as they are.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Resource {
+    format: ResourceFormat,
+    entries: Vec<Entry>,
+    // JSON is rebuilt from the tree, and the other formats from the pieces.
+    json: Option<(Value, Option<String>)>,
+    pieces: Vec<Piece>,
+}
+
+impl Resource {
+    pub fn parse(format: ResourceFormat, content: &str) -> Result<Self, I18nError> {
+        let mut resource = Self {
+            format,
+            entries: vec![],
+            json: None,
+            pieces: vec![],
+        };
+        match format {
+            ResourceFormat::Json => resource.parse_json(content)?,
+            ResourceFormat::Yaml => resource.parse_yaml(content),
+            ResourceFormat::Po => resource.parse_po(content),
+            ResourceFormat::Fluent => resource.parse_fluent(content),
+        }
+        Ok(resource)
+    }
+    pub fn format(&self) -> ResourceFormat {
+        self.format
+    }
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+    /// Messages which are already in the language of the file.
+    pub fn values(&self) -> HashMap<String, String> {
+        self.entries
+            .iter()
+            .filter(|entry| !entry.value.trim().is_empty())
+            .map(|entry| (entry.key.clone(), entry.value.clone()))
+            .collect()
+    }
+    /// The file with `values` by key. A missing value is the source text, or empty `msgstr`
+    /// for gettext.
+    pub fn render(&self, values: &HashMap<String, String>, lang: &Lang) -> String {
+        let sources = self
+            .entries
+            .iter()
+            .map(|entry| (entry.key.as_str(), entry.source.as_str()))
+            .collect::<HashMap<_, _>>();
+        let value = |key: &str| match values.get(key) {
+            Some(value) => value.as_str(),
+            None if self.format == ResourceFormat::Po => "",
+            None => sources.get(key).copied().unwrap_or_default(),
+        };
+        if let Some((json, root)) = &self.json {
+            let mut json = json.clone();
+            replace_json(&mut json, "", &value);
+            if root.is_some() {
+                json = Value::Object([(lang.tag().to_string(), json)].into_iter().collect());
+            }
+            return format!(
+                "{}\n",
+                serde_json::to_string_pretty(&json).unwrap_or_default()
+            );
+        }
+        let mut rendered = String::new();
+        for piece in &self.pieces {
+            match piece {
+                Piece::Keep(text) => rendered.push_str(text),
+                Piece::Root(rest) => {
+                    rendered.push_str(lang.tag());
+                    rendered.push_str(rest);
+                }
+                Piece::Value {
+                    key,
+                    prefix,
+                    suffix,
+                    style,
+                } => {
+                    rendered.push_str(prefix);
+                    rendered.push_str(&encode(style, value(key)));
+                    rendered.push_str(suffix);
+                }
+            }
+        }
+        if self.format == ResourceFormat::Po {
+            let language = format!(r#""Language: {}\n""#, lang.tag());
+            return PO_LANGUAGE
+                .replace(&rendered, regex::NoExpand(&language))
+                .into_owned();
+        }
+        rendered
+    }
+
+    fn push(&mut self, key: String, source: String, value: String) {
+        self.entries.push(Entry { key, source, value });
+    }
+    fn keep(&mut self, text: &str) {
+        match self.pieces.last_mut() {
+            Some(Piece::Keep(last)) => last.push_str(text),
+            _ => self.pieces.push(Piece::Keep(text.to_string())),
+        }
+    }
+    fn value(&mut self, key: &str, prefix: &str, suffix: &str, style: Style) {
+        self.pieces.push(Piece::Value {
+            key: key.to_string(),
+            prefix: prefix.to_string(),
+            suffix: suffix.to_string(),
+            style,
+        });
+    }
+
+    fn parse_json(&mut self, content: &str) -> Result<(), I18nError> {
+        let json = serde_json::from_str::<Value>(content).context("Invalid JSON")?;
+        let root = match &json {
+            Value::Object(map) if map.len() == 1 => map
+                .iter()
+                .next()
+                .filter(|(key, value)| value.is_object() && is_locale(key))
+                .map(|(key, _)| key.clone()),
+            _ => None,
+        };
+        let json = match &root {
+            Some(root) => json[root].clone(),
+            None => json,
+        };
+        collect_json(&json, "", &mut self.entries);
+        self.json = Some((json, root));
+        Ok(())
+    }
+
+    fn parse_yaml(&mut self, content: &str) {
+        let lines = content.split_inclusive('\n').collect::<Vec<_>>();
+        let top_level = lines
+            .iter()
+            .filter_map(|line| yaml_key(line).filter(|(indent, _, _)| *indent == 0))
+            .collect::<Vec<_>>();
+        let root = match top_level.as_slice() {
+            [(_, key, rest)] if is_locale(key) && yaml_is_parent(rest) => Some(key.clone()),
+            _ => None,
+        };
+        // keys of the parents with their indents.
+        let mut stack: Vec<(usize, String)> = vec![];
+        let mut items: HashMap<String, usize> = HashMap::new();
+        let mut i = 0;
+        while i < lines.len() {
+            let line = lines[i];
+            i += 1;
+            let content = line.trim_end_matches(['\n', '\r']);
+            let trimmed = content.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') || trimmed == "---" {
+                self.keep(line);
+                continue;
+            }
+            let (indent, key, rest, is_item) = match yaml_key(line) {
+                Some((indent, key, rest)) => (indent, key, rest, false),
+                None => {
+                    let indent = content.len() - content.trim_start().len();
+                    let Some(rest) = content[indent..].strip_prefix("- ") else {
+                        self.keep(line);
+                        continue;
+                    };
+                    (indent, String::new(), rest.to_string(), true)
+                }
+            };
+            // a list item may be at the same indent as its parent.
+            stack.retain(|(parent, _)| *parent < indent || (is_item && *parent == indent));
+            let parent = stack
+                .iter()
+                .map(|(_, key)| key.as_str())
+                .filter(|key| Some(*key) != root.as_deref())
+                .collect::<Vec<_>>()
+                .join(".");
+            let key = if is_item {
+                let index = items.entry(parent.clone()).or_default();
+                *index += 1;
+                (*index - 1).to_string()
+            } else {
+                key
+            };
+            let path = if parent.is_empty() {
+                key.clone()
+            } else {
+                format!("{}.{}", parent, key)
+            };
+            let value = rest.trim_start();
+            let prefix = &content[..content.len() - value.len()];
+            if !is_item && yaml_is_parent(value) {
+                if indent == 0 && root.as_deref() == Some(key.as_str()) {
+                    self.pieces.push(Piece::Root(line[key.len()..].to_string()));
+                } else {
+                    self.keep(line);
+                }
+                stack.push((indent, key));
+                continue;
+            }
+            let eol = &line[content.len()..];
+            if value.starts_with(['|', '>']) {
+                let block = lines[i..]
+                    .iter()
+                    .take_while(|l| {
+                        let l = l.trim_end_matches(['\n', '\r']);
+                        l.trim().is_empty() || l.len() - l.trim_start().len() > indent
+                    })
+                    .count();
+                // trailing blank lines are not a part of the value.
+                let block = lines[i..i + block]
+                    .iter()
+                    .rposition(|l| !l.trim().is_empty())
+                    .map_or(0, |last| last + 1);
+                let body = &lines[i..i + block];
+                let block_indent = body
+                    .iter()
+                    .filter(|l| !l.trim().is_empty())
+                    .map(|l| l.len() - l.trim_start().len())
+                    .min()
+                    .unwrap_or(indent + 2);
+                let text = body
+                    .iter()
+                    .map(|l| {
+                        l.trim_end_matches(['\n', '\r'])
+                            .get(block_indent..)
+                            .unwrap_or_default()
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                self.value(
+                    &path,
+                    line,
+                    "\n",
+                    Style::YamlBlock {
+                        indent: " ".repeat(block_indent),
+                    },
+                );
+                self.push(path, text.clone(), text);
+                i += block;
+                continue;
+            }
+            // a mapping in a list is not supported.
+            let is_mapping = is_item && (value.contains(": ") || value.ends_with(':'));
+            let Some((text, style, end)) = yaml_scalar(value).filter(|_| !is_mapping) else {
+                self.keep(line);
+                continue;
+            };
+            self.value(&path, prefix, &format!("{}{}", &value[end..], eol), style);
+            self.push(path, text.clone(), text);
+        }
+    }
+
+    fn parse_po(&mut self, content: &str) {
+        let lines = content.split_inclusive('\n').collect::<Vec<_>>();
+        let mut i = 0;
+        while i < lines.len() {
+            if lines[i].trim().is_empty() {
+                self.keep(lines[i]);
+                i += 1;
+                continue;
+            }
+            let size = lines[i..]
+                .iter()
+                .take_while(|l| !l.trim().is_empty())
+                .count();
+            self.parse_po_entry(&lines[i..i + size]);
+            i += size;
+        }
+    }
+    fn parse_po_entry(&mut self, lines: &[&str]) {
+        // (keyword, raw lines, decoded string). comments have no keyword.
+        let mut fields: Vec<(&str, String, String)> = vec![];
+        for line in lines {
+            let trimmed = line.trim();
+            if trimmed.starts_with('"')
+                && let Some((keyword, raw, text)) = fields.last_mut()
+                && !keyword.is_empty()
+            {
+                raw.push_str(line);
+                text.push_str(&po_decode(trimmed));
+                continue;
+            }
+            if trimmed.starts_with('#') {
+                fields.push(("", line.to_string(), String::new()));
+                continue;
+            }
+            let (keyword, string) = trimmed.split_once(' ').unwrap_or((trimmed, ""));
+            fields.push((keyword, line.to_string(), po_decode(string.trim())));
+        }
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(keyword, _, _)| *keyword == name)
+                .map(|(_, _, text)| text.clone())
+        };
+        let msgid = field("msgid").unwrap_or_default();
+        let plural = field("msgid_plural");
+        let key = match field("msgctxt") {
+            Some(context) => format!("{}|{}", context, msgid),
+            None => msgid.clone(),
+        };
+        for (keyword, raw, text) in &fields {
+            let index = keyword
+                .strip_prefix("msgstr")
+                .map(|rest| rest.trim_matches(['[', ']']).parse::<usize>().unwrap_or(0));
+            let Some(index) = index.filter(|_| !msgid.is_empty()) else {
+                self.keep(raw);
+                continue;
+            };
+            let (key, source) = match (index, &plural) {
+                (0, _) | (_, None) => (key.clone(), msgid.clone()),
+                (n, Some(plural)) => (format!("{}[{}]", key, n), plural.clone()),
+            };
+            self.value(&key, &format!("{} ", keyword), "\n", Style::Po);
+            self.push(key, source, text.clone());
+        }
+    }
+
+    fn parse_fluent(&mut self, content: &str) {
+        let lines = content.split_inclusive('\n').collect::<Vec<_>>();
+        let mut message = None;
+        let mut i = 0;
+        while i < lines.len() {
+            let line = lines[i];
+            i += 1;
+            let (key, start) = if let Some(caps) = FLUENT_MESSAGE.captures(line) {
+                message = Some(caps[1].to_string());
+                (caps[1].to_string(), caps[0].len())
+            } else if let Some(caps) = FLUENT_ATTRIBUTE.captures(line)
+                && let Some(message) = &message
+            {
+                (format!("{}.{}", message, &caps[1]), caps[0].len())
+            } else {
+                if !line.starts_with([' ', '\t']) {
+                    message = None;
+                }
+                self.keep(line);
+                continue;
+            };
+            // indented lines which are not attributes continue the value.
+            let continuation = lines[i..]
+                .iter()
+                .take_while(|l| {
+                    l.starts_with([' ', '\t'])
+                        && !l.trim().is_empty()
+                        && !FLUENT_ATTRIBUTE.is_match(l)
+                })
+                .count();
+            let first = line[start..].trim_end();
+            let text = std::iter::once(first)
+                .filter(|first| !first.is_empty())
+                .chain(lines[i..i + continuation].iter().map(|l| l.trim()))
+                .collect::<Vec<_>>()
+                .join("\n");
+            if text.is_empty() {
+                self.keep(line);
+                continue;
+            }
+            let prefix = line[..start].trim_end();
+            self.value(&key, prefix, "\n", Style::Fluent);
+            self.push(key, text.clone(), text);
+            i += continuation;
+        }
+    }
+}
+
+impl ResourceFormat {
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = std::path::Path::new(path).extension()?.to_str()?;
+        Some(match extension {
+            "json" => Self::Json,
+            "yaml" | "yml" => Self::Yaml,
+            "po" | "pot" => Self::Po,
+            "ftl" => Self::Fluent,
+            _ => return None,
+        })
+    }
+}
+
+// a single top-level key like `en` or `ja` is the locale, not a part of the message keys.
+fn is_locale(key: &str) -> bool {
+    key.parse::<Lang>().is_ok_and(|lang| lang.is_known())
+}
+
+fn collect_json(json: &Value, path: &str, entries: &mut Vec<Entry>) {
+    let join = |key: &str| {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", path, key)
+        }
+    };
+    match json {
+        Value::String(text) => entries.push(Entry {
+            key: path.to_string(),
+            source: text.clone(),
+            value: text.clone(),
+        }),
+        Value::Object(map) => map
+            .iter()
+            .for_each(|(key, value)| collect_json(value, &join(key), entries)),
+        Value::Array(items) => items
+            .iter()
+            .enumerate()
+            .for_each(|(i, value)| collect_json(value, &join(&i.to_string()), entries)),
+        _ => {}
+    }
+}
+
+fn replace_json<'a>(json: &mut Value, path: &str, value: &impl Fn(&str) -> &'a str) {
+    let join = |key: &str| {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", path, key)
+        }
+    };
+    match json {
+        Value::String(text) => *text = value(path).to_string(),
+        Value::Object(map) => map
+            .iter_mut()
+            .for_each(|(key, child)| replace_json(child, &join(key), value)),
+        Value::Array(items) => items
+            .iter_mut()
+            .enumerate()
+            .for_each(|(i, child)| replace_json(child, &join(&i.to_string()), value)),
+        _ => {}
+    }
+}
+
+// `key: rest` with its indent. The key may be quoted.
+fn yaml_key(line: &str) -> Option<(usize, String, String)> {
+    let content = line.trim_end_matches(['\n', '\r']);
+    let indent = content.len() - content.trim_start().len();
+    let body = &content[indent..];
+    if body.starts_with(['#', '-']) {
+        return None;
+    }
+    let (key, rest) = match body.chars().next()? {
+        quote @ ('"' | '\'') => {
+            let end = body[1..].find(quote)? + 1;
+            (body[1..end].to_string(), body[end + 1..].strip_prefix(':')?)
+        }
+        _ => {
+            let colon = body
+                .char_indices()
+                .find(|&(i, c)| {
+                    c == ':' && (i + 1 == body.len() || body[i + 1..].starts_with([' ', '\t']))
+                })
+                .map(|(i, _)| i)?;
+            (body[..colon].trim_end().to_string(), &body[colon + 1..])
+        }
+    };
+    if key.is_empty() || key.contains(" #") {
+        return None;
+    }
+    Some((indent, key, rest.to_string()))
+}
+
+// an empty value or a comment starts a mapping or a list.
+fn yaml_is_parent(rest: &str) -> bool {
+    let rest = rest.trim();
+    rest.is_empty() || rest.starts_with('#')
+}
+
+// the decoded scalar, its style and where it ends. Anchors, flow collections and values like
+// numbers are not messages.
+fn yaml_scalar(value: &str) -> Option<(String, Style, usize)> {
+    match value.chars().next()? {
+        '"' => {
+            let mut escaped = false;
+            let end = value[1..].char_indices().find_map(|(i, c)| {
+                let end = (c == '"' && !escaped).then_some(i + 2);
+                escaped = c == '\\' && !escaped;
+                end
+            })?;
+            let text = serde_json::from_str::<String>(&value[..end]).ok()?;
+            Some((text, Style::YamlDouble, end))
+        }
+        '\'' => {
+            let mut end = 1;
+            loop {
+                end += value[end..].find('\'')? + 1;
+                if !value[end..].starts_with('\'') {
+                    break;
+                }
+                end += 1;
+            }
+            let text = value[1..end - 1].replace("''", "'");
+            Some((text, Style::YamlSingle, end))
+        }
+        '&' | '*' | '[' | '{' | '!' => None,
+        _ => {
+            let end = value.find(" #").unwrap_or(value.len());
+            let text = value[..end].trim_end();
+            let is_literal = matches!(
+                text,
+                "true" | "false" | "yes" | "no" | "on" | "off" | "null" | "~"
+            ) || text.parse::<f64>().is_ok();
+            (!is_literal).then(|| (text.to_string(), Style::YamlPlain, text.len()))
+        }
+    }
+}
+
+fn yaml_plain_is_safe(text: &str) -> bool {
+    !text.is_empty()
+        && text.trim() == text
+        && !text.contains(['\n', '\t'])
+        && !text.contains(": ")
+        && !text.contains(" #")
+        && !text.ends_with(':')
+        && !text.starts_with([
+            '-', '?', ':', ',', '[', ']', '{', '}', '#', '&', '*', '!', '|', '>', '\'', '"', '%',
+            '@', '`',
+        ])
+        && yaml_scalar(text).is_some_and(|(decoded, _, _)| decoded == text)
+}
+
+fn po_decode(quoted: &str) -> String {
+    serde_json::from_str::<String>(quoted).unwrap_or_else(|_| quoted.trim_matches('"').to_string())
+}
+
+fn po_encode(text: &str) -> String {
+    let escape = |line: &str| {
+        line.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\t', "\\t")
+            .replace('\n', "\\n")
+    };
+    let lines = text.split_inclusive('\n').collect::<Vec<_>>();
+    if lines.len() <= 1 {
+        return format!("\"{}\"", escape(text));
+    }
+    // multi-line messages are written like xgettext.
+    let mut encoded = "\"\"".to_string();
+    for line in lines {
+        encoded.push_str(&format!("\n\"{}\"", escape(line)));
+    }
+    encoded
+}
+
+fn encode(style: &Style, text: &str) -> String {
+    match style {
+        Style::YamlPlain if yaml_plain_is_safe(text) => text.to_string(),
+        Style::YamlSingle if !text.contains('\n') => format!("'{}'", text.replace('\'', "''")),
+        Style::YamlPlain | Style::YamlSingle | Style::YamlDouble => {
+            serde_json::to_string(text).unwrap_or_default()
+        }
+        Style::YamlBlock { indent } => text
+            .lines()
+            .map(|line| {
+                if line.is_empty() {
+                    String::new()
+                } else {
+                    format!("{}{}", indent, line)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Style::Po => po_encode(text),
+        Style::Fluent if !text.contains('\n') => format!(" {}", text),
+        Style::Fluent => text.lines().map(|line| format!("\n    {}", line)).collect(),
+    }
+}
+
+/// A translated resource file.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct LocaleFile {
+    pub content: String,
+    pub translated: usize,
+    /// Messages which were already translated in the existing file.
+    pub kept: usize,
+    /// Keys whose translation could not be read or did not keep the placeholders. They are left
+    /// untranslated.
+    pub failed: Vec<String>,
+}
+
+/// Translates the messages of the resource into a file for `target_lang`. Messages which are
+/// in `existing`, like the current file of the target language, are kept unless they are still
+/// the source text, which is what a failed message is written as.
+pub async fn translate_resource<AI: GenerativeAIInterface>(
+    ai: &AI,
+    resource: &Resource,
+    existing: Option<&Resource>,
+    source_lang: Option<Lang>,
+    target_lang: &Lang,
+) -> Result<LocaleFile, AIError> {
+    let existing = existing.map(Resource::values).unwrap_or_default();
+    let mut file = LocaleFile::default();
+    let mut values = HashMap::new();
+    let mut pending = vec![];
+    for entry in resource.entries() {
+        if let Some(value) = existing
+            .get(&entry.key)
+            .filter(|value| **value != entry.source)
+        {
+            values.insert(entry.key.clone(), value.clone());
+            file.kept += 1;
+        } else if entry.source.chars().any(char::is_alphabetic) {
+            pending.push(entry);
+        } else if !entry.source.is_empty() {
+            values.insert(entry.key.clone(), entry.source.clone());
+        }
+    }
+    let source_lang = match source_lang {
+        Some(lang) => Some(lang),
+        None if pending.is_empty() => None,
+        None => {
+            let sample = pending
+                .iter()
+                .map(|entry| entry.source.as_str())
+                .collect::<Vec<_>>();
+            detect_lang(ai, &sample.join("\n")).await?
+        }
+    };
+    // the second try asks to keep the placeholders which were lost in the first one.
+    for instruction in [INSTRUCTION, RETRY_INSTRUCTION] {
+        if pending.is_empty() {
+            break;
+        }
+        let keys = pending.iter().map(|e| e.key.clone()).collect::<Vec<_>>();
+        let texts = pending.iter().map(|e| e.source.clone()).collect::<Vec<_>>();
+        let translated = translate_keyed(
+            ai,
+            &keys,
+            &texts,
+            source_lang.as_ref(),
+            target_lang,
+            instruction,
+        )
+        .await?;
+        let mut lost = vec![];
+        for (entry, translated) in pending.into_iter().zip(translated) {
+            match translated {
+                Some(translated) if placeholders(&entry.source) == placeholders(&translated) => {
+                    values.insert(entry.key.clone(), translated);
+                    file.translated += 1;
+                }
+                _ => lost.push(entry),
+            }
+        }
+        pending = lost;
+    }
+    file.failed = pending.iter().map(|entry| entry.key.clone()).collect();
+    file.content = resource.render(&values, target_lang);
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Prompt, clients::mocks::FakeAI};
+
+    fn ja() -> Lang {
+        "ja".parse().unwrap()
+    }
+    fn upper(resource: &Resource) -> HashMap<String, String> {
+        resource
+            .entries()
+            .iter()
+            .map(|e| (e.key.clone(), e.source.to_uppercase()))
+            .collect()
+    }
+
+    #[test]
+    fn placeholders_are_found() {
+        assert_eq!(
+            placeholders("Hi {name}, %s has {{count}} %1$d { $n } <b>x</b> 100%"),
+            ["%1$d", "%s", "</b>", "<b>", "{ $n }", "{name}", "{{count}}"]
+        );
+    }
+    #[test]
+    fn json_and_yaml_keep_keys_and_rename_locale_root() {
+        let json = Resource::parse(
+            ResourceFormat::Json,
+            r#"{"en": {"title": "Hello", "count": 3, "items": ["a {x}"]}}"#,
+        )
+        .unwrap();
+        let yaml = Resource::parse(
+            ResourceFormat::Yaml,
+            "# comment\nen:\n  title: Hello # greeting\n  quoted: 'it''s'\n  list:\n  - one\n  body: |\n    line 1\n    line 2\n  enabled: true\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            json.entries()
+                .iter()
+                .map(|e| e.key.as_str())
+                .collect::<Vec<_>>(),
+            ["title", "items.0"]
+        );
+        assert_eq!(
+            json.render(&upper(&json), &ja()),
+            "{\n  \"ja\": {\n    \"title\": \"HELLO\",\n    \"count\": 3,\n    \"items\": [\n      \"A {X}\"\n    ]\n  }\n}\n"
+        );
+        assert_eq!(
+            yaml.entries()
+                .iter()
+                .map(|e| e.key.as_str())
+                .collect::<Vec<_>>(),
+            ["title", "quoted", "list.0", "body"]
+        );
+        assert_eq!(
+            yaml.render(&upper(&yaml), &ja()),
+            "# comment\nja:\n  title: HELLO # greeting\n  quoted: 'IT''S'\n  list:\n  - ONE\n  body: |\n    LINE 1\n    LINE 2\n  enabled: true\n"
+        );
+    }
+    #[test]
+    fn po_and_fluent_fill_translations() {
+        let po = Resource::parse(
+            ResourceFormat::Po,
+            "msgid \"\"\nmsgstr \"\"\n\"Language: en\\n\"\n\n#: src/main.rs:1\nmsgctxt \"menu\"\nmsgid \"Open\"\nmsgstr \"\"\n\nmsgid \"%d file\"\nmsgid_plural \"%d files\"\nmsgstr[0] \"\"\nmsgstr[1] \"\"\n",
+        )
+        .unwrap();
+        let ftl = Resource::parse(
+            ResourceFormat::Fluent,
+            "# Menu\nopen = Open { $name }\nlogin =\n    .placeholder = Email\nabout =\n    About\n    us\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            po.entries()
+                .iter()
+                .map(|e| (e.key.as_str(), e.source.as_str()))
+                .collect::<Vec<_>>(),
+            [
+                ("menu|Open", "Open"),
+                ("%d file", "%d file"),
+                ("%d file[1]", "%d files")
+            ]
+        );
+        assert_eq!(
+            po.render(&upper(&po), &ja()),
+            "msgid \"\"\nmsgstr \"\"\n\"Language: ja\\n\"\n\n#: src/main.rs:1\nmsgctxt \"menu\"\nmsgid \"Open\"\nmsgstr \"OPEN\"\n\nmsgid \"%d file\"\nmsgid_plural \"%d files\"\nmsgstr[0] \"%D FILE\"\nmsgstr[1] \"%D FILES\"\n"
+        );
+        assert_eq!(
+            ftl.render(&upper(&ftl), &ja()),
+            "# Menu\nopen = OPEN { $NAME }\nlogin =\n    .placeholder = EMAIL\nabout =\n    ABOUT\n    US\n"
+        );
+    }
+    #[tokio::test]
+    async fn unread_messages_fail_and_are_translated_again() {
+        let resource = Resource::parse(
+            ResourceFormat::Json,
+            r#"{"title": "Hello", "body": "World"}"#,
+        )
+        .unwrap();
+        // translates only "Hello", and answers the other requests with prose.
+        let ai = FakeAI::new(|prompt: Prompt| {
+            let question = prompt.messages()[0].content().to_string();
+            Ok(if question.ends_with(r#"{"title":"Hello"}"#) {
+                r#"{"title": "こんにちは"}"#.to_string()
+            } else {
+                "Sorry.".to_string()
+            })
+        });
+        let en = Some("en".parse().unwrap());
+
+        let first = translate_resource(&ai, &resource, None, en.clone(), &ja())
+            .await
+            .unwrap();
+        let existing = Resource::parse(ResourceFormat::Json, &first.content).unwrap();
+        let second = translate_resource(&ai, &resource, Some(&existing), en, &ja())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            (first.translated, first.failed.clone()),
+            (1, vec!["body".to_string()])
+        );
+        assert_eq!((second.kept, second.failed), (1, vec!["body".to_string()]));
+    }
+}
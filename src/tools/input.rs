@@ -0,0 +1,158 @@
+use std::{
+    io::{IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use ignore::{WalkBuilder, overrides::OverrideBuilder};
+
+/// The path that means "read from stdin".
+pub const STDIN_PATH: &str = "-";
+
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub struct InputError(anyhow::Error);
+crate::impl_from_error!(InputError);
+
+/// Reads stdin if it is piped. Returns `None` for a terminal or empty input.
+pub fn read_piped_stdin() -> Result<Option<String>, InputError> {
+    let stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        return Ok(None);
+    }
+    let mut content = String::new();
+    stdin
+        .lock()
+        .read_to_string(&mut content)
+        .context("Failed to read stdin")?;
+    if content.trim().is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(content))
+}
+
+/// Reads the file, or stdin if the path is `-`.
+pub fn read_path_or_stdin(path: &str) -> Result<String, InputError> {
+    if path != STDIN_PATH {
+        return Ok(std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read file: {}", path))?);
+    }
+    let mut content = String::new();
+    std::io::stdin()
+        .lock()
+        .read_to_string(&mut content)
+        .context("Failed to read stdin")?;
+    Ok(content)
+}
+
+/// Expands files, directories and glob patterns like `src/**/*.rs` into sorted files.
+/// `.gitignore` is respected when walking, and binary files are skipped.
+pub fn collect_files(inputs: &[String]) -> Result<Vec<PathBuf>, InputError> {
+    let mut files = vec![];
+    for input in inputs {
+        let path = Path::new(input);
+        if path.is_file() {
+            files.push(path.to_path_buf());
+            continue;
+        }
+        let walk = if path.is_dir() {
+            WalkBuilder::new(path).build()
+        } else {
+            let overrides = OverrideBuilder::new(".")
+                .add(input)
+                .and_then(|builder| builder.build())
+                .with_context(|| format!("Invalid path or glob: {}", input))?;
+            WalkBuilder::new(".").overrides(overrides).build()
+        };
+        let before = files.len();
+        for entry in walk {
+            let entry = entry.context("Failed to walk directory")?;
+            if entry.file_type().is_some_and(|t| t.is_file()) {
+                let path = entry.into_path();
+                files.push(
+                    path.strip_prefix(".")
+                        .map(Path::to_path_buf)
+                        .unwrap_or(path),
+                );
+            }
+        }
+        if files.len() == before {
+            return Err(anyhow::anyhow!("No files match: {}", input).into());
+        }
+    }
+    files.retain(|file| !is_binary(file));
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+// a NUL byte in the head of the file is how git detects binaries too.
+fn is_binary(path: &Path) -> bool {
+    let Ok(file) = std::fs::File::open(path) else {
+        return true;
+    };
+    let mut head = Vec::with_capacity(8000);
+    if file.take(8000).read_to_end(&mut head).is_err() {
+        return true;
+    }
+    head.contains(&0)
+}
+
+/// Asks a yes/no question on the terminal. Returns false if stdin is not a terminal.
+pub fn confirm(question: &str) -> bool {
+    let stdin = std::io::stdin();
+    if !stdin.is_terminal() {
+        eprintln!("{} skipped because stdin is not a terminal", question);
+        return false;
+    }
+    eprint!("{} [y/N] ", question);
+    let _ = std::io::stderr().flush();
+    let mut answer = String::new();
+    if stdin.read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+/// Appends the context to the question in a block whose end is clear to the model.
+pub fn attach_context(question: &str, name: &str, context: &str) -> String {
+    format!(
+        "{}\n\n<{name}>\n{}\n</{name}>",
+        question,
+        context.trim_end_matches('\n'),
+        name = name
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn directories_are_walked_without_ignored_and_binary_files() {
+        let dir = std::env::temp_dir().join(format!("cai-collect-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        // the walker reads .gitignore only inside a git repository.
+        std::fs::create_dir_all(dir.join(".git")).unwrap();
+        std::fs::write(dir.join(".gitignore"), "target/\n").unwrap();
+        std::fs::create_dir_all(dir.join("target")).unwrap();
+        std::fs::write(dir.join("target/out.rs"), "fn x() {}").unwrap();
+        std::fs::write(dir.join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(dir.join("src/logo.png"), [0x89, 0x50, 0x00, 0x01]).unwrap();
+        let main = dir.join("src/main.rs").to_string_lossy().to_string();
+
+        let files = collect_files(&[dir.to_string_lossy().to_string(), main]).unwrap();
+
+        assert_eq!(files, vec![dir.join("src/main.rs")]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn context_is_wrapped_in_named_block() {
+        let sut = attach_context("write a commit message", "stdin", "diff --git a b\n+x\n\n");
+
+        assert_eq!(
+            sut,
+            "write a commit message\n\n<stdin>\ndiff --git a b\n+x\n</stdin>"
+        );
+    }
+}
@@ -0,0 +1,219 @@
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::Context;
+
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub struct LangError(anyhow::Error);
+crate::impl_from_error!(LangError);
+
+/// A BCP-47 language tag like `ja`, `en-US` or `zh-Hant-TW`, normalized to the recommended case.
+/// Serialized as the tag.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize)]
+#[serde(transparent)]
+pub struct Lang {
+    tag: String,
+}
+
+impl Lang {
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+    /// The primary language subtag, like `zh` for `zh-Hant-TW`.
+    pub fn language(&self) -> &str {
+        self.tag.split('-').next().unwrap_or_default()
+    }
+    /// True if the language is in the table of names, so the tag is not just any word.
+    pub fn is_known(&self) -> bool {
+        language_name(self.language()).is_some()
+    }
+    /// English name like "Chinese (Traditional, Taiwan)". Unknown subtags are left out, and
+    /// the tag itself is returned if the language is unknown.
+    pub fn display_name(&self) -> String {
+        let Some(language) = language_name(self.language()) else {
+            return self.tag.clone();
+        };
+        let details = self
+            .tag
+            .split('-')
+            .skip(1)
+            // subtags after a singleton are extensions.
+            .take_while(|subtag| subtag.len() > 1)
+            .filter_map(|subtag| script_name(subtag).or_else(|| region_name(subtag)))
+            .collect::<Vec<_>>();
+        if details.is_empty() {
+            return language.to_string();
+        }
+        format!("{} ({})", language, details.join(", "))
+    }
+    /// How the language is written in prompts, like "Japanese (ja)".
+    pub fn to_prompt(&self) -> String {
+        let name = self.display_name();
+        if name == self.tag {
+            return name;
+        }
+        format!("{} ({})", name, self.tag)
+    }
+}
+
+impl FromStr for Lang {
+    type Err = LangError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse(s)
+            .with_context(|| format!("Invalid language tag: {}", s))
+            .map_err(Into::into)
+    }
+}
+
+fn parse(s: &str) -> anyhow::Result<Lang> {
+    let mut subtags = vec![];
+    let mut in_extension = false;
+    for (i, subtag) in s.trim().split(['-', '_']).enumerate() {
+        let valid =
+            (1..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric());
+        anyhow::ensure!(valid, "`{}` is not a subtag", subtag);
+        let is_alpha = subtag.chars().all(|c| c.is_ascii_alphabetic());
+        if i == 0 {
+            anyhow::ensure!(
+                is_alpha && subtag.len() != 1 && subtag.len() != 4,
+                "`{}` is not a language",
+                subtag
+            );
+        }
+        in_extension |= i > 0 && subtag.len() == 1;
+        let subtag = match subtag.len() {
+            _ if i == 0 || in_extension => subtag.to_ascii_lowercase(),
+            2 if is_alpha => subtag.to_ascii_uppercase(),
+            4 if is_alpha => {
+                let (first, rest) = subtag.split_at(1);
+                first.to_ascii_uppercase() + &rest.to_ascii_lowercase()
+            }
+            _ => subtag.to_ascii_lowercase(),
+        };
+        subtags.push(subtag);
+    }
+    Ok(Lang {
+        tag: subtags.join("-"),
+    })
+}
+
+impl Display for Lang {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.tag)
+    }
+}
+
+fn language_name(language: &str) -> Option<&'static str> {
+    Some(match language {
+        "ar" => "Arabic",
+        "bn" => "Bengali",
+        "cs" => "Czech",
+        "da" => "Danish",
+        "de" => "German",
+        "el" => "Greek",
+        "en" => "English",
+        "es" => "Spanish",
+        "fa" => "Persian",
+        "fi" => "Finnish",
+        "fr" => "French",
+        "he" => "Hebrew",
+        "hi" => "Hindi",
+        "hu" => "Hungarian",
+        "id" => "Indonesian",
+        "it" => "Italian",
+        "ja" => "Japanese",
+        "ko" => "Korean",
+        "ms" => "Malay",
+        "nb" => "Norwegian Bokmål",
+        "nl" => "Dutch",
+        "no" => "Norwegian",
+        "pl" => "Polish",
+        "pt" => "Portuguese",
+        "ro" => "Romanian",
+        "ru" => "Russian",
+        "sv" => "Swedish",
+        "sw" => "Swahili",
+        "ta" => "Tamil",
+        "th" => "Thai",
+        "tl" => "Tagalog",
+        "tr" => "Turkish",
+        "uk" => "Ukrainian",
+        "ur" => "Urdu",
+        "vi" => "Vietnamese",
+        "zh" => "Chinese",
+        _ => return None,
+    })
+}
+
+fn script_name(script: &str) -> Option<&'static str> {
+    Some(match script {
+        "Arab" => "Arabic",
+        "Cyrl" => "Cyrillic",
+        "Hans" => "Simplified",
+        "Hant" => "Traditional",
+        "Latn" => "Latin",
+        _ => return None,
+    })
+}
+
+fn region_name(region: &str) -> Option<&'static str> {
+    Some(match region {
+        "419" => "Latin America",
+        "AU" => "Australia",
+        "BR" => "Brazil",
+        "CA" => "Canada",
+        "CH" => "Switzerland",
+        "CN" => "China",
+        "DE" => "Germany",
+        "ES" => "Spain",
+        "FR" => "France",
+        "GB" => "United Kingdom",
+        "HK" => "Hong Kong",
+        "IN" => "India",
+        "JP" => "Japan",
+        "KR" => "South Korea",
+        "MX" => "Mexico",
+        "PT" => "Portugal",
+        "SG" => "Singapore",
+        "TW" => "Taiwan",
+        "US" => "United States",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags_are_normalized_and_named() {
+        let tags = ["JA", "en_us", "zh-hant-tw", "es-419", "de-CH-x-Foo", "tlh"];
+
+        let langs = tags
+            .iter()
+            .map(|tag| tag.parse::<Lang>().unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            langs.iter().map(Lang::tag).collect::<Vec<_>>(),
+            ["ja", "en-US", "zh-Hant-TW", "es-419", "de-CH-x-foo", "tlh"]
+        );
+        assert_eq!(
+            langs.iter().map(Lang::to_prompt).collect::<Vec<_>>(),
+            [
+                "Japanese (ja)",
+                "English (United States) (en-US)",
+                "Chinese (Traditional, Taiwan) (zh-Hant-TW)",
+                "Spanish (Latin America) (es-419)",
+                "German (Switzerland) (de-CH-x-foo)",
+                "tlh"
+            ]
+        );
+    }
+    #[test]
+    fn invalid_tags_are_rejected() {
+        for tag in ["", "e", "1a", "en--US", "en-toolongsubtag", "日本語"] {
+            assert!(tag.parse::<Lang>().is_err(), "{}", tag);
+        }
+    }
+}
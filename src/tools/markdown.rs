@@ -0,0 +1,502 @@
+use std::sync::LazyLock;
+
+use regex::Regex;
+
+use crate::tools::translator::TranslatedDocument;
+
+static FENCE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[ \t]{0,3}(`{3,}|~{3,})").unwrap());
+static THEMATIC_BREAK: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^[ \t]{0,3}(?:(?:\*[ \t]*){3,}|(?:-[ \t]*){3,}|(?:_[ \t]*){3,})$").unwrap()
+});
+static SETEXT_UNDERLINE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[ \t]{0,3}(?:=+|-+)[ \t]*$").unwrap());
+static TABLE_DELIMITER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^[ \t]*\|?(?:[ \t]*:?-+:?[ \t]*\|)*[ \t]*:?-+:?[ \t]*\|?[ \t]*$").unwrap()
+});
+static HTML_BLOCK: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[ \t]{0,3}<[A-Za-z/!?]").unwrap());
+static REFERENCE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[ \t]{0,3}\[[^\]^][^\]]*\]:[ \t]").unwrap());
+static QUOTE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"^[ \t]{0,3}>[ \t]?").unwrap());
+static HEADING: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^[ \t]{0,3}#{1,6}(?:[ \t]+|$)").unwrap());
+static CLOSING_HASHES: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[ \t]+#+[ \t]*$").unwrap());
+// list items with an optional task box, and footnote definitions.
+static ITEM: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"^(?:[ \t]*(?:[-*+]|\d{1,9}[.)])(?:[ \t]+\[[ xX]\])?|[ \t]{0,3}\[\^[^\]]+\]:)(?:[ \t]+|$)",
+    )
+    .unwrap()
+});
+// the text of a link or an image is translated, and the rest is protected.
+static LINK: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(!?\[)([^\[\]]*)(\]\([^()\s]*(?:[ \t]+"[^"]*")?\)|\]\[[^\[\]]*\])"#).unwrap()
+});
+static INLINE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r#"<!--.*?-->|</?[A-Za-z][^<>]*>|<https?://[^<>]+>|https?://[^\s<>()⟦⟧]*[^\s<>()⟦⟧.,;:!?'"]|\[\^[^\]]+\]|\{#[^}]+\}"#,
+    )
+    .unwrap()
+});
+
+/// Prose of a markdown document, with the parts which must not be translated, like inline code
+/// and URLs, replaced by placeholders like `⟦0⟧`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prose {
+    source: String,
+    masked: String,
+    protected: Vec<String>,
+}
+
+impl Prose {
+    pub fn new(source: &str) -> Self {
+        let mut protected = vec![];
+        let mut masked = mask_code_spans(source, &mut protected);
+        while LINK.is_match(&masked) {
+            masked = LINK
+                .replace_all(&masked, |caps: &regex::Captures| {
+                    let open = placeholder(&caps[1], &mut protected);
+                    let close = placeholder(&caps[3], &mut protected);
+                    format!("{}{}{}", open, &caps[2], close)
+                })
+                .into_owned();
+        }
+        let masked = INLINE
+            .replace_all(&masked, |caps: &regex::Captures| {
+                placeholder(&caps[0], &mut protected)
+            })
+            .into_owned();
+        Self {
+            source: source.to_string(),
+            masked,
+            protected,
+        }
+    }
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+    /// The text which is sent to the model.
+    pub fn masked(&self) -> &str {
+        &self.masked
+    }
+    /// Puts the protected parts back. `None` if a placeholder is lost or duplicated.
+    pub fn restore(&self, translated: &str) -> Option<String> {
+        // a blank line would split the paragraph, and a heading or a cell must be one line.
+        let separator = if self.source.contains('\n') {
+            "\n"
+        } else {
+            " "
+        };
+        let mut restored = translated
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join(separator);
+        for i in 0..self.protected.len() {
+            let token = format!("⟦{}⟧", i);
+            if restored.matches(&token).count() != self.masked.matches(&token).count() {
+                return None;
+            }
+        }
+        // a protected part may contain an earlier placeholder, so they are put back from the last.
+        for (i, part) in self.protected.iter().enumerate().rev() {
+            restored = restored.replace(&format!("⟦{}⟧", i), part);
+        }
+        Some(restored)
+    }
+    // prose which is only code, links or symbols is kept as it is.
+    fn is_translatable(&self) -> bool {
+        let mut text = self.masked.clone();
+        for i in 0..self.protected.len() {
+            text = text.replace(&format!("⟦{}⟧", i), "");
+        }
+        text.chars().any(char::is_alphabetic)
+    }
+}
+
+fn placeholder(part: &str, protected: &mut Vec<String>) -> String {
+    protected.push(part.to_string());
+    format!("⟦{}⟧", protected.len() - 1)
+}
+
+// code spans are found by hand because the closing backticks must be as many as the opening.
+fn mask_code_spans(source: &str, protected: &mut Vec<String>) -> String {
+    let mut masked = String::new();
+    let mut rest = source;
+    while let Some(start) = rest.find('`') {
+        let escaped = rest[..start].ends_with('\\');
+        let run = rest[start..].len() - rest[start..].trim_start_matches('`').len();
+        let after = &rest[start + run..];
+        let close = (!escaped).then(|| find_run(after, run)).flatten();
+        masked.push_str(&rest[..start]);
+        match close {
+            Some(end) => {
+                masked.push_str(&placeholder(
+                    &rest[start..start + run + end + run],
+                    protected,
+                ));
+                rest = &after[end + run..];
+            }
+            None => {
+                masked.push_str(&rest[start..start + run]);
+                rest = after;
+            }
+        }
+    }
+    masked.push_str(rest);
+    masked
+}
+
+// the position of a run of exactly `len` backticks.
+fn find_run(text: &str, len: usize) -> Option<usize> {
+    let mut offset = 0;
+    while let Some(start) = text[offset..].find('`') {
+        let start = offset + start;
+        let run = text[start..].len() - text[start..].trim_start_matches('`').len();
+        if run == len {
+            return Some(start);
+        }
+        offset = start + run;
+    }
+    None
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    Keep(String),
+    Prose(Prose),
+}
+
+/// A markdown document split into prose to translate and everything else, like code blocks,
+/// front matter, HTML and the markup of headings, lists and tables, which is kept as it is.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MarkdownDocument {
+    segments: Vec<Segment>,
+    // the pending paragraph and the line ending after it.
+    paragraph: Option<(String, String)>,
+}
+
+impl MarkdownDocument {
+    pub fn parse(source: &str) -> Self {
+        let lines = source
+            .split_inclusive('\n')
+            .map(split_line_ending)
+            .collect::<Vec<_>>();
+        let mut doc = Self::default();
+        let mut i = front_matter_end(&lines).unwrap_or(0);
+        for (line, end) in &lines[..i] {
+            doc.keep(line);
+            doc.keep(end);
+        }
+        let mut previous_blank = true;
+        let mut in_list = false;
+        while i < lines.len() {
+            let (line, end) = lines[i];
+            let blank = line.trim().is_empty();
+            if let Some(fence) = FENCE.captures(line) {
+                // the fence character repeated at least as many times closes the block.
+                let fence = &fence[1];
+                let close = lines[i + 1..].iter().position(|(l, _)| {
+                    let l = l.trim();
+                    l.starts_with(fence) && l.trim_start_matches(&fence[..1]).is_empty()
+                });
+                let last = close.map_or(lines.len() - 1, |close| i + 1 + close);
+                doc.keep_lines(&lines[i..=last]);
+                i = last + 1;
+                previous_blank = false;
+                continue;
+            }
+            let indented = line.starts_with("    ") || line.starts_with('\t');
+            if blank
+                || (doc.paragraph.is_some() && SETEXT_UNDERLINE.is_match(line))
+                || THEMATIC_BREAK.is_match(line)
+                || (indented && previous_blank && !in_list)
+                || REFERENCE.is_match(line)
+            {
+                doc.keep_lines(&lines[i..=i]);
+            } else if line.contains('|')
+                && lines
+                    .get(i + 1)
+                    .is_some_and(|(next, _)| next.contains('|') && TABLE_DELIMITER.is_match(next))
+            {
+                doc.flush();
+                let rows = lines[i..]
+                    .iter()
+                    .take_while(|(l, _)| l.contains('|') && !l.trim().is_empty())
+                    .count();
+                for (j, (row, end)) in lines[i..i + rows].iter().enumerate() {
+                    if j == 1 {
+                        doc.keep(row);
+                    } else {
+                        doc.push_row(row);
+                    }
+                    doc.keep(end);
+                }
+                i += rows;
+                previous_blank = false;
+                in_list = false;
+                continue;
+            } else if HTML_BLOCK.is_match(line) && doc.paragraph.is_none() {
+                let rows = lines[i..]
+                    .iter()
+                    .take_while(|(l, _)| !l.trim().is_empty())
+                    .count();
+                doc.keep_lines(&lines[i..i + rows]);
+                i += rows;
+                previous_blank = false;
+                continue;
+            } else {
+                doc.push_block_line(line, end, &mut in_list);
+            }
+            // a blank line inside a list does not end it.
+            if !blank && !indented && !ITEM.is_match(line) {
+                in_list = false;
+            }
+            previous_blank = blank;
+            i += 1;
+        }
+        doc.flush();
+        doc
+    }
+    /// The masked prose in the order of the document.
+    pub fn texts(&self) -> Vec<String> {
+        self.prose()
+            .map(|prose| prose.masked().to_string())
+            .collect()
+    }
+    pub fn prose(&self) -> impl Iterator<Item = &Prose> {
+        self.segments.iter().filter_map(|segment| match segment {
+            Segment::Prose(prose) => Some(prose),
+            Segment::Keep(_) => None,
+        })
+    }
+    /// Rebuilds the document with `translated` in the order of `texts`. Prose whose translation
+    /// is missing or lost a placeholder is kept as the source, and reported as untranslated.
+    pub fn render(&self, translated: &[Option<String>]) -> TranslatedDocument {
+        let mut translated = translated.iter();
+        let mut doc = TranslatedDocument::default();
+        for segment in &self.segments {
+            match segment {
+                Segment::Keep(text) => doc.content.push_str(text),
+                Segment::Prose(prose) => {
+                    doc.segments += 1;
+                    let restored = translated
+                        .next()
+                        .and_then(Option::as_deref)
+                        .and_then(|text| prose.restore(text));
+                    match restored {
+                        Some(restored) => doc.content.push_str(&restored),
+                        None => {
+                            doc.content.push_str(prose.source());
+                            doc.untranslated.push(prose.source().to_string());
+                        }
+                    }
+                }
+            }
+        }
+        doc
+    }
+
+    fn keep(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        match self.segments.last_mut() {
+            Some(Segment::Keep(last)) => last.push_str(text),
+            _ => self.segments.push(Segment::Keep(text.to_string())),
+        }
+    }
+    fn keep_lines(&mut self, lines: &[(&str, &str)]) {
+        self.flush();
+        for (line, end) in lines {
+            self.keep(line);
+            self.keep(end);
+        }
+    }
+    fn push_text(&mut self, text: &str) {
+        let trimmed = text.trim();
+        let lead = text.len() - text.trim_start().len();
+        let prose = Prose::new(trimmed);
+        if trimmed.is_empty() || !prose.is_translatable() {
+            self.keep(text);
+            return;
+        }
+        self.keep(&text[..lead]);
+        self.segments.push(Segment::Prose(prose));
+        self.keep(&text[lead + trimmed.len()..]);
+    }
+    fn flush(&mut self) {
+        if let Some((paragraph, end)) = self.paragraph.take() {
+            self.push_text(&paragraph);
+            self.keep(&end);
+        }
+    }
+    // headings, list items, quotes and paragraphs.
+    fn push_block_line(&mut self, line: &str, end: &str, in_list: &mut bool) {
+        let mut prefix = 0;
+        while let Some(quote) = QUOTE.find(&line[prefix..]) {
+            prefix += quote.end();
+        }
+        let rest = &line[prefix..];
+        if let Some(heading) = HEADING.find(rest) {
+            self.flush();
+            self.keep(&line[..prefix + heading.end()]);
+            let text = &rest[heading.end()..];
+            let closing = CLOSING_HASHES.find(text).map_or(text.len(), |m| m.start());
+            self.push_text(&text[..closing]);
+            self.keep(&text[closing..]);
+            self.keep(end);
+            return;
+        }
+        if let Some(item) = ITEM.find(rest) {
+            *in_list = true;
+            prefix += item.end();
+        }
+        if prefix > 0 {
+            self.flush();
+            self.keep(&line[..prefix]);
+            self.paragraph = Some((line[prefix..].to_string(), end.to_string()));
+            return;
+        }
+        match &mut self.paragraph {
+            Some((paragraph, last_end)) => {
+                paragraph.push_str(last_end);
+                paragraph.push_str(line);
+                *last_end = end.to_string();
+            }
+            None => {
+                let indent = line.len() - line.trim_start().len();
+                self.keep(&line[..indent]);
+                self.paragraph = Some((line[indent..].to_string(), end.to_string()));
+            }
+        }
+    }
+    // cells are split at pipes which are not escaped nor in code.
+    fn push_row(&mut self, row: &str) {
+        let mut start = 0;
+        let mut in_code = false;
+        let mut escaped = false;
+        for (i, c) in row.char_indices() {
+            match c {
+                '\\' if !escaped => {
+                    escaped = true;
+                    continue;
+                }
+                '`' if !escaped => in_code = !in_code,
+                '|' if !escaped && !in_code => {
+                    self.push_text(&row[start..i]);
+                    self.keep("|");
+                    start = i + 1;
+                }
+                _ => {}
+            }
+            escaped = false;
+        }
+        self.push_text(&row[start..]);
+    }
+}
+
+fn split_line_ending(line: &str) -> (&str, &str) {
+    let content = line.trim_end_matches(['\n', '\r']);
+    (content, &line[content.len()..])
+}
+
+// YAML front matter between `---` lines, or TOML between `+++` lines.
+fn front_matter_end(lines: &[(&str, &str)]) -> Option<usize> {
+    let delimiter = lines.first()?.0.trim_end();
+    if delimiter != "---" && delimiter != "+++" {
+        return None;
+    }
+    let close = lines[1..]
+        .iter()
+        .position(|(line, _)| line.trim_end() == delimiter)?;
+    Some(close + 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOCUMENT: &str = "---
+title: Hello
+---
+# Getting started #
+
+Install the `cai` command
+from [the releases](https://example.com/releases \"Releases\").
+
+```sh
+cargo install cai # do not translate
+```
+
+- [x] Run `cai ask` ![logo](logo.png)
+> Note: see https://example.com.
+
+| Name | Description |
+|------|-------------|
+| `-e` | Engine \\| model |
+";
+
+    fn upper(texts: &[String]) -> Vec<Option<String>> {
+        texts.iter().map(|text| Some(text.to_uppercase())).collect()
+    }
+
+    #[test]
+    fn only_prose_is_translated() {
+        let doc = MarkdownDocument::parse(DOCUMENT);
+
+        let texts = doc.texts();
+        let translated = doc.render(&upper(&texts)).content;
+
+        assert_eq!(
+            texts,
+            [
+                "Getting started",
+                "Install the ⟦0⟧ command\nfrom ⟦1⟧the releases⟦2⟧.",
+                "Run ⟦0⟧ ⟦1⟧logo⟦2⟧",
+                "Note: see ⟦0⟧.",
+                "Name",
+                "Description",
+                "Engine \\| model",
+            ]
+        );
+        assert_eq!(
+            translated,
+            "---
+title: Hello
+---
+# GETTING STARTED #
+
+INSTALL THE `cai` COMMAND
+FROM [THE RELEASES](https://example.com/releases \"Releases\").
+
+```sh
+cargo install cai # do not translate
+```
+
+- [x] RUN `cai ask` ![LOGO](logo.png)
+> NOTE: SEE https://example.com.
+
+| NAME | DESCRIPTION |
+|------|-------------|
+| `-e` | ENGINE \\| MODEL |
+"
+        );
+    }
+    #[test]
+    fn prose_which_lost_placeholders_is_kept() {
+        let doc = MarkdownDocument::parse("Run `cai` now.\n\nSecond paragraph.\n");
+
+        let translated = doc.render(&[
+            Some("Run cai now.".to_string()),
+            Some("2nd\n\nparagraph".to_string()),
+        ]);
+        let unparsed = doc.render(&[Some("Run ⟦0⟧ now.".to_string()), None]);
+
+        assert_eq!(translated.content, "Run `cai` now.\n\n2nd paragraph\n");
+        assert_eq!(translated.untranslated, ["Run `cai` now."]);
+        assert_eq!(unparsed.content, "Run `cai` now.\n\nSecond paragraph.\n");
+        assert_eq!(unparsed.untranslated, ["Second paragraph."]);
+    }
+}
@@ -0,0 +1,359 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use anyhow::Context;
+use regex::Regex;
+
+use crate::{
+    config::Config,
+    tools::{bilingual::escape_xml, lang::Lang},
+    unix_now,
+};
+
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub struct MemoryError(anyhow::Error);
+crate::impl_from_error!(MemoryError);
+
+static TMX_TU: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?s)<tu[\s>].*?</tu>").unwrap());
+static TMX_TUV: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?s)<tuv[^>]*?\b(?:xml:)?lang\s*=\s*"([^"]+)"[^>]*>.*?<seg>(.*?)</seg>"#).unwrap()
+});
+static TMX_SRCLANG: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"<header[^>]*?\bsrclang\s*=\s*"([^"]+)""#).unwrap());
+static TMX_ENGINE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"<prop type="x-engine">([^<]*)</prop>"#).unwrap());
+// inline markup of segments like `<ph>` and `<bpt>`.
+static TMX_INLINE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]*>").unwrap());
+
+/// A translated segment.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct MemoryEntry {
+    pub source: String,
+    pub translated: String,
+    pub source_lang: String,
+    pub target_lang: String,
+    /// The engine which translated it. `None` for imported translations, which are used with
+    /// any engine.
+    pub engine: Option<String>,
+    // unix time in seconds
+    pub updated_at: u64,
+}
+
+impl MemoryEntry {
+    fn is_pair(&self, source_lang: &Lang, target_lang: &Lang) -> bool {
+        self.source_lang == source_lang.tag() && self.target_lang == target_lang.tag()
+    }
+}
+
+/// Translations of segments which are reused when the same segment is translated again.
+/// Stored as a JSON file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TranslationMemory {
+    path: PathBuf,
+    entries: Vec<MemoryEntry>,
+}
+
+impl TranslationMemory {
+    /// `translation_memory.json` under `Config::data_dir`.
+    pub fn default_path() -> PathBuf {
+        Config::data_dir().join("translation_memory.json")
+    }
+    /// Opens the memory at `path`. A missing file is an empty memory.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self, MemoryError> {
+        let path = path.into();
+        if !path.exists() {
+            return Ok(Self {
+                path,
+                entries: vec![],
+            });
+        }
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let entries = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        Ok(Self { path, entries })
+    }
+    pub fn save(&self) -> Result<(), MemoryError> {
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let content = serde_json::to_string_pretty(&self.entries)
+            .context("Failed to serialize translation memory")?;
+        std::fs::write(&self.path, content)
+            .with_context(|| format!("Failed to write {}", self.path.display()))?;
+        Ok(())
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    pub fn entries(&self) -> &[MemoryEntry] {
+        &self.entries
+    }
+    /// The translation of the same segment by the engine, or an imported one.
+    pub fn get(
+        &self,
+        source: &str,
+        source_lang: &Lang,
+        target_lang: &Lang,
+        engine: &str,
+    ) -> Option<&MemoryEntry> {
+        let source = source.trim();
+        let found = self
+            .entries
+            .iter()
+            .filter(|e| e.source == source && e.is_pair(source_lang, target_lang));
+        let mut imported = None;
+        for entry in found {
+            match entry.engine.as_deref() {
+                Some(e) if e == engine => return Some(entry),
+                None => imported = imported.or(Some(entry)),
+                Some(_) => {}
+            }
+        }
+        imported
+    }
+    /// The most similar segment of the language pair whose similarity is at least `threshold`,
+    /// from 0.0 to 1.0. It is a hint for the translation, not the translation itself.
+    pub fn fuzzy(
+        &self,
+        source: &str,
+        source_lang: &Lang,
+        target_lang: &Lang,
+        threshold: f64,
+    ) -> Option<(&MemoryEntry, f64)> {
+        let source = source.trim();
+        self.entries
+            .iter()
+            .filter(|e| e.is_pair(source_lang, target_lang))
+            .map(|e| (e, similarity(source, &e.source)))
+            .filter(|(_, score)| *score >= threshold)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+    }
+    /// Adds the translation, replacing the one of the same segment and engine.
+    pub fn insert(&mut self, entry: MemoryEntry) {
+        let entry = MemoryEntry {
+            source: entry.source.trim().to_string(),
+            ..entry
+        };
+        let existing = self.entries.iter_mut().find(|e| {
+            e.source == entry.source
+                && e.source_lang == entry.source_lang
+                && e.target_lang == entry.target_lang
+                && e.engine == entry.engine
+        });
+        match existing {
+            Some(existing) => *existing = entry,
+            None => self.entries.push(entry),
+        }
+    }
+    /// Adds the translation units of a TMX file, and returns how many segments are added.
+    /// The source language is `srclang` of the header, or the first language of each unit.
+    pub fn import_tmx(&mut self, tmx: &str) -> Result<usize, MemoryError> {
+        if !tmx.contains("<tmx") {
+            return Err(anyhow::anyhow!("Not a TMX file").into());
+        }
+        let srclang = TMX_SRCLANG
+            .captures(tmx)
+            .map(|c| c[1].to_string())
+            .filter(|lang| lang != "*all*");
+        let mut added = 0;
+        for tu in TMX_TU.find_iter(tmx) {
+            let tu = tu.as_str();
+            let engine = TMX_ENGINE.captures(tu).map(|c| unescape(&c[1]));
+            let tuvs = TMX_TUV
+                .captures_iter(tu)
+                .map(|c| {
+                    (
+                        normalize_lang(&c[1]),
+                        unescape(&TMX_INLINE.replace_all(&c[2], "")),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let source = match &srclang {
+                Some(lang) => tuvs.iter().find(|(l, _)| *l == normalize_lang(lang)),
+                None => tuvs.first(),
+            };
+            let Some((source_lang, source)) = source else {
+                continue;
+            };
+            for (target_lang, translated) in &tuvs {
+                if target_lang == source_lang || source.trim().is_empty() {
+                    continue;
+                }
+                self.insert(MemoryEntry {
+                    source: source.clone(),
+                    translated: translated.clone(),
+                    source_lang: source_lang.clone(),
+                    target_lang: target_lang.clone(),
+                    engine: engine.clone(),
+                    updated_at: unix_now(),
+                });
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+    /// All segments as TMX 1.4, one translation unit per entry. The engine is a `x-engine` prop.
+    pub fn to_tmx(&self) -> String {
+        let mut tmx = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<tmx version=\"1.4\">\n  <header creationtool=\"cai\" creationtoolversion=\"{}\" segtype=\"sentence\" o-tmf=\"cai\" adminlang=\"en\" srclang=\"*all*\" datatype=\"plaintext\"/>\n  <body>\n",
+            env!("CARGO_PKG_VERSION")
+        );
+        for entry in &self.entries {
+            tmx.push_str("    <tu>\n");
+            if let Some(engine) = &entry.engine {
+                tmx.push_str(&format!(
+                    "      <prop type=\"x-engine\">{}</prop>\n",
+                    escape_xml(engine)
+                ));
+            }
+            for (lang, seg) in [
+                (&entry.source_lang, &entry.source),
+                (&entry.target_lang, &entry.translated),
+            ] {
+                tmx.push_str(&format!(
+                    "      <tuv xml:lang=\"{}\"><seg>{}</seg></tuv>\n",
+                    escape_xml(lang),
+                    escape_xml(seg)
+                ));
+            }
+            tmx.push_str("    </tu>\n");
+        }
+        tmx.push_str("  </body>\n</tmx>\n");
+        tmx
+    }
+}
+
+fn normalize_lang(lang: &str) -> String {
+    lang.parse::<Lang>()
+        .map(|lang| lang.tag().to_string())
+        .unwrap_or_else(|_| lang.to_string())
+}
+
+/// How similar the texts are by the edit distance of characters, from 0.0 to 1.0.
+pub fn similarity(a: &str, b: &str) -> f64 {
+    let a = a.chars().collect::<Vec<_>>();
+    let b = b.chars().collect::<Vec<_>>();
+    let longest = a.len().max(b.len());
+    if longest == 0 {
+        return 1.0;
+    }
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substituted = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substituted.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    1.0 - row[b.len()] as f64 / longest as f64
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(source: &str, translated: &str, engine: Option<&str>) -> MemoryEntry {
+        MemoryEntry {
+            source: source.to_string(),
+            translated: translated.to_string(),
+            source_lang: "en".to_string(),
+            target_lang: "ja".to_string(),
+            engine: engine.map(str::to_string),
+            updated_at: 0,
+        }
+    }
+
+    #[test]
+    fn exact_and_fuzzy_matches_are_found() {
+        let (en, ja) = ("en".parse().unwrap(), "ja".parse().unwrap());
+        let mut memory = TranslationMemory::default();
+        memory.insert(entry(
+            "Open the file.",
+            "ファイルを開く。",
+            Some("gpt4-o-mini"),
+        ));
+        memory.insert(entry("Close the file.", "ファイルを閉じる。", None));
+        memory.insert(entry(
+            "Open the file.",
+            "ファイルを開きます。",
+            Some("gpt4-o-mini"),
+        ));
+
+        assert_eq!(memory.entries().len(), 2);
+        assert_eq!(
+            memory
+                .get(" Open the file.", &en, &ja, "gpt4-o-mini")
+                .map(|e| e.translated.as_str()),
+            Some("ファイルを開きます。")
+        );
+        assert_eq!(
+            memory.get("Open the file.", &en, &ja, "claude3-haiku"),
+            None
+        );
+        assert!(
+            memory
+                .get("Close the file.", &en, &ja, "claude3-haiku")
+                .is_some()
+        );
+        let (hint, score) = memory.fuzzy("Open the files.", &en, &ja, 0.8).unwrap();
+        assert_eq!(hint.source, "Open the file.");
+        assert!(score > 0.9);
+        assert!(memory.fuzzy("Something else.", &en, &ja, 0.8).is_none());
+    }
+    #[test]
+    fn tmx_is_exported_and_imported() {
+        let mut memory = TranslationMemory::default();
+        memory.insert(entry(
+            "Fish & <chips>",
+            "フィッシュ&チップス",
+            Some("gpt4-o-mini"),
+        ));
+
+        let tmx = memory.to_tmx();
+        let mut imported = TranslationMemory::default();
+        let added = imported.import_tmx(&tmx).unwrap();
+        let other = imported
+            .import_tmx(
+                "<tmx version=\"1.4\"><header srclang=\"EN-us\"/><body>\n<tu><tuv xml:lang=\"fr\"><seg>Bonjour</seg></tuv><tuv xml:lang=\"en-US\"><seg>Hello <ph>{0}</ph></seg></tuv></tu>\n</body></tmx>",
+            )
+            .unwrap();
+
+        assert!(tmx.contains("<seg>Fish &amp; &lt;chips&gt;</seg>"));
+        assert_eq!((added, other), (1, 1));
+        let fields = |e: &MemoryEntry| {
+            (
+                e.source.clone(),
+                e.translated.clone(),
+                e.source_lang.clone(),
+                e.target_lang.clone(),
+                e.engine.clone(),
+            )
+        };
+        assert_eq!(fields(&imported.entries()[0]), fields(&memory.entries()[0]));
+        assert_eq!(
+            fields(&imported.entries()[1]),
+            (
+                "Hello {0}".to_string(),
+                "Bonjour".to_string(),
+                "en-US".to_string(),
+                "fr".to_string(),
+                None
+            )
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use anyhow::Context;
+
+use crate::{
+    AIError, GenerativeAIInterface, Prompt,
+    handlers::recorder::Recorder,
+    tools::{
+        input::attach_context,
+        review::{Finding, Hunk, ReviewOptions},
+    },
+};
+
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub struct PatchError(anyhow::Error);
+crate::impl_from_error!(PatchError);
+
+/// Asks for a unified diff of `content` which fixes the finding.
+pub fn fix_prompt(finding: &Finding, content: &str, options: &ReviewOptions) -> Prompt {
+    let mut question = format!(
+        "Fix this problem in {} at lines {}-{}: {}\n\
+        Answer only a unified diff of the file with `@@ -start,count +start,count @@` hunk headers \
+        and 3 lines of context around each change. Copy the context and removed lines exactly, \
+        and do not change anything else.",
+        finding.path, finding.start_line, finding.end_line, finding.message
+    );
+    if let Some(suggestion) = &finding.suggestion {
+        question = attach_context(&question, "suggestion", suggestion);
+    }
+    Prompt::ask_with_role_play(
+        &attach_context(&question, "file", content),
+        &options.system_prompt(),
+    )
+}
+
+/// Requests a patch for the finding. The hunks may be empty if the reply has no diff.
+pub async fn request_fix<AI: GenerativeAIInterface>(
+    ai: &AI,
+    finding: &Finding,
+    content: &str,
+    options: &ReviewOptions,
+) -> Result<Vec<Hunk>, AIError> {
+    let mut recorder = Recorder::new();
+    ai.request_mut(fix_prompt(finding, content, options), &mut recorder)
+        .await?;
+    Ok(parse_patch(&recorder.take()))
+}
+
+/// Hunks of a unified diff in the reply. Code fences and file headers are ignored.
+/// `--- ` and `+++ ` lines in a hunk are removed and added lines, unless they start the next file.
+pub fn parse_patch(reply: &str) -> Vec<Hunk> {
+    let lines = reply.lines().collect::<Vec<_>>();
+    let mut hunks: Vec<Hunk> = vec![];
+    // true before the first hunk and between files, where `--- ` and `+++ ` are file headers.
+    let mut in_header = true;
+    for (i, line) in lines.iter().copied().enumerate() {
+        if line.starts_with("@@") {
+            // models sometimes omit the ranges, then the hunk is searched in the whole file.
+            hunks.push(Hunk::from_header(line).unwrap_or_else(|| Hunk {
+                header: line.to_string(),
+                old_start: 0,
+                new_start: 0,
+                lines: vec![],
+            }));
+            in_header = false;
+            continue;
+        }
+        // a file header without `diff --git`, like "--- a/x\n+++ b/x\n@@ ..." after a hunk.
+        let starts_file = line.starts_with("--- ")
+            && lines.get(i + 1).is_some_and(|l| l.starts_with("+++ "))
+            && lines.get(i + 2).is_some_and(|l| l.starts_with("@@"));
+        if starts_file || line.starts_with("```") || line.starts_with("diff --git") {
+            in_header = true;
+            continue;
+        }
+        if in_header && (line.starts_with("--- ") || line.starts_with("+++ ")) {
+            continue;
+        }
+        let Some(hunk) = hunks.last_mut() else {
+            continue;
+        };
+        match line {
+            "" => hunk.lines.push(" ".to_string()),
+            _ if line.starts_with([' ', '+', '-']) => hunk.lines.push(line.to_string()),
+            _ => {}
+        }
+    }
+    hunks.retain(|hunk| hunk.lines.iter().any(|l| l.starts_with(['+', '-'])));
+    hunks
+}
+
+/// Applies the hunks in order. Context and removed lines must match the content exactly,
+/// but a hunk may be found at a different line than its header says, like `git apply`.
+pub fn apply_patch(content: &str, hunks: &[Hunk]) -> Result<String, PatchError> {
+    let lines = content.lines().collect::<Vec<_>>();
+    let mut patched: Vec<&str> = vec![];
+    // the first line which is not copied yet.
+    let mut next = 0;
+    for hunk in hunks {
+        let side = |skip: char| {
+            hunk.lines
+                .iter()
+                .filter(|l| !l.starts_with(skip))
+                .map(|l| &l[1..])
+                .collect::<Vec<_>>()
+        };
+        let (old, new) = (side('+'), side('-'));
+        // without old lines, like `@@ -5,0 +6,2 @@`, the new lines go after `old_start`.
+        let hint = if old.is_empty() {
+            hunk.old_start
+        } else {
+            hunk.old_start.saturating_sub(1)
+        };
+        let at = find_hunk(&lines, &old, next, hint)
+            .with_context(|| format!("Hunk does not apply: {}", hunk.header))?;
+        patched.extend(&lines[next..at]);
+        patched.extend(new);
+        next = at + old.len();
+    }
+    patched.extend(&lines[next..]);
+    let mut result = patched.join("\n");
+    if !result.is_empty() && (content.is_empty() || content.ends_with('\n')) {
+        result.push('\n');
+    }
+    Ok(result)
+}
+
+// the position of `old` at or after `from` which is the nearest to `hint`.
+fn find_hunk(lines: &[&str], old: &[&str], from: usize, hint: usize) -> Option<usize> {
+    if old.is_empty() {
+        return Some(hint.clamp(from, lines.len()));
+    }
+    (from..=lines.len().checked_sub(old.len())?)
+        .filter(|&at| lines[at..at + old.len()] == *old)
+        .min_by_key(|&at| at.abs_diff(hint))
+}
+
+/// The hunks as a diff of `path` to show before it is applied.
+pub fn to_diff(path: &str, hunks: &[Hunk]) -> String {
+    let mut diff = format!("--- a/{path}\n+++ b/{path}\n");
+    for hunk in hunks {
+        diff.push_str(&format!("{}\n", hunk.header));
+        for line in &hunk.lines {
+            diff.push_str(&format!("{}\n", line));
+        }
+    }
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONTENT: &str =
+        "fn main() {\n    let a = 1;\n    let b = 2;\n    println!(\"{}\", a);\n}\n";
+
+    #[test]
+    fn patch_is_parsed_from_fenced_reply_and_applied_with_offset() {
+        let reply = "Here is the fix.\n```diff\n--- a/src/main.rs\n+++ b/src/main.rs\n@@ -5,3 +5,3 @@\n     let b = 2;\n-    println!(\"{}\", a);\n+    println!(\"{}\", a + b);\n }\n```\n";
+
+        let hunks = parse_patch(reply);
+        let patched = apply_patch(CONTENT, &hunks).unwrap();
+
+        assert_eq!(hunks.len(), 1);
+        assert_eq!(hunks[0].old_start, 5);
+        assert_eq!(
+            patched,
+            "fn main() {\n    let a = 1;\n    let b = 2;\n    println!(\"{}\", a + b);\n}\n"
+        );
+    }
+    #[test]
+    fn patch_with_unknown_lines_is_rejected() {
+        let hunks = parse_patch("@@ -2,1 +2,1 @@\n-    let a = 10;\n+    let a = 11;\n");
+
+        let error = apply_patch(CONTENT, &hunks).unwrap_err();
+
+        assert_eq!(error.to_string(), "Hunk does not apply: @@ -2,1 +2,1 @@");
+    }
+    #[test]
+    fn lines_are_inserted_after_the_line_without_context() {
+        let hunks = parse_patch("@@ -4,0 +5,1 @@\n+    println!(\"{}\", b);\n");
+
+        let patched = apply_patch(CONTENT, &hunks).unwrap();
+
+        assert_eq!(
+            patched,
+            "fn main() {\n    let a = 1;\n    let b = 2;\n    println!(\"{}\", a);\n    println!(\"{}\", b);\n}\n"
+        );
+    }
+    #[test]
+    fn removed_and_added_lines_like_headers_are_kept() {
+        let content = "SELECT 1;\n-- comment\nSELECT 2;\n";
+        let reply = "```diff\n--- a/q.sql\n+++ b/q.sql\n@@ -1,3 +1,3 @@\n SELECT 1;\n--- comment\n+++ counter\n SELECT 2;\n```\n";
+
+        let hunks = parse_patch(reply);
+        let patched = apply_patch(content, &hunks).unwrap();
+
+        assert_eq!(
+            hunks[0].lines,
+            [" SELECT 1;", "--- comment", "+++ counter", " SELECT 2;"]
+        );
+        assert_eq!(patched, "SELECT 1;\n++ counter\nSELECT 2;\n");
+    }
+}
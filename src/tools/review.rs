@@ -0,0 +1,980 @@
+use std::{fmt::Display, process::Command};
+
+use anyhow::Context;
+
+use crate::{
+    AIError, GenerativeAIInterface, Prompt, handlers::recorder::Recorder,
+    limiter::estimate_text_tokens,
+};
+
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub struct ReviewError(anyhow::Error);
+crate::impl_from_error!(ReviewError);
+
+/// Runs `git diff` in the current directory. `range` is like `main..HEAD`.
+pub fn git_diff(range: Option<&str>, staged: bool, context: usize) -> Result<String, ReviewError> {
+    let mut command = Command::new("git");
+    command.args([
+        "diff",
+        "--no-color",
+        "--no-ext-diff",
+        &format!("--unified={}", context),
+    ]);
+    if staged {
+        command.arg("--staged");
+    }
+    if let Some(range) = range {
+        command.arg(range);
+    }
+    let output = command.output().context("Failed to run git")?;
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "git diff failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(String::from_utf8(output.stdout).context("git diff is not valid utf-8")?)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileDiff {
+    pub path: String,
+    pub hunks: Vec<Hunk>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    // like "@@ -1,3 +1,4 @@ fn main() {"
+    pub header: String,
+    pub old_start: usize,
+    pub new_start: usize,
+    // each line starts with ' ', '+' or '-'.
+    pub lines: Vec<String>,
+}
+
+impl Hunk {
+    pub(crate) fn from_header(header: &str) -> Option<Self> {
+        let mut ranges = header.split_whitespace().skip(1);
+        let old = ranges.next()?.strip_prefix('-')?;
+        let new = ranges.next()?.strip_prefix('+')?;
+        let start = |range: &str| range.split(',').next()?.parse().ok();
+        Some(Self {
+            header: header.to_string(),
+            old_start: start(old)?,
+            new_start: start(new)?,
+            lines: vec![],
+        })
+    }
+    /// The hunk with the line numbers of the new file, so findings can point to them.
+    /// Removed lines have no number.
+    pub fn numbered(&self) -> String {
+        let mut new_line = self.new_start;
+        let mut numbered = format!("{}\n", self.header);
+        for line in &self.lines {
+            if line.starts_with('\\') {
+                continue;
+            }
+            if line.starts_with('-') {
+                numbered.push_str(&format!("{:>6} {}\n", "", line));
+                continue;
+            }
+            numbered.push_str(&format!("{:>6} {}\n", new_line, line));
+            new_line += 1;
+        }
+        numbered
+    }
+}
+
+/// Splits the output of `git diff` per file and hunk. Files without hunks, like binary files,
+/// are skipped.
+pub fn parse_diff(diff: &str) -> Vec<FileDiff> {
+    let mut files: Vec<FileDiff> = vec![];
+    for line in diff.lines() {
+        if let Some(rest) = line.strip_prefix("diff --git ") {
+            // "a/src/main.rs b/src/main.rs". it is replaced by "+++ b/..." if it exists.
+            let path = rest.rsplit_once(" b/").map_or(rest, |(_, path)| path);
+            files.push(FileDiff {
+                path: path.to_string(),
+                hunks: vec![],
+            });
+            continue;
+        }
+        let Some(file) = files.last_mut() else {
+            continue;
+        };
+        if line.starts_with("@@") {
+            file.hunks.extend(Hunk::from_header(line));
+            continue;
+        }
+        let Some(hunk) = file.hunks.last_mut() else {
+            if let Some(path) = line.strip_prefix("+++ b/") {
+                file.path = path.to_string();
+            }
+            continue;
+        };
+        match line {
+            "" => hunk.lines.push(" ".to_string()),
+            _ if line.starts_with([' ', '+', '-', '\\']) => hunk.lines.push(line.to_string()),
+            _ => {}
+        }
+    }
+    files.retain(|file| !file.hunks.is_empty());
+    files
+}
+
+/// Project guidelines which are used if `--guidelines` is not given.
+pub const DEFAULT_GUIDELINES_PATH: &str = ".cai/review.md";
+
+/// Reads the guideline file. Without `path`, `DEFAULT_GUIDELINES_PATH` is read if it exists.
+pub fn load_guidelines(path: Option<&str>) -> Result<Option<String>, ReviewError> {
+    let path = match path {
+        Some(path) => path,
+        None if std::path::Path::new(DEFAULT_GUIDELINES_PATH).exists() => DEFAULT_GUIDELINES_PATH,
+        None => return Ok(None),
+    };
+    let guidelines = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read guidelines: {}", path))?;
+    Ok(Some(guidelines))
+}
+
+/// Preset of what is reported and how strictly.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Rubric {
+    #[default]
+    Standard,
+    Strict,
+    Lenient,
+    Security,
+}
+impl Rubric {
+    fn instruction(self) -> &'static str {
+        match self {
+            Rubric::Standard => {
+                "Report bugs, security issues and code which is hard to maintain. Skip matters of taste."
+            }
+            Rubric::Strict => {
+                "Review strictly. Report every bug, risk, unclear name, missing test and style issue, and mark nits as info."
+            }
+            Rubric::Lenient => {
+                "Report only bugs and security issues which must be fixed before merging. Skip everything else."
+            }
+            Rubric::Security => {
+                "Audit the code for security. Consider injection, authentication, authorization, secrets, unsafe deserialization and input validation. \
+                Mark exploitable issues as error."
+            }
+        }
+    }
+    /// Focus used when `ReviewOptions::focus` is empty.
+    fn default_focus(self) -> &'static [Focus] {
+        match self {
+            Rubric::Security => &[Focus::Security],
+            _ => &[],
+        }
+    }
+}
+impl std::str::FromStr for Rubric {
+    type Err = ReviewError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "standard" => Ok(Rubric::Standard),
+            "strict" => Ok(Rubric::Strict),
+            "lenient" => Ok(Rubric::Lenient),
+            "security" => Ok(Rubric::Security),
+            _ => Err(anyhow::anyhow!(
+                "Unknown rubric: {}. choose from standard, strict, lenient and security",
+                s
+            )
+            .into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Focus {
+    Security,
+    Performance,
+    Readability,
+    Tests,
+}
+impl Focus {
+    fn instruction(self) -> &'static str {
+        match self {
+            Focus::Security => "security, like injection, leaked secrets and missing validation",
+            Focus::Performance => {
+                "performance, like needless allocations, blocking calls and quadratic loops"
+            }
+            Focus::Readability => "readability, like naming, structure and comments",
+            Focus::Tests => "tests, like missing cases and fragile assertions",
+        }
+    }
+}
+impl std::str::FromStr for Focus {
+    type Err = ReviewError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "security" => Ok(Focus::Security),
+            "performance" => Ok(Focus::Performance),
+            "readability" => Ok(Focus::Readability),
+            "tests" => Ok(Focus::Tests),
+            _ => Err(anyhow::anyhow!(
+                "Unknown focus: {}. choose from security, performance, readability and tests",
+                s
+            )
+            .into()),
+        }
+    }
+}
+
+/// How the code is reviewed. It becomes the system prompt of every review request.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReviewOptions {
+    // language of the review comments, like "ja" or "English".
+    language: Option<String>,
+    rubric: Rubric,
+    focus: Vec<Focus>,
+    guidelines: Option<String>,
+}
+
+impl ReviewOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn language(mut self, language: Option<&str>) -> Self {
+        self.language = language.map(str::to_string);
+        self
+    }
+    pub fn rubric(mut self, rubric: Rubric) -> Self {
+        self.rubric = rubric;
+        self
+    }
+    pub fn focus(mut self, focus: Vec<Focus>) -> Self {
+        self.focus = focus;
+        self
+    }
+    pub fn guidelines(mut self, guidelines: Option<String>) -> Self {
+        self.guidelines = guidelines;
+        self
+    }
+    pub fn system_prompt(&self) -> String {
+        let mut prompt = format!(
+            "You are an experienced reviewer of pull requests. {}",
+            self.rubric.instruction()
+        );
+        let focus = if self.focus.is_empty() {
+            self.rubric.default_focus()
+        } else {
+            self.focus.as_slice()
+        };
+        if !focus.is_empty() {
+            let focus = focus.iter().map(|f| f.instruction()).collect::<Vec<_>>();
+            prompt.push_str(&format!("\nFocus on {}.", focus.join("; ")));
+        }
+        if let Some(language) = &self.language {
+            prompt.push_str(&format!(
+                "\nWrite the review comments in the language `{}`.",
+                language
+            ));
+        }
+        if let Some(guidelines) = &self.guidelines {
+            prompt.push_str(&format!(
+                "\nFollow the guidelines of this project.\n<guidelines>\n{}\n</guidelines>",
+                guidelines.trim_end()
+            ));
+        }
+        prompt
+    }
+    /// Prompt of a free-form review of a whole file.
+    pub fn file_prompt(&self, content: &str) -> Prompt {
+        Prompt::ask_with_role_play(
+            &format!("Review the content of this file.\n{}", content),
+            &self.system_prompt(),
+        )
+    }
+}
+
+/// Hunks of a file which are reviewed by one request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReviewChunk {
+    pub path: String,
+    pub hunks: Vec<Hunk>,
+}
+
+impl ReviewChunk {
+    fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            hunks: vec![],
+        }
+    }
+    /// The whole file as added lines, so a file can be reviewed like a diff.
+    pub fn file(path: &str, content: &str) -> Self {
+        let lines = content.lines().collect::<Vec<_>>();
+        Self::lines(path, &lines, 0)
+    }
+    // `lines` starting at the index `start` of the file as added lines.
+    fn lines(path: &str, lines: &[&str], start: usize) -> Self {
+        Self {
+            path: path.to_string(),
+            hunks: vec![Hunk {
+                header: format!("@@ -0,0 +{},{} @@", start + 1, lines.len()),
+                old_start: 0,
+                new_start: start + 1,
+                lines: lines.iter().map(|line| format!("+{}", line)).collect(),
+            }],
+        }
+    }
+    /// Splits a file into chunks of about `token_budget` estimated tokens.
+    /// The file is split before top-level items like functions, and a section larger than the
+    /// budget is split by lines.
+    pub fn split_file(path: &str, content: &str, token_budget: u32) -> Vec<Self> {
+        let lines = content.lines().collect::<Vec<_>>();
+        // prefix[i] is the tokens of lines[..i]. a newline is counted as one token.
+        let prefix = lines.iter().fold(vec![0], |mut acc, line| {
+            acc.push(acc[acc.len() - 1] + estimate_text_tokens(line) + 1);
+            acc
+        });
+        let tokens = |range: std::ops::Range<usize>| prefix[range.end] - prefix[range.start];
+        let mut sections = vec![];
+        let mut start = 0;
+        for (i, line) in lines.iter().enumerate() {
+            if i == 0 || !is_item_start(line) {
+                continue;
+            }
+            // comments and attributes belong to the item below them.
+            let mut boundary = i;
+            while boundary > start + 1 && is_item_prefix(lines[boundary - 1]) {
+                boundary -= 1;
+            }
+            sections.push(start..boundary);
+            start = boundary;
+        }
+        if start < lines.len() {
+            sections.push(start..lines.len());
+        }
+
+        let mut ranges: Vec<std::ops::Range<usize>> = vec![];
+        for section in sections {
+            // a section larger than the budget is split by lines.
+            let mut parts = vec![];
+            let mut part = section.start..section.start;
+            for line in section {
+                if part.start < line && tokens(part.start..line + 1) > token_budget {
+                    parts.push(part.clone());
+                    part = line..line;
+                }
+                part.end = line + 1;
+            }
+            parts.push(part);
+            for part in parts {
+                match ranges.last_mut() {
+                    Some(last) if tokens(last.start..part.end) <= token_budget => {
+                        last.end = part.end
+                    }
+                    _ => ranges.push(part),
+                }
+            }
+        }
+        ranges
+            .into_iter()
+            .map(|range| Self::lines(path, &lines[range.clone()], range.start))
+            .collect()
+    }
+    pub fn text(&self) -> String {
+        self.hunks.iter().map(Hunk::numbered).collect()
+    }
+    fn to_prompt(&self, options: &ReviewOptions) -> Prompt {
+        let question = format!(
+            "Review the following git diff of {path}. Each line starts with its line number in the new file, and removed lines have no number. \
+            Report only real problems in the changed lines, such as bugs, security issues and misleading code. \
+            Answer only a JSON array of findings like \
+            `[{{\"path\": \"{path}\", \"start_line\": 1, \"end_line\": 2, \"severity\": \"error|warning|info\", \"category\": \"bug|security|performance|style|docs\", \"message\": \"...\", \"suggestion\": \"code which replaces the lines, or null\"}}]`. \
+            If there is no problem, answer `[]`. Keep the JSON keys in English.\n\n{}",
+            self.text(),
+            path = self.path
+        );
+        Prompt::ask_with_role_play(&question, &options.system_prompt())
+    }
+}
+
+// a line which starts a top-level item, like "pub fn main() {" or "class App:".
+fn is_item_start(line: &str) -> bool {
+    if line.starts_with(char::is_whitespace) {
+        return false;
+    }
+    let word = line
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .next()
+        .unwrap_or_default();
+    matches!(
+        word,
+        "fn" | "pub"
+            | "async"
+            | "impl"
+            | "struct"
+            | "enum"
+            | "trait"
+            | "mod"
+            | "macro_rules"
+            | "def"
+            | "class"
+            | "func"
+            | "function"
+            | "export"
+            | "interface"
+            | "type"
+    )
+}
+
+// comments, attributes and decorators above an item.
+fn is_item_prefix(line: &str) -> bool {
+    let line = line.trim_start();
+    ["//", "#", "@", "/*", "*"]
+        .iter()
+        .any(|prefix| line.starts_with(prefix))
+}
+
+/// Splits the files into chunks of about `token_budget` estimated tokens.
+/// Hunks of one file are kept together as far as possible, and a hunk is never split.
+pub fn split_by_budget(files: Vec<FileDiff>, token_budget: u32) -> Vec<ReviewChunk> {
+    let mut chunks = vec![];
+    for file in files {
+        let mut current = ReviewChunk::new(&file.path);
+        let mut tokens = 0;
+        for hunk in file.hunks {
+            let hunk_tokens = estimate_text_tokens(&hunk.numbered());
+            if !current.hunks.is_empty() && tokens + hunk_tokens > token_budget {
+                chunks.push(std::mem::replace(
+                    &mut current,
+                    ReviewChunk::new(&file.path),
+                ));
+                tokens = 0;
+            }
+            tokens += hunk_tokens;
+            current.hunks.push(hunk);
+        }
+        if !current.hunks.is_empty() {
+            chunks.push(current);
+        }
+    }
+    chunks
+}
+
+/// Ordered from the most severe, so `severity <= threshold` means "at least as severe".
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+}
+impl Severity {
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Info => "info",
+        }
+    }
+    fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "error" | "bug" | "critical" | "high" => Some(Severity::Error),
+            "warning" | "warn" | "medium" => Some(Severity::Warning),
+            "info" | "note" | "suggestion" | "low" => Some(Severity::Info),
+            _ => None,
+        }
+    }
+}
+impl std::str::FromStr for Severity {
+    type Err = ReviewError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s).ok_or_else(|| anyhow::anyhow!("Unknown severity: {}", s).into())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Finding {
+    pub path: String,
+    pub start_line: usize,
+    pub end_line: usize,
+    pub severity: Severity,
+    pub category: String,
+    pub message: String,
+    // code which replaces the lines from `start_line` to `end_line`.
+    pub suggestion: Option<String>,
+}
+
+// what the model is asked to answer. missing fields are filled by the chunk.
+#[derive(Debug, serde::Deserialize)]
+struct RawFinding {
+    path: Option<String>,
+    #[serde(alias = "line")]
+    start_line: usize,
+    end_line: Option<usize>,
+    severity: Option<String>,
+    category: Option<String>,
+    message: String,
+    #[serde(alias = "suggested_fix")]
+    suggestion: Option<String>,
+}
+
+impl Finding {
+    /// Parses a line like "src/main.rs:42: warning: message". Returns `None` for other lines.
+    pub fn parse(line: &str) -> Option<Self> {
+        let line = line.trim().trim_start_matches(['-', '*']).trim();
+        let (path, rest) = line.split_once(':')?;
+        let (line_number, rest) = rest.split_once(':')?;
+        let (severity, message) = rest.split_once(':')?;
+        let message = message.trim().trim_end_matches('`').trim();
+        if message.is_empty() {
+            return None;
+        }
+        let line_number = line_number.trim().parse().ok()?;
+        Some(Self {
+            path: path
+                .trim_matches(|c: char| c == '`' || c.is_whitespace())
+                .to_string(),
+            start_line: line_number,
+            end_line: line_number,
+            severity: Severity::parse(severity.trim())?,
+            category: DEFAULT_CATEGORY.to_string(),
+            message: message.to_string(),
+            suggestion: None,
+        })
+    }
+    /// Parses the JSON array in the reply. If there is none, lines like
+    /// "src/main.rs:42: warning: message" are parsed instead.
+    pub fn parse_reply(reply: &str, default_path: &str) -> Vec<Self> {
+        let json = reply
+            .find('[')
+            .zip(reply.rfind(']'))
+            .and_then(|(start, end)| reply.get(start..=end));
+        let Some(raw) = json.and_then(|json| serde_json::from_str::<Vec<RawFinding>>(json).ok())
+        else {
+            return reply.lines().filter_map(Self::parse).collect();
+        };
+        raw.into_iter()
+            .map(|raw| Self {
+                path: raw.path.unwrap_or_else(|| default_path.to_string()),
+                start_line: raw.start_line,
+                end_line: raw.end_line.unwrap_or(raw.start_line).max(raw.start_line),
+                severity: raw
+                    .severity
+                    .as_deref()
+                    .and_then(Severity::parse)
+                    .unwrap_or(Severity::Info),
+                category: raw.category.unwrap_or_else(|| DEFAULT_CATEGORY.to_string()),
+                message: raw.message,
+                suggestion: raw.suggestion.filter(|s| !s.trim().is_empty()),
+            })
+            .collect()
+    }
+}
+
+const DEFAULT_CATEGORY: &str = "general";
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.path, self.start_line)?;
+        if self.end_line != self.start_line {
+            write!(f, "-{}", self.end_line)?;
+        }
+        write!(f, ": {}: {}", self.severity.to_str(), self.message)?;
+        if self.category != DEFAULT_CATEGORY {
+            write!(f, " [{}]", self.category)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct FileSummary {
+    pub path: String,
+    // reviewed lines, which are all lines for a file and the hunks for a diff.
+    pub lines: usize,
+    pub chunks: usize,
+    pub errors: usize,
+    pub warnings: usize,
+    pub infos: usize,
+}
+
+/// Consolidated result of reviewing several files.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct ReviewReport {
+    pub files: Vec<FileSummary>,
+    pub findings: Vec<Finding>,
+    // problems which involve more than one file.
+    pub observations: Vec<String>,
+    // chunks which could not be reviewed, so their findings are missing.
+    pub failures: Vec<ChunkFailure>,
+}
+
+/// A chunk whose review request failed.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct ChunkFailure {
+    pub path: String,
+    // the first line of the chunk in the new file.
+    pub start_line: usize,
+    pub error: String,
+}
+
+impl Display for ChunkFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.path, self.start_line, self.error)
+    }
+}
+
+impl ReviewReport {
+    pub fn new(chunks: &[ReviewChunk], findings: Vec<Finding>) -> Self {
+        let mut files: Vec<FileSummary> = vec![];
+        for chunk in chunks {
+            let index = match files.iter().position(|f| f.path == chunk.path) {
+                Some(index) => index,
+                None => {
+                    files.push(FileSummary {
+                        path: chunk.path.clone(),
+                        ..Default::default()
+                    });
+                    files.len() - 1
+                }
+            };
+            files[index].chunks += 1;
+            files[index].lines += chunk.hunks.iter().map(|h| h.lines.len()).sum::<usize>();
+        }
+        for finding in &findings {
+            let Some(file) = files.iter_mut().find(|f| f.path == finding.path) else {
+                continue;
+            };
+            match finding.severity {
+                Severity::Error => file.errors += 1,
+                Severity::Warning => file.warnings += 1,
+                Severity::Info => file.infos += 1,
+            }
+        }
+        Self {
+            files,
+            findings,
+            observations: vec![],
+            failures: vec![],
+        }
+    }
+    pub fn observations(mut self, observations: Vec<String>) -> Self {
+        self.observations = observations;
+        self
+    }
+    pub fn failures(mut self, failures: Vec<ChunkFailure>) -> Self {
+        self.failures = failures;
+        self
+    }
+}
+
+impl Display for ReviewReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for file in &self.files {
+            writeln!(
+                f,
+                "{}: {} errors, {} warnings, {} infos in {} lines",
+                file.path, file.errors, file.warnings, file.infos, file.lines
+            )?;
+        }
+        if !self.findings.is_empty() {
+            writeln!(f)?;
+        }
+        for finding in &self.findings {
+            writeln!(f, "{}", finding)?;
+        }
+        if !self.observations.is_empty() {
+            writeln!(f, "\ncross-file observations:")?;
+        }
+        for observation in &self.observations {
+            writeln!(f, "- {}", observation)?;
+        }
+        if !self.failures.is_empty() {
+            writeln!(f, "\nfailed to review:")?;
+        }
+        for failure in &self.failures {
+            writeln!(f, "- {}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+/// Asks for problems across the files, like duplicated logic or inconsistent error handling.
+/// The model sees an outline of each file and the findings, not the whole code.
+pub async fn cross_file_observations<AI: GenerativeAIInterface>(
+    ai: &AI,
+    chunks: &[ReviewChunk],
+    findings: &[Finding],
+    options: &ReviewOptions,
+) -> Result<Vec<String>, AIError> {
+    let mut outline = String::new();
+    for chunk in chunks {
+        outline.push_str(&format!("## {}\n", chunk.path));
+        for hunk in &chunk.hunks {
+            let items = hunk.lines.iter().enumerate().filter_map(|(i, line)| {
+                let line = line.get(1..)?;
+                is_item_start(line).then(|| format!("{}: {}\n", hunk.new_start + i, line))
+            });
+            outline.extend(items);
+        }
+    }
+    let findings = findings
+        .iter()
+        .map(|f| format!("{}\n", f))
+        .collect::<String>();
+    let question = format!(
+        "These files were reviewed one by one. Point out problems which involve more than one file, \
+        like duplicated logic, inconsistent naming or error handling, and broken contracts between modules. \
+        Write each observation on its own line starting with `- `. If there is none, answer `none`.\n\n\
+        <outline>\n{}</outline>\n<findings>\n{}</findings>",
+        outline, findings
+    );
+    let mut recorder = Recorder::new();
+    ai.request_mut(
+        Prompt::ask_with_role_play(&question, &options.system_prompt()),
+        &mut recorder,
+    )
+    .await?;
+    Ok(recorder
+        .take()
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim_start();
+            line.strip_prefix("- ").or_else(|| line.strip_prefix("* "))
+        })
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect())
+}
+
+/// Reviews the chunks concurrently and returns the findings sorted by file and line, with the
+/// chunks whose request failed. It fails only if no chunk could be reviewed.
+pub async fn review_diff<AI: GenerativeAIInterface>(
+    ai: &AI,
+    chunks: &[ReviewChunk],
+    options: &ReviewOptions,
+) -> Result<(Vec<Finding>, Vec<ChunkFailure>), AIError> {
+    let tasks = chunks.iter().map(|chunk| review_chunk(ai, chunk, options));
+    let results = futures::future::join_all(tasks).await;
+    let mut findings = vec![];
+    let mut failures = vec![];
+    let mut last_error = None;
+    for (chunk, result) in chunks.iter().zip(results) {
+        match result {
+            Ok(chunk_findings) => findings.extend(chunk_findings),
+            Err(e) => {
+                failures.push(ChunkFailure {
+                    path: chunk.path.clone(),
+                    start_line: chunk.hunks.first().map_or(1, |hunk| hunk.new_start),
+                    error: e.to_string(),
+                });
+                last_error = Some(e);
+            }
+        }
+    }
+    if let Some(e) = last_error
+        && failures.len() == chunks.len()
+    {
+        return Err(e);
+    }
+    findings.sort_by(|a, b| (&a.path, a.start_line).cmp(&(&b.path, b.start_line)));
+    findings.dedup();
+    Ok((findings, failures))
+}
+
+async fn review_chunk<AI: GenerativeAIInterface>(
+    ai: &AI,
+    chunk: &ReviewChunk,
+    options: &ReviewOptions,
+) -> Result<Vec<Finding>, AIError> {
+    let mut recorder = Recorder::new();
+    ai.request_mut(chunk.to_prompt(options), &mut recorder)
+        .await?;
+    Ok(Finding::parse_reply(&recorder.take(), &chunk.path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clients::mocks::FakeAI;
+
+    const DIFF: &str = "diff --git a/src/lib.rs b/src/lib.rs
+index 1111111..2222222 100644
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,3 +1,4 @@ mod a;
+ use std::io;
+-fn old() {}
++fn new() {}
++fn other() {}
+
+@@ -20,2 +21,2 @@ fn main() {
+-    old();
++    new();
+diff --git a/logo.png b/logo.png
+Binary files a/logo.png and b/logo.png differ
+diff --git a/README.md b/README.md
+--- a/README.md
++++ b/README.md
+@@ -1 +1 @@
+-# cai
++# cai cli
+";
+
+    #[test]
+    fn diff_is_split_per_file_and_hunk() {
+        let files = parse_diff(DIFF);
+
+        assert_eq!(
+            files.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(),
+            vec!["src/lib.rs", "README.md"]
+        );
+        assert_eq!(files[0].hunks.len(), 2);
+        assert_eq!(files[0].hunks[1].new_start, 21);
+        assert_eq!(
+            files[0].hunks[0].numbered(),
+            "@@ -1,3 +1,4 @@ mod a;\n     1  use std::io;\n       -fn old() {}\n     2 +fn new() {}\n     3 +fn other() {}\n     4  \n"
+        );
+    }
+    #[test]
+    fn hunks_are_grouped_within_budget() {
+        let files = parse_diff(DIFF);
+
+        let small = split_by_budget(files.clone(), 1);
+        let large = split_by_budget(files, 10_000);
+
+        assert_eq!(small.len(), 3);
+        assert_eq!(small[0].path, small[1].path);
+        assert_eq!(large.len(), 2);
+        assert_eq!(large[0].hunks.len(), 2);
+    }
+    #[test]
+    fn findings_are_parsed_from_lines_without_json() {
+        let reply = "Here are the findings:\n- `src/lib.rs:2: error: new() is never called`\n* src/lib.rs:21: Warning: old name in comment\nLGTM otherwise: really\n";
+
+        let findings = Finding::parse_reply(reply, "src/lib.rs");
+
+        assert_eq!(
+            findings.iter().map(|f| f.to_string()).collect::<Vec<_>>(),
+            vec![
+                "src/lib.rs:2: error: new() is never called",
+                "src/lib.rs:21: warning: old name in comment"
+            ]
+        );
+    }
+    #[test]
+    fn structured_findings_are_parsed_from_json() {
+        let reply = "```json\n[{\"start_line\": 3, \"end_line\": 4, \"severity\": \"warning\", \"category\": \"bug\", \"message\": \"unused\", \"suggestion\": \"fn used() {}\"},\n {\"path\": \"b.rs\", \"line\": 7, \"message\": \"typo\", \"suggestion\": \"\"}]\n```";
+
+        let findings = Finding::parse_reply(reply, "a.rs");
+
+        assert_eq!(
+            findings,
+            vec![
+                Finding {
+                    path: "a.rs".to_string(),
+                    start_line: 3,
+                    end_line: 4,
+                    severity: Severity::Warning,
+                    category: "bug".to_string(),
+                    message: "unused".to_string(),
+                    suggestion: Some("fn used() {}".to_string()),
+                },
+                Finding {
+                    path: "b.rs".to_string(),
+                    start_line: 7,
+                    end_line: 7,
+                    severity: Severity::Info,
+                    category: "general".to_string(),
+                    message: "typo".to_string(),
+                    suggestion: None,
+                },
+            ]
+        );
+        assert_eq!(findings[0].to_string(), "a.rs:3-4: warning: unused [bug]");
+    }
+    #[test]
+    fn system_prompt_contains_rubric_focus_language_and_guidelines() {
+        let security = ReviewOptions::new().rubric("security".parse().unwrap());
+        let sut = ReviewOptions::new()
+            .rubric(Rubric::Lenient)
+            .focus(vec![Focus::Performance, Focus::Tests])
+            .language(Some("ja"))
+            .guidelines(Some("- use anyhow\n".to_string()));
+
+        let prompt = sut.system_prompt();
+
+        assert!(security.system_prompt().contains("Focus on security"));
+        assert!(prompt.contains(Rubric::Lenient.instruction()));
+        assert!(prompt.contains("Focus on performance, like"));
+        assert!(prompt.contains("; tests, like"));
+        assert!(prompt.contains("language `ja`"));
+        assert!(prompt.ends_with("<guidelines>\n- use anyhow\n</guidelines>"));
+        assert!("fast".parse::<Focus>().is_err());
+    }
+    #[test]
+    fn large_file_is_split_before_items() {
+        let content =
+            "use std::io;\n\n/// doc\n#[inline]\nfn a() {\n    1;\n}\n\nfn b() {\n    2;\n}\n";
+
+        let whole = ReviewChunk::split_file("a.rs", content, 10_000);
+        let split = ReviewChunk::split_file("a.rs", content, 17);
+
+        assert_eq!(whole, vec![ReviewChunk::file("a.rs", content)]);
+        assert_eq!(
+            split
+                .iter()
+                .map(|c| (c.hunks[0].new_start, c.hunks[0].lines.len()))
+                .collect::<Vec<_>>(),
+            vec![(1, 2), (3, 6), (9, 3)]
+        );
+        assert_eq!(split[1].hunks[0].lines[0], "+/// doc");
+    }
+    #[test]
+    fn report_summarizes_findings_per_file() {
+        let chunks = split_by_budget(parse_diff(DIFF), 1);
+        let findings = Finding::parse_reply("src/lib.rs:2: error: a\nREADME.md:1: info: b", "");
+
+        let report = ReviewReport::new(&chunks, findings)
+            .observations(vec!["README does not mention new()".to_string()]);
+
+        assert_eq!(report.files.len(), 2);
+        assert_eq!(report.files[0].chunks, 2);
+        assert_eq!(report.files[0].lines, 7);
+        assert_eq!(report.files[0].errors, 1);
+        assert_eq!(report.files[1].infos, 1);
+        assert!(
+            report
+                .to_string()
+                .ends_with("cross-file observations:\n- README does not mention new()\n")
+        );
+    }
+    #[tokio::test]
+    async fn failed_chunks_are_reported_with_findings_of_the_others() {
+        let chunks = split_by_budget(parse_diff(DIFF), 10_000);
+
+        // fails the review of README.md and finds an error in the other files.
+        let ai = FakeAI::new(|prompt: Prompt| {
+            if prompt.messages()[1].content().contains("diff of README.md") {
+                anyhow::bail!("timeout");
+            }
+            Ok(r#"[{"path": "src/lib.rs", "start_line": 2, "end_line": 2, "severity": "error", "category": "bug", "message": "a", "suggestion": null}]"#.to_string())
+        });
+
+        let (findings, failures) = review_diff(&ai, &chunks, &ReviewOptions::new())
+            .await
+            .unwrap();
+        let only_readme = review_diff(&ai, &chunks[1..], &ReviewOptions::new()).await;
+
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].path, "src/lib.rs");
+        assert_eq!(
+            failures.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ["README.md:1: timeout"]
+        );
+        assert!(only_readme.is_err());
+    }
+}
@@ -0,0 +1,224 @@
+use std::path::PathBuf;
+
+use anyhow::Context;
+
+use crate::{Conversation, Role, config::Config, unix_now};
+
+/// A named chat which is stored between runs.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Session {
+    pub name: String,
+    pub engine: String,
+    // unix time in seconds
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub conversation: Conversation,
+    // one turn per AI message in the conversation.
+    pub turns: Vec<Turn>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Turn {
+    pub engine: String,
+    pub started_at: u64,
+    pub finished_at: u64,
+    // estimated by `limiter::estimate_tokens` because the clients do not report usage.
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+}
+
+impl Session {
+    pub fn new(name: &str, engine: &str) -> Self {
+        let now = unix_now();
+        Self {
+            name: name.to_string(),
+            engine: engine.to_string(),
+            created_at: now,
+            updated_at: now,
+            conversation: Conversation::new(),
+            turns: Vec::new(),
+        }
+    }
+    /// Replaces the conversation. Turns of AI messages removed by undo or retry are dropped.
+    pub fn set_conversation(&mut self, conversation: Conversation) {
+        let replies = conversation.iter().filter(|m| m.role() == Role::AI).count();
+        self.turns.truncate(replies);
+        self.conversation = conversation;
+        self.updated_at = unix_now();
+    }
+    pub fn record_turn(&mut self, turn: Turn) {
+        self.engine = turn.engine.clone();
+        self.turns.push(turn);
+        self.updated_at = unix_now();
+    }
+    pub fn total_tokens(&self) -> u32 {
+        self.turns
+            .iter()
+            .map(|t| t.prompt_tokens + t.completion_tokens)
+            .sum()
+    }
+    pub fn to_markdown(&self) -> String {
+        let mut turns = self.turns.iter();
+        self.conversation.iter().fold(
+            format!("# {}\n\nengine: {}\n", self.name, self.engine),
+            |mut acc, message| {
+                let heading = match message.role() {
+                    Role::User => "User".to_string(),
+                    Role::RolePlay => "System".to_string(),
+                    Role::AI => match turns.next() {
+                        Some(turn) => format!("AI ({})", turn.engine),
+                        None => "AI".to_string(),
+                    },
+                };
+                acc.push_str(&format!("\n## {}\n\n{}\n", heading, message.content()));
+                acc
+            },
+        )
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub struct SessionError(anyhow::Error);
+crate::impl_from_error!(SessionError);
+
+/// Stores each session as `<dir>/<name>.json`.
+pub struct SessionStore {
+    dir: PathBuf,
+}
+
+impl SessionStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+    /// `sessions` under `Config::data_dir`.
+    pub fn default_dir() -> PathBuf {
+        Config::data_dir().join("sessions")
+    }
+    /// Sessions sorted by the last update, newest first.
+    pub fn list(&self) -> Result<Vec<Session>, SessionError> {
+        if !self.dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut sessions = vec![];
+        for entry in std::fs::read_dir(&self.dir)
+            .with_context(|| format!("Failed to read {}", self.dir.display()))?
+        {
+            let path = entry.context("Failed to read session directory")?.path();
+            let Some(name) = path
+                .extension()
+                .filter(|ext| *ext == "json")
+                .and(path.file_stem())
+                .and_then(|stem| stem.to_str())
+            else {
+                continue;
+            };
+            if let Some(session) = self.load(name)? {
+                sessions.push(session);
+            }
+        }
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.updated_at));
+        Ok(sessions)
+    }
+    pub fn load(&self, name: &str) -> Result<Option<Session>, SessionError> {
+        let path = self.path(name)?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let session = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        Ok(Some(session))
+    }
+    pub fn save(&self, session: &Session) -> Result<(), SessionError> {
+        let path = self.path(&session.name)?;
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+        let content =
+            serde_json::to_string_pretty(session).context("Failed to serialize session")?;
+        std::fs::write(&path, content)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        Ok(())
+    }
+    /// Returns false if the session does not exist.
+    pub fn delete(&self, name: &str) -> Result<bool, SessionError> {
+        let path = self.path(name)?;
+        if !path.exists() {
+            return Ok(false);
+        }
+        std::fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove {}", path.display()))?;
+        Ok(true)
+    }
+    fn path(&self, name: &str) -> Result<PathBuf, SessionError> {
+        // the name is used as a file name, so it must not escape the directory.
+        let valid = !name.is_empty()
+            && !name.starts_with('.')
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !valid {
+            return Err(anyhow::anyhow!("Invalid session name: {}", name).into());
+        }
+        Ok(self.dir.join(format!("{}.json", name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sessions_are_saved_listed_and_deleted() {
+        let dir = std::env::temp_dir().join(format!("cai-sessions-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let store = SessionStore::new(&dir);
+        let mut session = Session::new("design-review", "gpt4-o-mini");
+        let mut conversation = Conversation::new();
+        conversation.add_user_message("hi");
+        conversation.add_ai_message("hello");
+        session.set_conversation(conversation);
+        session.record_turn(Turn {
+            engine: "claude3-haiku".to_string(),
+            started_at: 1,
+            finished_at: 2,
+            prompt_tokens: 1,
+            completion_tokens: 2,
+        });
+
+        store.save(&session).unwrap();
+
+        assert_eq!(store.load("design-review").unwrap(), Some(session.clone()));
+        assert_eq!(store.list().unwrap(), vec![session]);
+        assert!(store.delete("design-review").unwrap());
+        assert!(!store.delete("design-review").unwrap());
+        assert!(store.load("../escape").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn turns_follow_removed_replies() {
+        let mut session = Session::new("s", "gpt4");
+        let mut conversation = Conversation::new();
+        conversation.add_user_message("hi");
+        conversation.add_ai_message("hello");
+        session.set_conversation(conversation.clone());
+        session.record_turn(Turn {
+            engine: "gpt4".to_string(),
+            started_at: 1,
+            finished_at: 2,
+            prompt_tokens: 1,
+            completion_tokens: 2,
+        });
+        assert_eq!(session.total_tokens(), 3);
+        assert_eq!(
+            session.to_markdown(),
+            "# s\n\nengine: gpt4\n\n## User\n\nhi\n\n## AI (gpt4)\n\nhello\n"
+        );
+
+        conversation.pop();
+        session.set_conversation(conversation);
+
+        assert!(session.turns.is_empty());
+    }
+}
@@ -0,0 +1,334 @@
+use std::sync::LazyLock;
+
+use anyhow::Context;
+use regex::Regex;
+
+use crate::{
+    AIError, GenerativeAIInterface,
+    tools::{
+        lang::Lang,
+        translator::{TranslatedDocument, detect_lang, translate_texts},
+    },
+};
+
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub struct SubtitleError(anyhow::Error);
+crate::impl_from_error!(SubtitleError);
+
+// `00:01:02,345 --> 00:01:04,000` in SRT, and `01:02.345 --> 01:04.000 line:0` in WebVTT.
+static TIMING: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?:\d+:)?\d{1,2}:\d{2}[,.]\d{3}[ \t]+-->[ \t]+(?:\d+:)?\d{1,2}:\d{2}[,.]\d{3}")
+        .unwrap()
+});
+
+// cues translated in one request, and cues around them which are given as context.
+const CUES_PER_REQUEST: usize = 20;
+const CONTEXT_CUES: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubtitleFormat {
+    /// SubRip `.srt`
+    Srt,
+    /// WebVTT `.vtt`
+    Vtt,
+}
+
+impl SubtitleFormat {
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = std::path::Path::new(path).extension()?.to_str()?;
+        Some(match extension {
+            "srt" => Self::Srt,
+            "vtt" => Self::Vtt,
+            _ => return None,
+        })
+    }
+}
+
+/// A cue with its number or identifier, timing line and text lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cue {
+    pub id: Option<String>,
+    /// The whole timing line, with the WebVTT settings after the timestamps.
+    pub timing: String,
+    pub lines: Vec<String>,
+}
+
+impl Cue {
+    /// The text in one line. Line breaks of subtitles are only for the display.
+    pub fn text(&self) -> String {
+        self.lines
+            .iter()
+            .map(|line| line.trim())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Block {
+    Cue(Cue),
+    // the WebVTT header and `NOTE`, `STYLE` and `REGION` blocks.
+    Raw(String),
+}
+
+/// A subtitle file. Rendering keeps everything but the cue text as it is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subtitle {
+    blocks: Vec<Block>,
+}
+
+impl Subtitle {
+    pub fn parse(format: SubtitleFormat, content: &str) -> Result<Self, SubtitleError> {
+        Ok(Self {
+            blocks: parse_blocks(format, content)?,
+        })
+    }
+    pub fn cues(&self) -> impl Iterator<Item = &Cue> {
+        self.blocks.iter().filter_map(|block| match block {
+            Block::Cue(cue) => Some(cue),
+            Block::Raw(_) => None,
+        })
+    }
+    /// The text of each cue in one line, in order.
+    pub fn texts(&self) -> Vec<String> {
+        self.cues().map(Cue::text).collect()
+    }
+    /// Renders with the cue texts replaced by `texts`, wrapped at `max_line_chars`. A cue whose
+    /// text is missing or empty keeps the original.
+    pub fn render(&self, texts: &[String], max_line_chars: usize) -> String {
+        let mut texts = texts.iter();
+        let blocks = self
+            .blocks
+            .iter()
+            .map(|block| match block {
+                Block::Raw(raw) => raw.clone(),
+                Block::Cue(cue) => {
+                    let lines = match texts.next() {
+                        // `-->` would be read as a timing line.
+                        Some(text) if !text.trim().is_empty() => {
+                            wrap(&text.replace("-->", "->"), max_line_chars)
+                        }
+                        _ => cue.lines.clone(),
+                    };
+                    cue.id
+                        .iter()
+                        .chain([&cue.timing])
+                        .chain(&lines)
+                        .map(String::as_str)
+                        .collect::<Vec<_>>()
+                        .join("\n")
+                }
+            })
+            .collect::<Vec<_>>();
+        format!("{}\n", blocks.join("\n\n"))
+    }
+}
+
+fn parse_blocks(format: SubtitleFormat, content: &str) -> anyhow::Result<Vec<Block>> {
+    let content = content.trim_start_matches('\u{feff}');
+    let mut blocks = vec![];
+    let mut lines = vec![];
+    for line in content.lines().chain([""]) {
+        if !line.trim().is_empty() {
+            lines.push(line);
+            continue;
+        }
+        if lines.is_empty() {
+            continue;
+        }
+        let is_raw = match format {
+            SubtitleFormat::Vtt if blocks.is_empty() => {
+                anyhow::ensure!(
+                    lines[0].starts_with("WEBVTT"),
+                    "WebVTT file must start with `WEBVTT`"
+                );
+                true
+            }
+            SubtitleFormat::Vtt => ["NOTE", "STYLE", "REGION"]
+                .iter()
+                .any(|p| lines[0].starts_with(p)),
+            SubtitleFormat::Srt => false,
+        };
+        let block = if is_raw {
+            Block::Raw(lines.join("\n"))
+        } else {
+            Block::Cue(parse_cue(&lines)?)
+        };
+        blocks.push(block);
+        lines.clear();
+    }
+    if format == SubtitleFormat::Vtt && blocks.is_empty() {
+        anyhow::bail!("WebVTT file must start with `WEBVTT`");
+    }
+    Ok(blocks)
+}
+
+fn parse_cue(lines: &[&str]) -> anyhow::Result<Cue> {
+    let (id, rest) = match lines {
+        [timing, ..] if timing.contains("-->") => (None, lines),
+        [id, rest @ ..] => (Some(id.trim().to_string()), rest),
+        [] => unreachable!("blocks are not empty"),
+    };
+    let (timing, text) = rest
+        .split_first()
+        .with_context(|| format!("Cue without timing: {}", lines[0]))?;
+    anyhow::ensure!(TIMING.is_match(timing.trim()), "Invalid timing: {}", timing);
+    Ok(Cue {
+        id,
+        timing: timing.trim().to_string(),
+        lines: text.iter().map(|line| line.to_string()).collect(),
+    })
+}
+
+/// Breaks the text into lines of at most `max_chars` characters, at spaces where possible.
+/// Text without spaces, like Japanese, is broken at any character.
+pub fn wrap(text: &str, max_chars: usize) -> Vec<String> {
+    let max_chars = max_chars.max(1);
+    let mut lines: Vec<String> = vec![];
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let mut word = word;
+        while !word.is_empty() {
+            let used = line.chars().count();
+            let space = usize::from(used > 0);
+            if used + space + word.chars().count() <= max_chars {
+                if space > 0 {
+                    line.push(' ');
+                }
+                line.push_str(word);
+                break;
+            }
+            if used > 0 {
+                lines.push(std::mem::take(&mut line));
+                continue;
+            }
+            // the word is longer than a line.
+            let at = word
+                .char_indices()
+                .nth(max_chars)
+                .map_or(word.len(), |(i, _)| i);
+            lines.push(word[..at].to_string());
+            word = &word[at..];
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+const INSTRUCTION: &str = "The strings are consecutive cues of subtitles. Translate them as natural \
+    subtitles which read well in sequence, keep each string as one cue, and keep tags like <i> and {\\an8}.";
+
+/// Translates the cue texts into a subtitle file for `target_lang`. Cues are translated in
+/// groups, and each group is given the cues just before and after it for coherence. A cue which
+/// could not be translated keeps its text and is reported as untranslated.
+pub async fn translate_subtitle<AI: GenerativeAIInterface>(
+    ai: &AI,
+    subtitle: &Subtitle,
+    source_lang: Option<Lang>,
+    target_lang: &Lang,
+    max_line_chars: usize,
+) -> Result<TranslatedDocument, AIError> {
+    let texts = subtitle.texts();
+    if texts.is_empty() {
+        return Ok(TranslatedDocument {
+            content: subtitle.render(&[], max_line_chars),
+            ..Default::default()
+        });
+    }
+    let source_lang = match source_lang {
+        Some(lang) => Some(lang),
+        None => detect_lang(ai, &texts.join("\n")).await?,
+    };
+    let tasks = (0..texts.len())
+        .step_by(CUES_PER_REQUEST)
+        .map(|start| {
+            let end = (start + CUES_PER_REQUEST).min(texts.len());
+            let before = &texts[start.saturating_sub(CONTEXT_CUES)..start];
+            let after = &texts[end..(end + CONTEXT_CUES).min(texts.len())];
+            let instruction = context_instruction(before, after);
+            let (texts, source_lang) = (&texts[start..end], source_lang.as_ref());
+            async move { translate_texts(ai, texts, source_lang, target_lang, &instruction).await }
+        })
+        .collect::<Vec<_>>();
+    let translated = futures::future::join_all(tasks)
+        .await
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()?
+        .concat();
+    let untranslated = texts
+        .iter()
+        .zip(&translated)
+        .filter(|(_, translated)| translated.is_none())
+        .map(|(text, _)| text.clone())
+        .collect();
+    // an empty text keeps the cue as it is.
+    let translated = translated
+        .into_iter()
+        .map(Option::unwrap_or_default)
+        .collect::<Vec<_>>();
+    Ok(TranslatedDocument {
+        content: subtitle.render(&translated, max_line_chars),
+        segments: texts.len(),
+        untranslated,
+    })
+}
+
+fn context_instruction(before: &[String], after: &[String]) -> String {
+    let mut instruction = INSTRUCTION.to_string();
+    for (when, cues) in [("before", before), ("after", after)] {
+        if !cues.is_empty() {
+            instruction.push_str(&format!(
+                "\nThe cues just {} them, only for context: {}",
+                when,
+                serde_json::to_string(cues).unwrap_or_default()
+            ));
+        }
+    }
+    instruction
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cues_are_rendered_with_numbers_and_timings() {
+        let srt = Subtitle::parse(
+            SubtitleFormat::Srt,
+            "1\r\n00:00:01,000 --> 00:00:02,500\r\nHello,\r\nworld.\r\n\r\n2\r\n00:00:03,000 --> 00:00:04,000\r\n<i>Bye.</i>\r\n",
+        )
+        .unwrap();
+        let vtt = Subtitle::parse(
+            SubtitleFormat::Vtt,
+            "WEBVTT\n\nNOTE kept\n\nintro\n00:01.000 --> 00:02.000 line:0\nHi there\n\n00:03.000 --> 00:04.000\nA --> B\n",
+        )
+        .unwrap();
+
+        assert_eq!(srt.texts(), ["Hello, world.", "<i>Bye.</i>"]);
+        assert_eq!(
+            srt.render(&["こんにちは、世界。".to_string()], 5),
+            "1\n00:00:01,000 --> 00:00:02,500\nこんにちは\n、世界。\n\n2\n00:00:03,000 --> 00:00:04,000\n<i>Bye.</i>\n"
+        );
+        assert_eq!(
+            vtt.render(
+                &["Good morning everyone".to_string(), "A --> B".to_string()],
+                12
+            ),
+            "WEBVTT\n\nNOTE kept\n\nintro\n00:01.000 --> 00:02.000 line:0\nGood morning\neveryone\n\n00:03.000 --> 00:04.000\nA -> B\n"
+        );
+    }
+    #[test]
+    fn invalid_files_are_rejected() {
+        assert!(Subtitle::parse(SubtitleFormat::Vtt, "1\n00:01.000 --> 00:02.000\nHi\n").is_err());
+        assert!(Subtitle::parse(SubtitleFormat::Srt, "1\nHi\n").is_err());
+        assert!(Subtitle::parse(SubtitleFormat::Srt, "1\n00:01 --> 00:02\nHi\n").is_err());
+    }
+    #[test]
+    fn long_words_are_broken_to_fit_lines() {
+        assert_eq!(wrap("a bb ccccccc d", 4), ["a bb", "cccc", "ccc", "d"]);
+        assert_eq!(wrap("ab cdefg", 4), ["ab", "cdef", "g"]);
+    }
+}
@@ -1,56 +1,540 @@
-use std::fmt::Display;
+use std::{fmt::Display, ops::Range};
 
-use crate::{AIError, GenerativeAIInterface, Prompt, handlers::recorder::Recorder};
+use crate::{
+    AIError, GenerativeAIInterface, Prompt,
+    handlers::recorder::Recorder,
+    limiter::estimate_text_tokens,
+    tools::{
+        glossary::{Glossary, Violation},
+        input::attach_context,
+        lang::Lang,
+        markdown::MarkdownDocument,
+        memory::{MemoryEntry, MemoryError, TranslationMemory},
+    },
+    unix_now,
+};
 
+/// Translates the chunks concurrently, and returns a result for every chunk in source order.
+/// A chunk which failed has its error instead of the translation, so it can be retried with
+/// `retry_failed`. Without a source language, it is detected once from the beginning of the
+/// source so every chunk is translated from the same language. With a glossary, each chunk is
+/// told the terms in it, and the results have the terms which are not followed.
 pub async fn translate<AI: GenerativeAIInterface>(
-    ai: AI,
-    request: TranslateRequests,
+    ai: &AI,
+    request: &TranslateRequests,
 ) -> Result<Vec<TranslateResult>, AIError> {
-    let requests = request.to_requests();
-    let tasks = requests.into_iter().map(|req| translate_task(&ai, req));
-    Ok(futures::future::join_all(tasks)
-        .await
+    let source_lang = match &request.source_lang {
+        Some(lang) => Some(lang.clone()),
+        None => detect_lang(ai, &request.source).await?,
+    };
+    let mut results = request
+        .to_requests()
         .into_iter()
-        .filter_map(|res| res.ok())
-        .collect())
+        .map(|mut from| {
+            from.source_lang = source_lang.clone();
+            TranslateResult {
+                from,
+                translated: None,
+                violations: vec![],
+                error: None,
+                from_memory: false,
+                quality: None,
+            }
+        })
+        .collect::<Vec<_>>();
+    retry_failed(ai, request, &mut results).await;
+    Ok(results)
 }
 
+/// Translates only the chunks which are not translated yet, keeping the others. A chunk which
+/// is in the translation memory is not sent to the AI.
+pub async fn retry_failed<AI: GenerativeAIInterface>(
+    ai: &AI,
+    request: &TranslateRequests,
+    results: &mut [TranslateResult],
+) {
+    let glossary = request.glossary.as_ref();
+    for result in results.iter_mut().filter(|res| res.translated.is_none()) {
+        if let Some(entry) = request.remembered(&result.from) {
+            result.translated = Some(entry.translated.clone());
+            result.violations = glossary
+                .map(|glossary| glossary.violations(&result.from.source, &entry.translated))
+                .unwrap_or_default();
+            result.error = None;
+            result.from_memory = true;
+        }
+    }
+    let tasks = (0..results.len())
+        .filter(|&i| results[i].translated.is_none())
+        .map(|i| {
+            let notes = request
+                .context
+                .then(|| segment_context(results, i))
+                .flatten()
+                .into_iter()
+                .chain(request.memory_hint(&results[i].from))
+                .collect();
+            let from = &results[i].from;
+            async move {
+                let translated =
+                    translate_task(ai, from, notes, glossary, request.retry_violations).await;
+                (i, translated)
+            }
+        })
+        .collect::<Vec<_>>();
+    let translated = futures::future::join_all(tasks).await;
+    for (i, translated) in translated {
+        let result = &mut results[i];
+        match translated {
+            Ok((translated, violations)) => {
+                result.translated = Some(translated);
+                result.violations = violations;
+                result.error = None;
+            }
+            Err(e) => result.error = Some(e.to_string()),
+        }
+    }
+}
+
+// the chunks around the i-th one, which are given to keep the translation coherent.
+fn segment_context(results: &[TranslateResult], i: usize) -> Option<String> {
+    let before = i.checked_sub(1).map(|i| &results[i].from.source);
+    let after = results.get(i + 1).map(|result| &result.from.source);
+    let context = before
+        .map(|text| format!("the text just before it is '{}'", text))
+        .into_iter()
+        .chain(after.map(|text| format!("the text just after it is '{}'", text)))
+        .collect::<Vec<_>>();
+    (!context.is_empty()).then(|| {
+        format!(
+            "For context only, {}. Do not translate them.",
+            context.join(" and ")
+        )
+    })
+}
+
+// the translation and the glossary terms which it does not follow.
 async fn translate_task<AI: GenerativeAIInterface>(
     ai: &AI,
-    request: TranslateRequest,
-) -> Result<TranslateResult, AIError> {
+    request: &TranslateRequest,
+    mut notes: Vec<String>,
+    glossary: Option<&Glossary>,
+    retry_violations: bool,
+) -> Result<(String, Vec<Violation>), AIError> {
+    notes.extend(glossary.and_then(|glossary| glossary.instruction(&request.source)));
+    let ask = async |notes: &[String]| {
+        let mut recorder = Recorder::new();
+        ai.request_mut(request.to_prompt(Some(&notes.join(" "))), &mut recorder)
+            .await?;
+        let translated = recorder.take();
+        let violations = glossary
+            .map(|glossary| glossary.violations(&request.source, &translated))
+            .unwrap_or_default();
+        Ok::<_, AIError>((translated, violations))
+    };
+    let (translated, violations) = ask(&notes).await?;
+    if !retry_violations || violations.is_empty() {
+        return Ok((translated, violations));
+    }
+    let violated = violations
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    notes.push(format!(
+        "The last translation did not follow the glossary: {}.",
+        violated.join(", ")
+    ));
+    ask(&notes).await
+}
+
+const MARKDOWN_INSTRUCTION: &str = "The strings are parts of a markdown document. \
+    Keep markdown markup like `**` and `_`, line breaks, and placeholders like ⟦0⟧ as they are.";
+
+/// A translated document, like markdown or subtitles.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TranslatedDocument {
+    pub content: String,
+    /// Number of texts which were sent to be translated.
+    pub segments: usize,
+    /// Texts which could not be translated. They are kept as the source in `content`.
+    pub untranslated: Vec<String>,
+}
+
+/// Translates only the prose of a markdown document, so code, URLs and the structure are kept.
+pub async fn translate_markdown<AI: GenerativeAIInterface>(
+    ai: &AI,
+    source: &str,
+    source_lang: Option<Lang>,
+    target_lang: &Lang,
+) -> Result<TranslatedDocument, AIError> {
+    let doc = MarkdownDocument::parse(source);
+    let texts = doc.texts();
+    if texts.is_empty() {
+        return Ok(doc.render(&[]));
+    }
+    let source_lang = match source_lang {
+        Some(lang) => Some(lang),
+        None => detect_lang(ai, &texts.join("\n")).await?,
+    };
+    let translated = translate_texts(
+        ai,
+        &texts,
+        source_lang.as_ref(),
+        target_lang,
+        MARKDOWN_INSTRUCTION,
+    )
+    .await?;
+    Ok(doc.render(&translated))
+}
+
+// estimated tokens of the texts which are translated in one request.
+const BATCH_TOKEN_BUDGET: u32 = 1500;
+
+/// Translates the texts keeping their order, for documents which are split into many short
+/// texts. The texts are sent as JSON arrays, and a batch whose answer does not have the same
+/// number of items is translated one by one. A text whose answer can not be read even alone is
+/// `None`. `instruction` is added to the prompt, like how to treat markup.
+pub async fn translate_texts<AI: GenerativeAIInterface>(
+    ai: &AI,
+    texts: &[String],
+    source_lang: Option<&Lang>,
+    target_lang: &Lang,
+    instruction: &str,
+) -> Result<Vec<Option<String>>, AIError> {
+    let prompt = TextsPrompt {
+        keys: None,
+        texts,
+        source_lang,
+        target_lang,
+        instruction,
+    };
+    prompt.translate(ai).await
+}
+
+/// Like `translate_texts`, but the texts are sent as a JSON object with their keys, so the keys
+/// give the context, like the identifiers of messages.
+pub async fn translate_keyed<AI: GenerativeAIInterface>(
+    ai: &AI,
+    keys: &[String],
+    texts: &[String],
+    source_lang: Option<&Lang>,
+    target_lang: &Lang,
+    instruction: &str,
+) -> Result<Vec<Option<String>>, AIError> {
+    let prompt = TextsPrompt {
+        keys: Some(keys),
+        texts,
+        source_lang,
+        target_lang,
+        instruction,
+    };
+    prompt.translate(ai).await
+}
+
+struct TextsPrompt<'a> {
+    keys: Option<&'a [String]>,
+    texts: &'a [String],
+    source_lang: Option<&'a Lang>,
+    target_lang: &'a Lang,
+    instruction: &'a str,
+}
+
+impl TextsPrompt<'_> {
+    async fn translate<AI: GenerativeAIInterface>(
+        &self,
+        ai: &AI,
+    ) -> Result<Vec<Option<String>>, AIError> {
+        let mut batches: Vec<(Range<usize>, u32)> = vec![];
+        for (i, text) in self.texts.iter().enumerate() {
+            let tokens = estimate_text_tokens(text);
+            match batches.last_mut() {
+                Some((batch, total)) if *total + tokens <= BATCH_TOKEN_BUDGET => {
+                    batch.end = i + 1;
+                    *total += tokens;
+                }
+                _ => batches.push((i..i + 1, tokens)),
+            }
+        }
+        let tasks = batches.into_iter().map(|(batch, _)| async move {
+            if let Some(translated) = self.request(ai, batch.clone()).await? {
+                return Ok(translated.into_iter().map(Some).collect());
+            }
+            let mut translated = vec![];
+            for i in batch {
+                let one = self.request(ai, i..i + 1).await?;
+                translated.push(one.and_then(|mut one| one.pop()));
+            }
+            Ok::<_, AIError>(translated)
+        });
+        Ok(futures::future::join_all(tasks)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?
+            .concat())
+    }
+    fn to_prompt(&self, batch: Range<usize>) -> Prompt {
+        let from = self
+            .source_lang
+            .map(|lang| format!(" from {}", lang.to_prompt()))
+            .unwrap_or_default();
+        let texts = &self.texts[batch.clone()];
+        let (what, answer, json) = match self.keys {
+            Some(keys) => (
+                "each value of this JSON object",
+                "a JSON object with the same keys and the translated values",
+                serde_json::to_string(
+                    &keys[batch]
+                        .iter()
+                        .zip(texts)
+                        .map(|(key, text)| (key.clone(), serde_json::Value::from(text.as_str())))
+                        .collect::<serde_json::Map<_, _>>(),
+                ),
+            ),
+            None => (
+                "each string of this JSON array",
+                "a JSON array of the translated strings in the same order with the same number of items",
+                serde_json::to_string(texts),
+            ),
+        };
+        Prompt::ask(&format!(
+            "Translate {}{} to {}. {}\nAnswer only {}.\n\n{}",
+            what,
+            from,
+            self.target_lang.to_prompt(),
+            self.instruction,
+            answer,
+            json.unwrap_or_default()
+        ))
+    }
+    // `None` if the answer does not have all the texts.
+    async fn request<AI: GenerativeAIInterface>(
+        &self,
+        ai: &AI,
+        batch: Range<usize>,
+    ) -> Result<Option<Vec<String>>, AIError> {
+        let mut recorder = Recorder::new();
+        ai.request_mut(self.to_prompt(batch.clone()), &mut recorder)
+            .await?;
+        let reply = recorder.take();
+        let Some(keys) = self.keys else {
+            return Ok(parse_json::<Vec<String>>(&reply, '[', ']')
+                .filter(|translated| translated.len() == batch.len()));
+        };
+        let Some(mut translated) =
+            parse_json::<std::collections::HashMap<String, String>>(&reply, '{', '}')
+        else {
+            return Ok(None);
+        };
+        Ok(keys[batch]
+            .iter()
+            .map(|key| translated.remove(key))
+            .collect())
+    }
+}
+
+fn parse_json<T: serde::de::DeserializeOwned>(reply: &str, open: char, close: char) -> Option<T> {
+    let start = reply.find(open)?;
+    let end = reply.rfind(close)?;
+    serde_json::from_str(reply.get(start..=end)?).ok()
+}
+
+/// How well a translation keeps the meaning of the source, judged from its back-translation.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Quality {
+    pub back_translation: String,
+    /// From 1, a different meaning, to `MAX_SCORE`, the same meaning.
+    pub score: u8,
+    pub reason: String,
+    /// True if the score is below the threshold, so a reviewer should check the segment.
+    pub flagged: bool,
+}
+
+pub const MAX_SCORE: u8 = 5;
+
+#[derive(serde::Deserialize)]
+struct Judgement {
+    score: u8,
+    #[serde(default)]
+    reason: String,
+}
+
+/// Translates each translation back to the source language with `ai`, and asks `judge` to
+/// score how well the meaning is kept. Segments scored below `min_score` are flagged. Returns
+/// the number of translated segments which could not be verified, like when the source
+/// language is unknown or the judge does not answer a score.
+pub async fn verify_translations<AI: GenerativeAIInterface, J: GenerativeAIInterface>(
+    ai: &AI,
+    judge: &J,
+    results: &mut [TranslateResult],
+    min_score: u8,
+) -> usize {
+    let tasks = results.iter().map(|result| async move {
+        let translated = result.translated.as_deref()?;
+        let back = TranslateRequest {
+            source: translated.to_string(),
+            source_lang: Some(result.from.target_lang.clone()),
+            target_lang: result.from.source_lang.clone()?,
+        };
+        let mut recorder = Recorder::new();
+        ai.request_mut(back.to_prompt(None), &mut recorder)
+            .await
+            .ok()?;
+        let back_translation = recorder.take().trim().to_string();
+        let question = format!(
+            "Score from 1 to {} how well the back-translation keeps the meaning of the original, \
+            where {} is the same meaning and 1 is a different meaning. Ignore differences of wording. \
+            Answer only a JSON object like {{\"score\": 4, \"reason\": \"...\"}} with a short reason.",
+            MAX_SCORE, MAX_SCORE
+        );
+        let question = attach_context(&question, "original", &result.from.source);
+        let question = attach_context(&question, "back-translation", &back_translation);
+        let mut recorder = Recorder::new();
+        judge
+            .request_mut(Prompt::ask(&question), &mut recorder)
+            .await
+            .ok()?;
+        let judgement = parse_json::<Judgement>(&recorder.take(), '{', '}')?;
+        let score = judgement.score.clamp(1, MAX_SCORE);
+        Some(Quality {
+            back_translation,
+            score,
+            reason: judgement.reason,
+            flagged: score < min_score,
+        })
+    });
+    let qualities = futures::future::join_all(tasks).await;
+    let mut unverified = 0;
+    for (result, quality) in results.iter_mut().zip(qualities) {
+        unverified += usize::from(result.translated.is_some() && quality.is_none());
+        result.quality = quality;
+    }
+    unverified
+}
+
+// characters which are enough to tell the language.
+const DETECT_SAMPLE_CHARS: usize = 500;
+
+/// Asks the language of the text. `None` if the answer is not a language tag.
+pub async fn detect_lang<AI: GenerativeAIInterface>(
+    ai: &AI,
+    text: &str,
+) -> Result<Option<Lang>, AIError> {
+    let sample = text.chars().take(DETECT_SAMPLE_CHARS).collect::<String>();
     let mut recorder = Recorder::new();
-    ai.request_mut(request.to_prompt(), &mut recorder).await?;
-    Ok(TranslateResult {
-        from: request,
-        translated: recorder.take(),
-    })
+    ai.request_mut(
+        Prompt::ask(&format!(
+            "Which language is this text written in? Answer only its BCP-47 language tag like `en` or `zh-Hant`.\n\n{}",
+            sample
+        )),
+        &mut recorder,
+    )
+    .await?;
+    Ok(recorder.take().trim().trim_matches('`').parse().ok())
 }
 
+/// Serialized as `{"source": ..., "source_lang": ..., "target_lang": ..., "translated": ...}`,
+/// with `violations` of the glossary if there are any, or `error` instead of `translated` if
+/// the chunk failed.
+#[derive(serde::Serialize)]
 pub struct TranslateResult {
+    #[serde(flatten)]
     from: TranslateRequest,
-    translated: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    translated: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    violations: Vec<Violation>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    from_memory: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    quality: Option<Quality>,
+}
+impl TranslateResult {
+    pub fn source(&self) -> &str {
+        &self.from.source
+    }
+    /// `None` if it is not given and the detection failed.
+    pub fn source_lang(&self) -> Option<&Lang> {
+        self.from.source_lang.as_ref()
+    }
+    pub fn target_lang(&self) -> &Lang {
+        &self.from.target_lang
+    }
+    /// `None` if the chunk failed.
+    pub fn translated(&self) -> Option<&str> {
+        self.translated.as_deref()
+    }
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+    /// True if the translation is reused from the translation memory.
+    pub fn from_memory(&self) -> bool {
+        self.from_memory
+    }
+    /// Glossary terms which are not translated as the glossary says.
+    pub fn violations(&self) -> &[Violation] {
+        &self.violations
+    }
+    /// `None` unless it is verified by `verify_translations`.
+    pub fn quality(&self) -> Option<&Quality> {
+        self.quality.as_ref()
+    }
 }
 impl Display for TranslateResult {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}\n{}", self.from.source, self.translated)
+        match (&self.translated, &self.error) {
+            (Some(translated), _) => {
+                write!(f, "{}\n{}", self.from.source, translated)?;
+                match &self.quality {
+                    Some(quality) if quality.flagged => write!(
+                        f,
+                        "\n[check: {}/{} {}]",
+                        quality.score, MAX_SCORE, quality.reason
+                    ),
+                    _ => Ok(()),
+                }
+            }
+            (None, error) => write!(
+                f,
+                "{}\n[failed: {}]",
+                self.from.source,
+                error.as_deref().unwrap_or("not translated")
+            ),
+        }
     }
 }
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, serde::Serialize)]
 struct TranslateRequest {
     source: String,
-    target_lang: TargetLang,
+    // `None` if it is not given and the detection failed.
+    source_lang: Option<Lang>,
+    target_lang: Lang,
 }
 impl TranslateRequest {
-    fn to_prompt(&self) -> Prompt {
+    // `notes` are added to the instruction, like the context and the glossary.
+    fn to_prompt(&self, notes: Option<&str>) -> Prompt {
+        let from = self
+            .source_lang
+            .as_ref()
+            .map(|lang| format!(" from {}", lang.to_prompt()))
+            .unwrap_or_default();
+        let notes = notes
+            .filter(|notes| !notes.is_empty())
+            .map(|notes| format!(" {}", notes))
+            .unwrap_or_default();
         Prompt::ask(&format!(
-            "please translate '{}' to {}. you should answer only in the target language and result. If there is something like program code in the translation target, please ignore it and output it as is.",
+            "please translate '{}'{} to {}. you should answer only in the target language and result. If there is something like program code in the translation target, please ignore it and output it as is.{}",
             self.source,
-            self.target_lang.to_str()
+            from,
+            self.target_lang.to_prompt(),
+            notes
         ))
     }
 }
 
+const DEFAULT_FUZZY_THRESHOLD: f64 = 0.75;
+
 pub struct TranslateRequests {
     source: String,
     // if you want to separate the source string by some characters, set them here.
@@ -59,18 +543,118 @@ pub struct TranslateRequests {
     // then, the source string will be separated into ["hello, world!", "Are you okay?"]
     // first ',' is counted as 1, and second '!' is counted as 2 and separate_per_limit is 2, so the source string is separated.
     separate_per_limit: usize,
-    target_lang: TargetLang,
+    source_lang: Option<Lang>,
+    target_lang: Lang,
+    glossary: Option<Glossary>,
+    // translate a chunk again if it does not follow the glossary.
+    retry_violations: bool,
+    // give the chunks before and after each chunk in its prompt.
+    context: bool,
+    memory: Option<TranslationMemory>,
+    // the engine which is recorded in the memory.
+    engine: String,
+    // a similar segment in the memory is given as a hint if its similarity is at least this.
+    fuzzy_threshold: f64,
 }
 
 impl TranslateRequests {
-    pub fn new(source: String, target_lang: TargetLang) -> Self {
+    pub fn new(source: String, target_lang: Lang) -> Self {
         Self {
             source,
             separate_per_limit: 1,
             separators: vec![],
+            source_lang: None,
             target_lang,
+            glossary: None,
+            retry_violations: false,
+            context: false,
+            memory: None,
+            engine: String::new(),
+            fuzzy_threshold: DEFAULT_FUZZY_THRESHOLD,
         }
     }
+    /// Without it, the source language is detected by `translate`.
+    pub fn source_lang(mut self, lang: Option<Lang>) -> Self {
+        self.source_lang = lang;
+        self
+    }
+    pub fn glossary(mut self, glossary: Option<Glossary>) -> Self {
+        self.glossary = glossary;
+        self
+    }
+    /// Translates a chunk once more when it does not follow the glossary.
+    pub fn retry_violations(mut self, retry: bool) -> Self {
+        self.retry_violations = retry;
+        self
+    }
+    /// Gives each chunk the chunks before and after it as context, which are not translated.
+    pub fn context(mut self, context: bool) -> Self {
+        self.context = context;
+        self
+    }
+    /// Reuses the translations of the engine in the memory, and gives similar ones as hints.
+    pub fn memory(mut self, memory: Option<TranslationMemory>, engine: &str) -> Self {
+        self.memory = memory;
+        self.engine = engine.to_string();
+        self
+    }
+    pub fn fuzzy_threshold(mut self, threshold: f64) -> Self {
+        self.fuzzy_threshold = threshold;
+        self
+    }
+    /// Adds the new translations to the memory and saves it. Does nothing without a memory.
+    /// Translations which do not follow the glossary or are flagged by `verify_translations` are
+    /// not saved, so they are not reused without review.
+    pub fn save_memory(&mut self, results: &[TranslateResult]) -> Result<(), MemoryError> {
+        let Some(memory) = &mut self.memory else {
+            return Ok(());
+        };
+        let reusable = |res: &&TranslateResult| {
+            !res.from_memory
+                && res.violations.is_empty()
+                && !res.quality.as_ref().is_some_and(|quality| quality.flagged)
+        };
+        for result in results.iter().filter(reusable) {
+            let (Some(translated), Some(source_lang)) =
+                (&result.translated, &result.from.source_lang)
+            else {
+                continue;
+            };
+            memory.insert(MemoryEntry {
+                source: result.from.source.clone(),
+                translated: translated.trim().to_string(),
+                source_lang: source_lang.tag().to_string(),
+                target_lang: result.from.target_lang.tag().to_string(),
+                engine: Some(self.engine.clone()),
+                updated_at: unix_now(),
+            });
+        }
+        memory.save()
+    }
+    fn remembered(&self, request: &TranslateRequest) -> Option<&MemoryEntry> {
+        let memory = self.memory.as_ref()?;
+        let source_lang = request.source_lang.as_ref()?;
+        memory.get(
+            &request.source,
+            source_lang,
+            &request.target_lang,
+            &self.engine,
+        )
+    }
+    fn memory_hint(&self, request: &TranslateRequest) -> Option<String> {
+        let memory = self.memory.as_ref()?;
+        let source_lang = request.source_lang.as_ref()?;
+        let (entry, _) = memory.fuzzy(
+            &request.source,
+            source_lang,
+            &request.target_lang,
+            self.fuzzy_threshold,
+        )?;
+        Some(format!(
+            "A similar text '{}' was translated as '{}' before, so reuse its wording where it fits.",
+            entry.source, entry.translated
+        ))
+    }
     pub fn separate_per_limit(mut self, limit: usize) -> Self {
         self.separate_per_limit = limit;
         self
@@ -80,12 +664,9 @@ impl TranslateRequests {
         self
     }
 
-    fn to_requests(self) -> Vec<TranslateRequest> {
+    fn to_requests(&self) -> Vec<TranslateRequest> {
         if self.separators.is_empty() {
-            return vec![TranslateRequest {
-                source: self.source,
-                target_lang: self.target_lang,
-            }];
+            return vec![self.request(self.source.clone())];
         }
         self.source
             .split_inclusive(|c| self.separators.contains(&c))
@@ -115,93 +696,328 @@ impl TranslateRequests {
                 acc
             })
             .into_iter()
-            .map(|sentence| TranslateRequest {
-                source: sentence,
-                target_lang: self.target_lang,
-            })
+            .map(|sentence| self.request(sentence))
             .collect()
     }
-}
-
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum TargetLang {
-    English,
-    Japanese,
-}
-impl TargetLang {
-    pub fn to_str(&self) -> &str {
-        match self {
-            TargetLang::English => "en",
-            TargetLang::Japanese => "ja",
+    fn request(&self, source: String) -> TranslateRequest {
+        TranslateRequest {
+            source,
+            source_lang: self.source_lang.clone(),
+            target_lang: self.target_lang.clone(),
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use anyhow::Context;
+
     use super::*;
+    use crate::{Handler, MutHandler, clients::mocks::FakeAI};
+
+    fn ja() -> Lang {
+        "ja".parse().unwrap()
+    }
+
+    // answers "en" to the detection, and the JSON array at the end of the prompt in upper case.
+    struct UpperAI;
+    impl UpperAI {
+        fn answer(prompt: Prompt) -> String {
+            let question = prompt.messages()[0].content().to_string();
+            let Some((_, json)) = question.rsplit_once("\n\n") else {
+                return "en".to_string();
+            };
+            match serde_json::from_str::<Vec<String>>(json) {
+                Ok(texts) => serde_json::to_string(
+                    &texts.iter().map(|t| t.to_uppercase()).collect::<Vec<_>>(),
+                )
+                .unwrap(),
+                Err(_) => "en".to_string(),
+            }
+        }
+    }
+    impl GenerativeAIInterface for UpperAI {
+        async fn request<H: Handler>(&self, prompt: Prompt, handler: &H) -> Result<(), AIError> {
+            Ok(handler
+                .handle(&Self::answer(prompt))
+                .await
+                .context("handle")?)
+        }
+        async fn request_mut<H: MutHandler>(
+            &self,
+            prompt: Prompt,
+            handler: &mut H,
+        ) -> Result<(), AIError> {
+            Ok(handler
+                .handle_mut(&Self::answer(prompt))
+                .await
+                .context("handle")?)
+        }
+    }
+
+    // fails the first request of a prompt which has "world", and answers the prompt otherwise.
+    fn flaky_ai() -> FakeAI<impl Fn(Prompt) -> anyhow::Result<String>> {
+        let failed = AtomicBool::new(false);
+        FakeAI::new(move |prompt: Prompt| {
+            let question = prompt.messages()[0].content().to_string();
+            if question.starts_with("please translate 'world")
+                && !failed.swap(true, Ordering::SeqCst)
+            {
+                anyhow::bail!("rate limited");
+            }
+            Ok(question)
+        })
+    }
+
+    #[tokio::test]
+    async fn failed_chunks_are_reported_in_order_and_retried() {
+        let ai = flaky_ai();
+        let request = TranslateRequests::new("hello. world. bye.".to_string(), ja())
+            .source_lang(Some("en".parse().unwrap()))
+            .separators(vec!['.'])
+            .context(true);
+
+        let mut results = translate(&ai, &request).await.unwrap();
+        let errors = results
+            .iter()
+            .map(TranslateResult::error)
+            .collect::<Vec<_>>();
+        assert_eq!(errors, [None, Some("rate limited"), None]);
+        assert_eq!(results[1].to_string(), "world.\n[failed: rate limited]");
+
+        retry_failed(&ai, &request, &mut results).await;
+
+        assert!(results.iter().all(|res| res.error().is_none()));
+        assert!(results[1].translated().unwrap().ends_with(
+            "For context only, the text just before it is 'hello.' and the text just after it is 'bye.'. Do not translate them."
+        ));
+        assert!(results[0].translated().unwrap().contains(
+            "For context only, the text just after it is 'world.'. Do not translate them."
+        ));
+    }
+    #[tokio::test]
+    async fn remembered_chunks_are_reused_and_new_ones_are_saved() {
+        let path = std::env::temp_dir().join(format!("cai-tm-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut memory = TranslationMemory::open(&path).unwrap();
+        memory.insert(MemoryEntry {
+            source: "hello.".to_string(),
+            translated: "こんにちは。".to_string(),
+            source_lang: "en".to_string(),
+            target_lang: "ja".to_string(),
+            engine: None,
+            updated_at: 0,
+        });
+        let mut request = TranslateRequests::new("hello. hello!".to_string(), ja())
+            .source_lang(Some("en".parse().unwrap()))
+            .separators(vec!['.', '!'])
+            .memory(Some(memory), "echo");
+
+        let results = translate(&flaky_ai(), &request).await.unwrap();
+        request.save_memory(&results).unwrap();
+
+        assert!(results[0].from_memory());
+        assert_eq!(results[0].translated(), Some("こんにちは。"));
+        assert!(!results[1].from_memory());
+        assert!(
+            results[1]
+                .translated()
+                .unwrap()
+                .contains("A similar text 'hello.' was translated as 'こんにちは。' before")
+        );
+        let saved = TranslationMemory::open(&path).unwrap();
+        assert_eq!(saved.entries().len(), 2);
+        assert_eq!(saved.entries()[1].engine.as_deref(), Some("echo"));
+        std::fs::remove_file(&path).unwrap();
+    }
+    #[tokio::test]
+    async fn translations_to_review_are_not_remembered() {
+        let path = std::env::temp_dir().join(format!("cai-tm-review-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut request = TranslateRequests::new("hello. bye. see you.".to_string(), ja())
+            .source_lang(Some("en".parse().unwrap()))
+            .separators(vec!['.'])
+            .memory(Some(TranslationMemory::open(&path).unwrap()), "echo");
+        let mut results = translate(&flaky_ai(), &request).await.unwrap();
+        results[1].violations.push(Violation {
+            term: "bye".to_string(),
+            expected: "さようなら".to_string(),
+        });
+        results[2].quality = Some(Quality {
+            back_translation: "see.".to_string(),
+            score: 1,
+            reason: "r".to_string(),
+            flagged: true,
+        });
+
+        request.save_memory(&results).unwrap();
+
+        let saved = TranslationMemory::open(&path).unwrap();
+        assert_eq!(
+            saved
+                .entries()
+                .iter()
+                .map(|entry| entry.source.as_str())
+                .collect::<Vec<_>>(),
+            ["hello."]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+    #[tokio::test]
+    async fn back_translations_below_min_score_are_flagged() {
+        let ai = flaky_ai();
+        let request = TranslateRequests::new("hello. bye.".to_string(), ja())
+            .source_lang(Some("en".parse().unwrap()))
+            .separators(vec!['.']);
+        let mut results = translate(&ai, &request).await.unwrap();
+
+        // scores 2 if the back-translation is of the second chunk, and 5 otherwise.
+        let judge = FakeAI::new(|prompt: Prompt| {
+            let question = prompt.messages()[0].content().to_string();
+            let score = if question.contains("<original>\nbye.") {
+                2
+            } else {
+                5
+            };
+            Ok(format!(
+                "```json\n{{\"score\": {}, \"reason\": \"r\"}}\n```",
+                score
+            ))
+        });
+
+        let unverified = verify_translations(&ai, &judge, &mut results, 4).await;
+
+        assert_eq!(unverified, 0);
+        let scores = results
+            .iter()
+            .map(|res| res.quality().map(|q| (q.score, q.flagged)))
+            .collect::<Vec<_>>();
+        assert_eq!(scores, [Some((5, false)), Some((2, true))]);
+        assert!(
+            results[1]
+                .quality()
+                .unwrap()
+                .back_translation
+                .contains("from Japanese (ja) to English (en)")
+        );
+        assert!(results[1].to_string().ends_with("\n[check: 2/5 r]"));
+    }
+    #[tokio::test]
+    async fn markdown_prose_is_translated_in_batches() {
+        let source = "# Title\n\nRun `cai`.\n\n```\ncode\n```\n";
+
+        let translated = translate_markdown(&UpperAI, source, None, &ja())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            translated.content,
+            "# TITLE\n\nRUN `cai`.\n\n```\ncode\n```\n"
+        );
+        assert_eq!(translated.segments, 2);
+        assert!(translated.untranslated.is_empty());
+    }
+    #[tokio::test]
+    async fn texts_whose_answer_can_not_be_read_are_none() {
+        // answers only the request which has "a" alone, so "b" is never translated.
+        let ai = FakeAI::new(|prompt: Prompt| {
+            let question = prompt.messages()[0].content().to_string();
+            Ok(match question.rsplit_once("\n\n").map(|(_, json)| json) {
+                Some(r#"["a"]"#) => r#"["A"]"#.to_string(),
+                _ => "I can not translate it.".to_string(),
+            })
+        });
+        let texts = ["a".to_string(), "b".to_string()];
+
+        let translated = translate_texts(&ai, &texts, None, &ja(), "").await.unwrap();
+
+        assert_eq!(translated, [Some("A".to_string()), None]);
+    }
     #[test]
     fn translate_request_should_not_separate_separators_after_not_empty_char() {
-        let request = TranslateRequests::new(
-            "hello, world! Are you okay? app.NotSplit".to_string(),
-            TargetLang::Japanese,
-        )
-        .separate_per_limit(1)
-        .separators(vec![',', '?', '!', '.']);
+        let request =
+            TranslateRequests::new("hello, world! Are you okay? app.NotSplit".to_string(), ja())
+                .separate_per_limit(1)
+                .separators(vec![',', '?', '!', '.']);
         let requests = request.to_requests();
         assert_eq!(requests.len(), 4);
         assert_eq!(
             requests[0],
             TranslateRequest {
                 source: "hello,".to_string(),
-                target_lang: TargetLang::Japanese
+                source_lang: None,
+                target_lang: ja()
             },
         );
         assert_eq!(
             requests[1],
             TranslateRequest {
                 source: "world!".to_string(),
-                target_lang: TargetLang::Japanese
+                source_lang: None,
+                target_lang: ja()
             },
         );
         assert_eq!(
             requests[2],
             TranslateRequest {
                 source: "Are you okay?".to_string(),
-                target_lang: TargetLang::Japanese
+                source_lang: None,
+                target_lang: ja()
             },
         );
         assert_eq!(
             requests[3],
             TranslateRequest {
                 source: "app.NotSplit".to_string(),
-                target_lang: TargetLang::Japanese
+                source_lang: None,
+                target_lang: ja()
             },
         );
     }
     #[test]
     fn translate_request_should_separate_source_string() {
-        let request = TranslateRequests::new(
-            "hello, world! Are you okay?".to_string(),
-            TargetLang::Japanese,
-        )
-        .separate_per_limit(2)
-        .separators(vec![',', '?', '!']);
+        let request = TranslateRequests::new("hello, world! Are you okay?".to_string(), ja())
+            .separate_per_limit(2)
+            .separators(vec![',', '?', '!']);
         let requests = request.to_requests();
         assert_eq!(requests.len(), 2);
         assert_eq!(
             requests[0],
             TranslateRequest {
                 source: "hello, world!".to_string(),
-                target_lang: TargetLang::Japanese
+                source_lang: None,
+                target_lang: ja()
             }
         );
         assert_eq!(
             requests[1],
             TranslateRequest {
                 source: "Are you okay?".to_string(),
-                target_lang: TargetLang::Japanese
+                source_lang: None,
+                target_lang: ja()
             }
         );
     }
+    #[test]
+    fn prompt_names_source_and_target_languages() {
+        let request = TranslateRequests::new("hello".to_string(), "zh-hant".parse().unwrap())
+            .source_lang(Some("en".parse().unwrap()));
+        let detected = TranslateRequests::new("hello".to_string(), ja());
+
+        let prompt = request.to_requests()[0].to_prompt(None);
+        let detected = detected.to_requests()[0].to_prompt(None);
+
+        assert!(
+            prompt.messages()[0]
+                .content()
+                .contains("'hello' from English (en) to Chinese (Traditional) (zh-Hant)")
+        );
+        assert!(
+            detected.messages()[0]
+                .content()
+                .contains("'hello' to Japanese (ja)")
+        );
+    }
 }